@@ -3,6 +3,8 @@ use std::io;
 use std::fmt;
 use bincode::serde;
 use std::error::Error;
+use hash::NodeId;
+use storage::StoreResult;
 
 /// Subotai error type. It reports the various ways in which a hash table query may fail.
 #[derive(Debug)]
@@ -20,8 +22,30 @@ pub enum SubotaiError {
    StorageError,
    /// The network is unresponsive (several RPCs have timed out).
    UnresponsiveNetwork,
+   /// A string (such as a seed string) could not be parsed.
+   ParseError,
+   /// The remote address belongs to a different family (IPv4/IPv6) than
+   /// the local socket, so it can't be reached.
+   AddressFamilyMismatch,
+   /// The operation was not attempted, as this node has reached its
+   /// self-imposed limit of network waves per second.
+   RateLimited,
+   /// The operation was cancelled by the application before it finished.
+   Cancelled,
+   /// Not enough nodes accepted a store operation. Reports the result
+   /// given by each storage node that responded in time.
+   StoreQuorumFailed { results: Vec<(NodeId, StoreResult)> },
+   /// The replicas of a key returned different entries, so no read quorum was reached.
+   InconsistentReplicas,
+   /// A received datagram couldn't be decoded into an RPC, or an RPC couldn't be encoded.
+   MalformedRpc,
+   /// No chunk manifest was found under the key of a large blob.
+   MissingManifest,
+   /// A chunk of a large blob couldn't be retrieved, or didn't match the manifest.
+   MissingChunk { index: usize },
    Io(io::Error),
    Deserialize(serde::DeserializeError),
+   Serialize(serde::SerializeError),
 }
 
 /// Custom result type over `SubotaiError`.
@@ -36,8 +60,18 @@ impl fmt::Display for SubotaiError {
          SubotaiError::OutOfBounds => write!(f, "Index falls out of routing table."),
          SubotaiError::StorageError => write!(f, "Corrupted Storage."),
          SubotaiError::UnresponsiveNetwork => write!(f, "Network too small or unresponsive."),
+         SubotaiError::ParseError => write!(f, "Could not parse the provided string."),
+         SubotaiError::AddressFamilyMismatch => write!(f, "Remote address family (IPv4/IPv6) differs from the local socket."),
+         SubotaiError::RateLimited => write!(f, "Too many network operations per second."),
+         SubotaiError::Cancelled => write!(f, "The operation was cancelled."),
+         SubotaiError::InconsistentReplicas => write!(f, "Replicas disagree on the stored entries."),
+         SubotaiError::MalformedRpc => write!(f, "Could not decode the received RPC."),
+         SubotaiError::MissingManifest => write!(f, "No chunk manifest found under the key."),
+         SubotaiError::MissingChunk { index } => write!(f, "Could not retrieve chunk {} of a large blob.", index),
+         SubotaiError::StoreQuorumFailed { ref results } => write!(f, "Store rejected or unacknowledged ({} responses: {:?}).", results.len(), results),
          SubotaiError::Io(ref err) => err.fmt(f),
          SubotaiError::Deserialize(ref err) => err.fmt(f),
+         SubotaiError::Serialize(ref err) => err.fmt(f),
       }
    }
 }
@@ -51,8 +85,18 @@ impl Error for SubotaiError {
          SubotaiError::OutOfBounds => "Index outside routing table.",
          SubotaiError::StorageError => "Corrupted Storage.",
          SubotaiError::UnresponsiveNetwork => "Network too small or unresponsive.",
+         SubotaiError::ParseError => "Malformed string.",
+         SubotaiError::AddressFamilyMismatch => "Address family mismatch.",
+         SubotaiError::RateLimited => "Rate limited.",
+         SubotaiError::Cancelled => "Cancelled.",
+         SubotaiError::StoreQuorumFailed { .. } => "Store quorum not reached.",
+         SubotaiError::InconsistentReplicas => "Inconsistent replicas.",
+         SubotaiError::MalformedRpc => "Malformed RPC.",
+         SubotaiError::MissingManifest => "Missing chunk manifest.",
+         SubotaiError::MissingChunk { .. } => "Missing chunk.",
          SubotaiError::Io(ref err) => err.description(),
          SubotaiError::Deserialize(ref err) => err.description(),
+         SubotaiError::Serialize(ref err) => err.description(),
       }
    }
 
@@ -60,6 +104,7 @@ impl Error for SubotaiError {
       match *self {
          SubotaiError::Io(ref err) => Some(err),
          SubotaiError::Deserialize(ref err) => Some(err),
+         SubotaiError::Serialize(ref err) => Some(err),
          _ => None,
       }
    }
@@ -76,3 +121,9 @@ impl From<serde::DeserializeError> for SubotaiError {
       SubotaiError::Deserialize(err)
    }
 }
+
+impl From<serde::SerializeError> for SubotaiError {
+   fn from(err: serde::SerializeError) -> SubotaiError {
+      SubotaiError::Serialize(err)
+   }
+}
@@ -7,11 +7,13 @@
 //! which can then be used as a key for a storage entry.
 use rand::{thread_rng, Rng};
 use itertools;
-use std::ops::BitXor;
+use std::ops::{BitXor, Deref};
 use std::fmt;
 use std::fmt::Write;
 use std::cmp::{PartialOrd, Ordering};
+use std::str::FromStr;
 use sha1;
+use {SubotaiError, SubotaiResult};
 
 pub const HASH_SIZE : usize = 160;
 pub const HASH_SIZE_BYTES : usize = HASH_SIZE / 8;
@@ -36,7 +38,8 @@ impl SubotaiHash {
       hash
    }
 
-   /// Generates a SHA-1 hash from a string.
+   /// Generates a SHA-1 hash from a string. The digest is stored as-is, with its first
+   /// byte as the least significant one, so keys stay compatible across versions.
    pub fn sha1(data: &str) -> SubotaiHash {
       let mut m = sha1::Sha1::new();
       m.reset();
@@ -46,6 +49,18 @@ impl SubotaiHash {
       }
    }
 
+   /// Generates a SHA-1 hash from arbitrary data, to be used as a content addressed key.
+   /// Unlike `sha1`, the digest is stored with its first byte as the most significant one,
+   /// so `to_hex` produces the usual hexadecimal form of the SHA-1 digest.
+   pub fn from_content(data: &[u8]) -> SubotaiHash {
+      let mut m = sha1::Sha1::new();
+      m.reset();
+      m.update(data);
+      let mut raw = m.digest().bytes();
+      raw.reverse();
+      SubotaiHash { raw: raw }
+   }
+
    /// Creates a random hash at a given XOR distance from another (height of their XOR value).
    pub fn random_at_distance(reference: &SubotaiHash, distance: usize) -> SubotaiHash {
       let mut random_hash = SubotaiHash::random();
@@ -118,30 +133,165 @@ impl SubotaiHash {
       None
    }
 
+   /// Height of the XOR distance to another hash, which is the index of the routing
+   /// table bucket one of them would occupy in the other's table. Returns None if both
+   /// hashes are identical.
+   pub fn distance_to(&self, other: &SubotaiHash) -> Option<usize> {
+      (self ^ other).height()
+   }
+
+   /// Number of leading (most significant) bits shared with another hash.
+   pub fn common_prefix_len(&self, other: &SubotaiHash) -> usize {
+      match self.distance_to(other) {
+         Some(distance) => HASH_SIZE - 1 - distance,
+         None => HASH_SIZE,
+      }
+   }
+
    /// Flips a bit in the hash.
    pub fn flip_bit(&mut self, position : usize) {
       if position >= HASH_SIZE { return; }
       let byte = &mut self.raw[position / 8];
       *byte ^= 1 << (position % 8);
    }
+
+   /// Constructs a hash from exactly `HASH_SIZE_BYTES` bytes, in the same little
+   /// endian order as `raw` (least significant byte first). Returns None if the 
+   /// slice has any other length.
+   pub fn from_bytes(bytes: &[u8]) -> Option<SubotaiHash> {
+      if bytes.len() != HASH_SIZE_BYTES {
+         return None;
+      }
+
+      let mut hash = SubotaiHash::blank();
+      hash.raw.copy_from_slice(bytes);
+      Some(hash)
+   }
+
+   /// Returns a copy of the bytes of the hash, least significant byte first.
+   pub fn to_bytes(&self) -> [u8; HASH_SIZE_BYTES] {
+      self.raw
+   }
+
+   /// Produces a fixed width hexadecimal representation of the hash, with
+   /// the most significant byte first (`2 * HASH_SIZE_BYTES` characters).
+   pub fn to_hex(&self) -> String {
+      let mut hex = String::with_capacity(2 * HASH_SIZE_BYTES);
+      for byte in self.raw.iter().rev() {
+         write!(&mut hex, "{:02X}", byte).unwrap();
+      }
+      hex
+   }
+
+   /// Parses a hash from its fixed width hexadecimal representation, as
+   /// produced by `to_hex`. Returns None if the string is malformed.
+   pub fn from_hex(hex: &str) -> Option<SubotaiHash> {
+      if hex.len() != 2 * HASH_SIZE_BYTES || !hex.chars().all(|c| c.is_digit(16)) {
+         return None;
+      }
+
+      let mut hash = SubotaiHash::blank();
+      for (byte, index) in hash.raw.iter_mut().rev().zip(0..HASH_SIZE_BYTES) {
+         *byte = u8::from_str_radix(&hex[2 * index .. 2 * index + 2], 16).unwrap();
+      }
+      Some(hash)
+   }
 }
 
+/// Displays the hash as `0x[...]`, always with `2 * HASH_SIZE_BYTES` hexadecimal
+/// digits, most significant byte first.
 impl fmt::Display for SubotaiHash {
    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-      let mut leftpad_over = false;
-      let mut hex = String::new();
-      hex.push_str("0x[");
-      for byte in self.raw.iter().rev() {
-         if *byte > 0u8 {
-            leftpad_over = true;
-         }
+      write!(f, "0x[{}]", self.to_hex())
+   }
+}
 
-         if leftpad_over {
-            write!(&mut hex, "{:01$X}", byte, 2).unwrap();
+/// Parses either the `0x[...]` form produced by `Display` (also accepting it with its
+/// leading zero bytes omitted), or the plain hexadecimal form produced by `to_hex`.
+impl FromStr for SubotaiHash {
+   type Err = SubotaiError;
+
+   fn from_str(s: &str) -> SubotaiResult<SubotaiHash> {
+      let hex = if s.starts_with("0x[") && s.ends_with("]") && s.len() >= 4 {
+         let digits = &s[3 .. s.len() - 1];
+         if digits.len() > 2 * HASH_SIZE_BYTES {
+            return Err(SubotaiError::ParseError);
          }
-      }
-      hex.push_str("]");
-      write!(f, "{}", hex)
+         let mut padded : String = (digits.len() .. 2 * HASH_SIZE_BYTES).map(|_| '0').collect();
+         padded.push_str(digits);
+         padded
+      } else {
+         String::from(s)
+      };
+
+      SubotaiHash::from_hex(&hex).ok_or(SubotaiError::ParseError)
+   }
+}
+
+/// Hash identifying a node in the network. It's a thin wrapper that, unlike a plain
+/// `SubotaiHash`, can't be passed by mistake where a storage key is expected:
+///
+/// ```compile_fail
+/// let node = subotai::node::Node::new().unwrap();
+/// node.retrieve(node.id());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct NodeId(pub SubotaiHash);
+
+/// Hash identifying a group of entries in the network storage. Every method taking a
+/// key accepts either a `StorageKey` or a `SubotaiHash`, but never a `NodeId`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct StorageKey(pub SubotaiHash);
+
+impl Deref for NodeId {
+   type Target = SubotaiHash;
+
+   fn deref(&self) -> &SubotaiHash {
+      &self.0
+   }
+}
+
+impl Deref for StorageKey {
+   type Target = SubotaiHash;
+
+   fn deref(&self) -> &SubotaiHash {
+      &self.0
+   }
+}
+
+impl From<SubotaiHash> for NodeId {
+   fn from(hash: SubotaiHash) -> NodeId {
+      NodeId(hash)
+   }
+}
+
+impl<'a> From<&'a SubotaiHash> for NodeId {
+   fn from(hash: &'a SubotaiHash) -> NodeId {
+      NodeId(hash.clone())
+   }
+}
+
+impl<'a> From<&'a NodeId> for NodeId {
+   fn from(id: &'a NodeId) -> NodeId {
+      id.clone()
+   }
+}
+
+impl From<SubotaiHash> for StorageKey {
+   fn from(hash: SubotaiHash) -> StorageKey {
+      StorageKey(hash)
+   }
+}
+
+impl<'a> From<&'a SubotaiHash> for StorageKey {
+   fn from(hash: &'a SubotaiHash) -> StorageKey {
+      StorageKey(hash.clone())
+   }
+}
+
+impl<'a> From<&'a StorageKey> for StorageKey {
+   fn from(key: &'a StorageKey) -> StorageKey {
+      key.clone()
    }
 }
 
@@ -330,6 +480,22 @@ impl BitXor for SubotaiHash {
    }
 }
 
+impl<'a, 'b> BitXor<&'b SubotaiHash> for &'a NodeId {
+   type Output = SubotaiHash;
+
+   fn bitxor (self, rhs: &'b SubotaiHash) -> SubotaiHash {
+      &self.0 ^ rhs
+   }
+}
+
+impl<'a, 'b> BitXor<&'b NodeId> for &'a NodeId {
+   type Output = SubotaiHash;
+
+   fn bitxor (self, rhs: &'b NodeId) -> SubotaiHash {
+      &self.0 ^ &rhs.0
+   }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -396,6 +562,109 @@ mod tests {
        }
     }
 
+   #[test]
+   fn hex_round_trip() {
+      let hash = SubotaiHash::random();
+      let hex = hash.to_hex();
+      assert_eq!(hex.len(), 2 * HASH_SIZE_BYTES);
+      assert_eq!(SubotaiHash::from_hex(&hex), Some(hash));
+      assert_eq!(SubotaiHash::from_hex(&hex.to_lowercase()), SubotaiHash::from_hex(&hex));
+
+      let mut hash = SubotaiHash::blank();
+      hash.raw[HASH_SIZE_BYTES - 1] = 0xAB;
+      assert!(hash.to_hex().starts_with("AB00"));
+   }
+
+   #[test]
+   fn hex_parsing_rejects_malformed_strings() {
+      let hex = SubotaiHash::random().to_hex();
+      assert!(SubotaiHash::from_hex(&hex[1..]).is_none());
+      assert!(SubotaiHash::from_hex(&format!("{}0", hex)).is_none());
+      assert!(SubotaiHash::from_hex(&hex.replace(&hex[0..1], "G")).is_none());
+      assert!(SubotaiHash::from_hex("").is_none());
+   }
+
+   #[test]
+   fn content_hash_matches_known_sha1_vector() {
+      let hash = SubotaiHash::from_content(b"abc");
+      assert_eq!(hash.to_hex(), "A9993E364706816ABA3E25717850C26C9CD0D89D");
+      assert_eq!(hash.height(), Some(HASH_SIZE - 1));
+   }
+
+   #[test]
+   fn string_hash_keeps_digest_byte_order() {
+      let hash = SubotaiHash::sha1("abc");
+      assert_eq!(hash.to_hex(), "9DD8D09C6CC2507871253EBA6A810647363E99A9");
+
+      let mut reversed = hash.raw;
+      reversed.reverse();
+      assert_eq!(SubotaiHash::from_content(b"abc").raw, reversed);
+   }
+
+   #[test]
+   fn distance_and_common_prefix() {
+      let hash = SubotaiHash::random();
+      assert_eq!(hash.distance_to(&hash), None);
+      assert_eq!(hash.common_prefix_len(&hash), HASH_SIZE);
+
+      let mut opposite = hash.clone();
+      for byte in opposite.raw.iter_mut() {
+         *byte = !*byte;
+      }
+      assert_eq!(hash.distance_to(&opposite), Some(HASH_SIZE - 1));
+      assert_eq!(hash.common_prefix_len(&opposite), 0);
+
+      let mut close = hash.clone();
+      close.flip_bit(3);
+      assert_eq!(close.distance_to(&hash), Some(3));
+      assert_eq!(close.common_prefix_len(&hash), HASH_SIZE - 4);
+   }
+
+   #[test]
+   fn display_is_fixed_width() {
+      let zeroes: String = (0..2 * HASH_SIZE_BYTES).map(|_| '0').collect();
+      assert_eq!(format!("{}", SubotaiHash::blank()), format!("0x[{}]", zeroes));
+
+      let mut high = SubotaiHash::blank();
+      let mut higher = SubotaiHash::blank();
+      high.raw[HASH_SIZE_BYTES - 2] = 0x01;
+      higher.raw[HASH_SIZE_BYTES - 1] = 0x01;
+      let (high, higher) = (format!("{}", high), format!("{}", higher));
+      assert!(high != higher);
+      assert_eq!(high.len(), higher.len());
+      assert_eq!(high.len(), 2 * HASH_SIZE_BYTES + 4);
+   }
+
+   #[test]
+   fn parsing_inverts_display_and_hex() {
+      for _ in 0..50 {
+         let hash = SubotaiHash::random();
+         assert_eq!(format!("{}", hash).parse::<SubotaiHash>().unwrap(), hash);
+         assert_eq!(hash.to_hex().parse::<SubotaiHash>().unwrap(), hash);
+      }
+
+      let mut small = SubotaiHash::blank();
+      small.raw[0] = 0x0A;
+      assert_eq!("0x[0A]".parse::<SubotaiHash>().unwrap(), small);
+      assert_eq!("0x[]".parse::<SubotaiHash>().unwrap(), SubotaiHash::blank());
+
+      assert!("0x[0G]".parse::<SubotaiHash>().is_err());
+      assert!("0A".parse::<SubotaiHash>().is_err());
+      assert!("0x[".parse::<SubotaiHash>().is_err());
+   }
+
+   #[test]
+   fn bytes_round_trip() {
+      let hash = SubotaiHash::random();
+      assert_eq!(SubotaiHash::from_bytes(&hash.to_bytes()), Some(hash));
+      assert!(SubotaiHash::from_bytes(&[0u8; HASH_SIZE_BYTES - 1]).is_none());
+      assert!(SubotaiHash::from_bytes(&[0u8; HASH_SIZE_BYTES + 1]).is_none());
+
+      let mut bytes = [0u8; HASH_SIZE_BYTES];
+      bytes[0] = 1;
+      assert_eq!(SubotaiHash::from_bytes(&bytes).unwrap().height(), Some(0));
+   }
+
    #[test]
    fn random_at_a_distance() {
       let test_hash = SubotaiHash::random();
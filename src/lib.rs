@@ -32,6 +32,10 @@ extern crate bincode;
 extern crate bus;
 extern crate time;
 extern crate sha1;
+#[cfg(feature = "msgpack")]
+extern crate rmp_serde;
+#[cfg(feature = "ed25519")]
+extern crate ed25519_dalek;
 
 pub mod node;
 pub mod hash;
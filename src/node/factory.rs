@@ -3,14 +3,18 @@
 //! The factory module allows you to create Subotai nodes with specific configuration options,
 //! such as network constants and different UDP ports.
 use {node, SubotaiResult};
-use std::cmp;
+use bincode::serde;
+use std::{cmp, net};
 
 /// Allows the construction of nodes with custom network constants, specific ports,
 /// and other options.
 pub struct Factory {
-   configuration : node::Configuration,
-   inbound_port  : u16,
-   outbound_port : u16,
+   configuration              : node::Configuration,
+   bind_address               : net::IpAddr,
+   inbound_port               : u16,
+   outbound_port              : u16,
+   state                      : Option<node::NodeState>,
+   explicit_responses_advance : bool,
 }
 
 impl Default for Factory {
@@ -22,16 +26,52 @@ impl Default for Factory {
 impl Factory {
    pub fn new() -> Self {
       Factory {
-         configuration : Default::default(),
-         inbound_port  : 0,
-         outbound_port : 0,
+         configuration              : Default::default(),
+         bind_address               : net::IpAddr::V4(net::Ipv4Addr::new(0, 0, 0, 0)),
+         inbound_port               : 0,
+         outbound_port              : 0,
+         state                      : None,
+         explicit_responses_advance : false,
       }
    }
 
+   /// Constructs a factory that restores a node from a blob produced by `Node::export_state`. 
+   /// The node keeps its former ID, contacts and unexpired storage entries, but binds new 
+   /// sockets, so it should bootstrap to let the network know its new address.
+   pub fn from_state(bytes: &[u8]) -> SubotaiResult<Factory> {
+      let state: node::NodeState = try!(serde::deserialize(bytes));
+      Ok(Factory { state: Some(state), .. Factory::new() })
+   }
+
+   /// Configuration the factory will build nodes with. Some setters clamp values relative
+   /// to each other (e.g. impatience is always lower than alpha), so this may differ
+   /// from the values originally requested.
+   pub fn effective_configuration(&self) -> &node::Configuration {
+      &self.configuration
+   }
+
    /// Creates a node with the configuration values specified in the factory. Defaults to the 
    /// same values as calling Node::new().
    pub fn create_node(&self) -> SubotaiResult<node::Node> {
-      node::Node::with_configuration(self.inbound_port, self.outbound_port, self.configuration.clone())
+      node::Node::with_state(self.bind_address, self.inbound_port, self.outbound_port, self.configuration.clone(), self.state.clone())
+   }
+
+   /// Like `create_node`, but also reports any risky configuration values (see 
+   /// `Configuration::validate`). The warnings are informative; the node is created anyway.
+   pub fn create_node_checked(&self) -> SubotaiResult<(node::Node, Vec<String>)> {
+      let warnings = self.configuration.validate();
+      let node = try!(self.create_node());
+      Ok((node, warnings))
+   }
+
+   /// Local IP address both UDP sockets bind to. Defaults to `0.0.0.0` (all IPv4 
+   /// interfaces). Useful to pick an interface on multi-homed hosts, or to keep a
+   /// node reachable only through localhost. Binding to an IPv6 address (e.g. `::`)
+   /// makes the node part of an IPv6 network, as it can only reach nodes of the 
+   /// same address family.
+   pub fn bind_address(mut self, address: net::IpAddr) -> Self {
+      self.bind_address = address;
+      self
    }
    
    /// Inbound UDP port for incoming RPCs.
@@ -52,7 +92,7 @@ impl Factory {
    pub fn alpha(mut self, alpha: usize) -> Self {
       self.configuration.alpha = alpha;
       self.configuration.impatience = cmp::min(usize::saturating_sub(alpha, 1), self.configuration.impatience);
-      self
+      self.derive_min_responses_to_advance()
    }
 
    /// Impatience factor, valid in the range [0..ALPHA). When performing "waves",
@@ -60,9 +100,28 @@ impl Factory {
    /// starting the next wave. 
    ///
    /// If we send a request to ALPHA nodes during a lookup wave, we will start
-   /// the next wave after we receive 'ALPHA - IMPATIENCE' responses.
+   /// the next wave after we receive 'ALPHA - IMPATIENCE' responses, unless
+   /// `min_responses_to_advance` has been set explicitly.
    pub fn impatience(mut self, impatience: usize) -> Self {
-      self.configuration.impatience = cmp::min(usize::saturating_sub(self.configuration.alpha, 1), impatience);;
+      self.configuration.impatience = cmp::min(usize::saturating_sub(self.configuration.alpha, 1), impatience);
+      self.derive_min_responses_to_advance()
+   }
+
+   /// Number of responses a wave waits for before advancing, valid in the range
+   /// [1..ALPHA]. Higher values trade responsiveness for lookup quality. Once set,
+   /// it no longer follows 'ALPHA - IMPATIENCE' when either of those change.
+   pub fn min_responses_to_advance(mut self, min_responses_to_advance: usize) -> Self {
+      self.explicit_responses_advance = true;
+      self.configuration.min_responses_to_advance = min_responses_to_advance;
+      self.derive_min_responses_to_advance()
+   }
+
+   fn derive_min_responses_to_advance(mut self) -> Self {
+      let alpha = self.configuration.alpha;
+      if !self.explicit_responses_advance {
+         self.configuration.min_responses_to_advance = alpha - self.configuration.impatience;
+      }
+      self.configuration.min_responses_to_advance = cmp::max(1, cmp::min(alpha, self.configuration.min_responses_to_advance));
       self
    }
 
@@ -73,6 +132,21 @@ impl Factory {
       self
    }
 
+   /// Maximum amount of nodes in the routing table, bounding its memory footprint
+   /// regardless of the `k_factor`. Once reached, new nodes are ignored while known
+   /// ones are still updated. A value of 0 means no limit other than the bucket size.
+   pub fn max_total_contacts(mut self, max_total_contacts: usize) -> Self {
+      self.configuration.max_total_contacts = max_total_contacts;
+      self
+   }
+
+   /// Whether the node keeps its own entry in its routing table. Without it, the table
+   /// only ever contains remote contacts.
+   pub fn insert_self_in_table(mut self, insert_self_in_table: bool) -> Self {
+      self.configuration.insert_self_in_table = insert_self_in_table;
+      self
+   }
+
    /// Maximum amount of eviction conflicts allowed before the node goes into
    /// a temporary defensive mode, and starts to prioritize old contacts to new, 
    /// potentially malicious ones.
@@ -89,6 +163,20 @@ impl Factory {
       self
    }
 
+   /// Maximum combined size in bytes of all storage entries. Blobs count for their
+   /// length, and value entries for the size of a hash.
+   pub fn max_storage_bytes(mut self, max_storage_bytes: usize) -> Self {
+      self.configuration.max_storage_bytes = max_storage_bytes;
+      self
+   }
+
+   /// Maximum amount of distinct entries stored under a single key. Storing a new entry
+   /// under a full key fails, while refreshing one already stored still succeeds.
+   pub fn max_entries_per_key(mut self, max_entries_per_key: usize) -> Self {
+      self.configuration.max_entries_per_key = max_entries_per_key;
+      self
+   }
+
    /// Maximum size in bytes for a blob storage entry. (A blob entry consists in a 
    /// key associated with a chunk of binary data, instead of a 160 bit value hash).
    pub fn max_storage_blob_size(mut self, max_storage_blob_size: usize) -> Self {
@@ -113,6 +201,121 @@ impl Factory {
       self
    }
 
+   /// Read timeout in milliseconds for the inbound socket. Every time it expires, the node
+   /// checks whether it's shutting down and notifies time-bound operations. Longer timeouts
+   /// mean fewer wakeups on idle nodes, at the cost of slower shutdowns.
+   pub fn socket_read_timeout_ms(mut self, socket_read_timeout_ms: u64) -> Self {
+      self.configuration.socket_read_timeout_ms = socket_read_timeout_ms;
+      self
+   }
+
+   /// Maximum amount of waves (network-wide lookups, probes and retrieves) this node
+   /// will start per second. Any excess operation fails with `SubotaiError::RateLimited`
+   /// instead of reaching the network. A value of 0 disables the limit.
+   pub fn max_waves_per_sec(mut self, max_waves_per_sec: usize) -> Self {
+      self.configuration.max_waves_per_sec = max_waves_per_sec;
+      self
+   }
+
+   /// Refresh interval in minutes for the buckets closest to this node. Buckets are
+   /// indexed by the height of their xor distance to this node, so lower indices hold
+   /// closer nodes. The interval doubles every 40 bucket indices, so the most distant
+   /// buckets, which change slowly, are refreshed eight times less often.
+   pub fn bucket_refresh_base_mins(mut self, bucket_refresh_base_mins: i64) -> Self {
+      self.configuration.bucket_refresh_base_mins = bucket_refresh_base_mins;
+      self
+   }
+
+   /// When set, the node refuses to store entries under keys it doesn't believe itself
+   /// responsible for (i.e. it doesn't rank among the `k_factor` closest nodes to the key
+   /// according to its routing table). This prevents the node from being used as arbitrary
+   /// storage, at the cost of also refusing cached copies from retrieve operations.
+   pub fn reject_foreign_stores(mut self, reject_foreign_stores: bool) -> Self {
+      self.configuration.reject_foreign_stores = reject_foreign_stores;
+      self
+   }
+
+   /// When set, the maintenance thread picks randomly which bucket to refresh among those
+   /// due, favouring the most overdue ones, instead of always refreshing the most overdue.
+   /// Together with the random probe target within the bucket, this makes maintenance
+   /// traffic harder to predict and exploit.
+   pub fn randomize_bucket_refresh(mut self, randomize_bucket_refresh: bool) -> Self {
+      self.configuration.randomize_bucket_refresh = randomize_bucket_refresh;
+      self
+   }
+
+   /// When set, a node trying to enter a full bucket causes a conflict with the least
+   /// recently seen node in it, which is pinged before being evicted for good, and too many
+   /// simultaneous conflicts put the node in defensive mode. Disabling it on trusted,
+   /// controlled networks makes newcomers always take the place of the evicted node.
+   pub fn enable_defensive_mode(mut self, enable_defensive_mode: bool) -> Self {
+      self.configuration.enable_defensive_mode = enable_defensive_mode;
+      self
+   }
+
+   /// Interval in seconds at which the maintenance thread reconciles its storage with
+   /// a random neighbor (see `Node::reconcile_with`). A value of 0 disables it.
+   pub fn reconcile_interval_s(mut self, reconcile_interval_s: i64) -> Self {
+      self.configuration.reconcile_interval_s = reconcile_interval_s;
+      self
+   }
+
+   /// Amount of times a ping is re-sent if it isn't answered, to tolerate dropped packets.
+   /// Each attempt waits twice as long as the previous one, and all of them together take
+   /// at most `network_timeout_s`.
+   pub fn ping_retries(mut self, ping_retries: u32) -> Self {
+      self.configuration.ping_retries = ping_retries;
+      self
+   }
+
+   /// Interval in seconds at which the maintenance thread wakes up to refresh the most
+   /// overdue bucket, reconcile with a neighbor or republish entries when due. It bounds
+   /// how precisely the other maintenance intervals are honored.
+   pub fn maintenance_interval_s(mut self, maintenance_interval_s: i64) -> Self {
+      self.configuration.maintenance_interval_s = maintenance_interval_s;
+      self
+   }
+
+   /// Interval in seconds at which the maintenance thread republishes the entries this 
+   /// node holds, unless another node stored them in it since the last republish. It
+   /// should be well below `base_expiration_time_hrs`, or entries will expire before 
+   /// they're republished.
+   pub fn republish_interval_s(mut self, republish_interval_s: i64) -> Self {
+      self.configuration.republish_interval_s = republish_interval_s;
+      self
+   }
+
+   /// Format used to encode RPCs on the wire. Nodes using different formats
+   /// can't understand each other.
+   pub fn wire_format(mut self, wire_format: node::WireFormat) -> Self {
+      self.configuration.wire_format = wire_format;
+      self
+   }
+
+   /// Subnets this node accepts traffic from. Datagrams from any other source are
+   /// dropped on arrival, before being decoded. An empty list accepts any source.
+   pub fn allowed_cidrs(mut self, allowed_cidrs: Vec<node::Subnet>) -> Self {
+      self.configuration.allowed_cidrs = allowed_cidrs;
+      self
+   }
+
+   /// Application-defined region tag of this node (e.g. a datacenter ID), advertised to
+   /// other nodes. When set, waves query candidates in the same region first among those at
+   /// the same bucket distance from the target. Wave results are still sorted by distance alone.
+   pub fn region(mut self, region: u16) -> Self {
+      self.configuration.region = Some(region);
+      self
+   }
+
+   /// Fraction of the `k_factor` replicas of a key that must accept a store for it to 
+   /// succeed, in the range [0..1]. A value of 0 makes stores succeed as soon as they're
+   /// sent, without waiting for any response. Any other value requires at least one
+   /// replica, even if the fraction of `k_factor` rounds down to zero.
+   pub fn store_quorum(mut self, store_quorum: f64) -> Self {
+      self.configuration.store_quorum = store_quorum.max(0.0).min(1.0);
+      self
+   }
+
    /// Base expiration time for storage entries. Every time you call `store` on a node
    /// that resides on a live network (i.e. is in an `OnGrid` state) you guarantee the
    /// entry will remain in the network for this number of hours. Calling `store` again
@@ -132,18 +335,72 @@ impl Factory {
       self.configuration.base_cache_time_mins = cmp::min(59i64, base_cache_time_mins);
       self
    }
+
+   /// Time in seconds for which entries found by a retrieve are cached by the retrieving
+   /// node, so successive retrieves of a hot key don't flood the network. Longer times
+   /// lighten the load around hot keys, shorter ones reduce the risk of stale reads.
+   pub fn read_cache_ttl_s(mut self, read_cache_ttl_s: i64) -> Self {
+      self.configuration.read_cache_ttl_s = read_cache_ttl_s;
+      self
+   }
 }
 
 #[cfg(test)]
 mod tests {
    use super::*;
 
+   #[test]
+   fn small_k_factor_produces_a_durability_warning() {
+      let (_, warnings) = Factory::new().k_factor(2).create_node_checked().unwrap();
+      assert_eq!(warnings.len(), 1);
+      assert!(warnings[0].contains("k_factor of 2"));
+      assert!(Factory::new().effective_configuration().validate().is_empty());
+
+      // The store quorum still requires a replica, even if a third of 2 rounds down to 0.
+      assert_eq!(Factory::new().k_factor(2).effective_configuration().store_quorum_replicas(), 1);
+      assert_eq!(Factory::new().k_factor(2).store_quorum(0.0).effective_configuration().store_quorum_replicas(), 0);
+   }
+
+   #[test]
+   fn store_quorum_is_clamped_to_a_fraction() {
+      assert_eq!(Factory::new().store_quorum(3.0).configuration.store_quorum, 1.0);
+      assert_eq!(Factory::new().store_quorum(-1.0).configuration.store_quorum, 0.0);
+      assert_eq!(Factory::new().store_quorum(0.5).configuration.store_quorum, 0.5);
+   }
+
    #[test]
    fn impatience_always_lower_than_alpha() {
       let factory = Factory::new().alpha(5).impatience(10);
       assert_eq!(factory.configuration.impatience, 4);
    }
 
+   #[test]
+   fn min_responses_to_advance_clamped_between_one_and_alpha() {
+      let factory = Factory::new().alpha(5).min_responses_to_advance(10);
+      assert_eq!(factory.configuration.min_responses_to_advance, 5);
+      let factory = factory.min_responses_to_advance(0);
+      assert_eq!(factory.configuration.min_responses_to_advance, 1);
+      let factory = Factory::new().min_responses_to_advance(4).alpha(2);
+      assert_eq!(factory.configuration.min_responses_to_advance, 2);
+   }
+
+   #[test]
+   fn min_responses_to_advance_follows_impatience_unless_set_explicitly() {
+      let factory = Factory::new().alpha(10);
+      assert_eq!(factory.configuration.min_responses_to_advance, 8);
+      let factory = factory.impatience(4);
+      assert_eq!(factory.configuration.min_responses_to_advance, 6);
+      let factory = factory.min_responses_to_advance(3).alpha(12).impatience(1);
+      assert_eq!(factory.configuration.min_responses_to_advance, 3);
+   }
+
+   #[test]
+   fn effective_configuration_reports_clamped_values() {
+      let factory = Factory::new().alpha(5).impatience(10);
+      assert_eq!(factory.effective_configuration().alpha, 5);
+      assert_eq!(factory.effective_configuration().impatience, 4);
+   }
+
    #[test]
    fn base_cached_expiration_always_lower_than_hour() {
       let factory = Factory::new().base_cache_time_mins(61);
@@ -17,32 +17,159 @@
 pub mod receptions;
 pub use routing::NodeInfo as NodeInfo;
 pub use storage::StorageEntry as StorageEntry;
+pub use storage::StoreResult as StoreResult;
 pub use node::factory::Factory as Factory;
+pub use node::resources::MassStoreReport as MassStoreReport;
+pub use node::resources::StoreReport as StoreReport;
+pub use node::resources::RetrieveOutcome as RetrieveOutcome;
+pub use node::resources::NetworkUpdate as NetworkUpdate;
+pub use node::resources::StateUpdate as StateUpdate;
 
 #[cfg(test)]
 mod tests;
 mod resources;
 mod factory;
 
-use {storage, routing, rpc, bus, SubotaiResult, time};
-use hash::SubotaiHash;
-use std::{net, thread, sync};
+use {storage, routing, rpc, bus, bincode, SubotaiError, SubotaiResult, time};
+use bincode::serde;
+use hash::{SubotaiHash, NodeId, StorageKey, HASH_SIZE};
+use std::{net, thread, sync, cmp};
+use std::str::FromStr;
+use std::collections::{VecDeque, HashMap};
 use std::time::Duration as StdDuration;
 
+/// Version of the crate this node was built with. It's reported to other nodes on every ping.
+pub const CRATE_VERSION : &'static str = env!("CARGO_PKG_VERSION");
+
 /// Size of a typical UDP socket buffer.
 pub const SOCKET_BUFFER_SIZE_BYTES : usize = 65536;
-const SOCKET_TIMEOUT_MS     : u64   = 200;
-const UPDATE_BUS_SIZE_BYTES : usize = 50;
 
-/// Maintenance thread sleep period.
-const MAINTENANCE_SLEEP_S : u64 = 5;
+/// Keys sent per digest response when reconciling storage, so the key list of a node
+/// holding any amount of data is exchanged in datagrams that fit a socket buffer.
+pub const DIGEST_PAGE_KEYS : usize = 1000;
+const UPDATE_BUS_SIZE_BYTES : usize = 50;
 
 /// Attempts to probe self during the bootstrap process.
 const BOOTSTRAP_TRIES : u32 = 3;
 
+/// Amount of recent liveness checks remembered for each contact to estimate its reliability.
+pub const RELIABILITY_WINDOW : usize = 20;
+
+/// Snapshot of everything needed to migrate a node, as produced by `Node::export_state`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct NodeState {
+   id       : NodeId,
+   contacts : Vec<routing::NodeInfo>,
+   entries  : Vec<(SubotaiHash, Vec<(storage::StorageEntry, rpc::SerializableTime)>)>,
+}
+
 /// Subotai node. 
 pub struct Node {
-   resources: sync::Arc<resources::Resources>,
+   resources : sync::Arc<resources::Resources>,
+   threads   : Vec<thread::JoinHandle<()>>,
+}
+
+/// Format used to encode RPCs on the wire. All nodes in a network must use the same one.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum WireFormat {
+   /// Compact binary format, only understood by other Subotai nodes.
+   Bincode,
+
+   /// Self-describing MessagePack format, for interoperability with non-Rust clients.
+   #[cfg(feature = "msgpack")]
+   Msgpack,
+}
+
+/// Range of IP addresses in CIDR notation (e.g. `10.0.0.0/8` or `fd00::/8`).
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct Subnet {
+   address    : net::IpAddr,
+   prefix_len : u8,
+}
+
+impl Subnet {
+   /// Constructs a subnet from a base address and the number of leading bits that
+   /// identify the network. Fails with `OutOfBounds` if the prefix is longer than
+   /// the address.
+   pub fn new(address: net::IpAddr, prefix_len: u8) -> SubotaiResult<Subnet> {
+      if prefix_len as usize > Subnet::octets(&address).len() * 8 {
+         return Err(SubotaiError::OutOfBounds);
+      }
+      Ok(Subnet { address: address, prefix_len: prefix_len })
+   }
+
+   /// Whether an address falls within the subnet. Addresses of a different family
+   /// (IPv4/IPv6) are never contained.
+   pub fn contains(&self, address: &net::IpAddr) -> bool {
+      let (own, other) = (Subnet::octets(&self.address), Subnet::octets(address));
+      if own.len() != other.len() {
+         return false;
+      }
+
+      let full_octets = self.prefix_len as usize / 8;
+      let remaining_bits = self.prefix_len % 8;
+      if own[..full_octets] != other[..full_octets] {
+         return false;
+      }
+
+      remaining_bits == 0 || {
+         let mask = 0xffu8 << (8 - remaining_bits);
+         own[full_octets] & mask == other[full_octets] & mask
+      }
+   }
+
+   fn octets(address: &net::IpAddr) -> Vec<u8> {
+      match *address {
+         net::IpAddr::V4(ref address) => address.octets().to_vec(),
+         net::IpAddr::V6(ref address) => address.octets().to_vec(),
+      }
+   }
+}
+
+impl FromStr for Subnet {
+   type Err = SubotaiError;
+
+   fn from_str(s: &str) -> SubotaiResult<Subnet> {
+      let mut parts = s.splitn(2, '/');
+      let address = try!(parts.next().unwrap().parse().map_err(|_| SubotaiError::ParseError));
+      let prefix_len = match parts.next() {
+         Some(prefix_len) => try!(prefix_len.parse().map_err(|_| SubotaiError::ParseError)),
+         None => return Err(SubotaiError::ParseError),
+      };
+      Subnet::new(address, prefix_len)
+   }
+}
+
+/// Snapshot of a node's overall health, as produced by `Node::health`.
+#[derive(Debug, Clone)]
+pub struct HealthReport {
+   /// Current state of the node.
+   pub state             : State,
+   /// Number of contacts in the routing table, excluding the node itself.
+   pub routing_len       : usize,
+   /// Number of routing table buckets holding at least one contact.
+   pub populated_buckets : usize,
+   /// Number of keys held in local storage.
+   pub storage_len       : usize,
+   /// Fraction of the storage capacity in use, between 0.0 and 1.0.
+   pub storage_load      : f64,
+   /// Time elapsed since the node was created.
+   pub uptime            : time::Duration,
+   /// Whether the node is part of a live network and knows at least `k_factor` contacts.
+   pub is_well_connected : bool,
+}
+
+/// Routing table metrics, as produced by `Node::table_info`.
+#[derive(Debug, Clone)]
+pub struct TableInfo {
+   /// Total number of nodes in the routing table.
+   pub len                  : usize,
+   /// Number of buckets holding at least one node.
+   pub populated_buckets    : usize,
+   /// Index of the bucket holding the most nodes, or `None` if the table is empty.
+   pub fullest_bucket       : Option<usize>,
+   /// Index of the bucket that hasn't been probed for the longest.
+   pub oldest_probed_bucket : usize,
 }
 
 /// State of a Subotai node. 
@@ -66,6 +193,24 @@ pub enum State {
    ShuttingDown,
 }
 
+/// Outcome of introducing a contact into the routing table.
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum ContactUpdate {
+   /// The contact was unknown, and has been added.
+   Added,
+
+   /// The contact was already known, and has been refreshed.
+   Updated,
+
+   /// The contact's bucket is full. The least recently seen contact in that bucket
+   /// will be pinged, and replaced by the new contact if it doesn't respond.
+   Conflict,
+
+   /// The contact was ignored, either because the node is in defensive mode and
+   /// its bucket is full, or because the routing table reached `max_total_contacts`.
+   Rejected,
+}
+
 /// Network configuration constants. Do not set these values directly, as there 
 /// is no way to initialize a node from a `Configuration` struct. Instead, use 
 /// `node::Factory` if you want your application to use non-default network constants.
@@ -88,10 +233,24 @@ pub struct Configuration {
    /// the next wave after we receive 'ALPHA - IMPATIENCE' responses.
    pub impatience                    : usize,
 
+   /// Number of responses a wave waits for before advancing, valid in the range
+   /// [1..ALPHA]. It defaults to 'ALPHA - IMPATIENCE', but can be raised on lossy
+   /// networks to avoid starting new waves on stale information.
+   pub min_responses_to_advance      : usize,
+
    /// Data structure factor. It's used to dictate the size of the internal routing
    /// data structures (k-buckets).
    pub k_factor                      : usize,
 
+   /// Maximum amount of nodes in the routing table, bounding its memory footprint
+   /// regardless of the `k_factor`. Once reached, new nodes are ignored while known
+   /// ones are still updated. A value of 0 means no limit other than the bucket size.
+   pub max_total_contacts            : usize,
+
+   /// Whether the node keeps its own entry in its routing table. Without it, the table
+   /// only ever contains remote contacts.
+   pub insert_self_in_table          : bool,
+
    /// Maximum amount of eviction conflicts allowed before the node goes into
    /// a temporary defensive mode, and starts to prioritize old contacts to new, 
    /// potentially malicious ones.
@@ -102,6 +261,14 @@ pub struct Configuration {
    /// dictated by the k_factor.
    pub max_storage                   : usize,
 
+   /// Maximum combined size in bytes of all storage entries. Blobs count for their
+   /// length, and value entries for the size of a hash.
+   pub max_storage_bytes             : usize,
+
+   /// Maximum amount of distinct entries stored under a single key. Storing a new entry
+   /// under a full key fails, while refreshing one already stored still succeeds.
+   pub max_entries_per_key           : usize,
+
    /// Maximum size in bytes for a blob storage entry. (A blob entry consists in a 
    /// key associated with a chunk of binary data, instead of a 160 bit value hash).
    pub max_storage_blob_size         : usize,
@@ -125,9 +292,120 @@ pub struct Configuration {
    /// entries do not live long enough to be republished.
    pub base_cache_time_mins           : i64,
 
+   /// Time in seconds for which entries found by a retrieve are cached by the retrieving
+   /// node, so successive retrieves of a hot key don't flood the network. Longer times
+   /// lighten the load around hot keys, shorter ones reduce the risk of stale reads.
+   pub read_cache_ttl_s              : i64,
+
    /// Time in seconds after which it can be assumed that a remote node has failed to 
    /// respond to a query.
    pub network_timeout_s             : i64,
+
+   /// Read timeout in milliseconds for the inbound socket. Every time it expires, the node
+   /// checks whether it's shutting down and notifies time-bound operations. Longer timeouts
+   /// mean fewer wakeups on idle nodes, at the cost of slower shutdowns.
+   pub socket_read_timeout_ms        : u64,
+
+   /// Maximum amount of waves (network-wide lookups, probes and retrieves) this node
+   /// will start per second. Any excess operation fails with `SubotaiError::RateLimited`
+   /// instead of reaching the network. A value of 0 disables the limit.
+   pub max_waves_per_sec             : usize,
+
+   /// Refresh interval in minutes for the buckets closest to this node. Buckets are
+   /// indexed by the height of their xor distance to this node, so lower indices hold
+   /// closer nodes. The interval doubles every 40 bucket indices, so the most distant
+   /// buckets, which change slowly, are refreshed eight times less often.
+   pub bucket_refresh_base_mins      : i64,
+
+   /// When set, the node refuses to store entries under keys it doesn't believe itself
+   /// responsible for (i.e. it doesn't rank among the `k_factor` closest nodes to the key
+   /// according to its routing table). This prevents the node from being used as arbitrary
+   /// storage, at the cost of also refusing cached copies from retrieve operations.
+   pub reject_foreign_stores         : bool,
+
+   /// When set, the maintenance thread picks randomly which bucket to refresh among those
+   /// due, favouring the most overdue ones, instead of always refreshing the most overdue.
+   /// Together with the random probe target within the bucket, this makes maintenance
+   /// traffic harder to predict and exploit.
+   pub randomize_bucket_refresh      : bool,
+
+   /// When set, a node trying to enter a full bucket causes a conflict with the least
+   /// recently seen node in it, which is pinged before being evicted for good, and too many
+   /// simultaneous conflicts put the node in defensive mode. Disabling it on trusted,
+   /// controlled networks makes newcomers always take the place of the evicted node.
+   pub enable_defensive_mode         : bool,
+
+   /// Interval in seconds at which the maintenance thread reconciles its storage with
+   /// a random neighbor (see `Node::reconcile_with`). A value of 0 disables it.
+   pub reconcile_interval_s          : i64,
+
+   /// Amount of times a ping is re-sent if it isn't answered, to tolerate dropped packets.
+   /// Each attempt waits twice as long as the previous one, and all of them together take
+   /// at most `network_timeout_s`.
+   pub ping_retries                  : u32,
+
+   /// Interval in seconds at which the maintenance thread wakes up to refresh the most
+   /// overdue bucket, reconcile with a neighbor or republish entries when due. It bounds
+   /// how precisely the other maintenance intervals are honored.
+   pub maintenance_interval_s        : i64,
+
+   /// Interval in seconds at which the maintenance thread republishes the entries this 
+   /// node holds, unless another node stored them in it since the last republish. It
+   /// should be well below `base_expiration_time_hrs`, or entries will expire before 
+   /// they're republished.
+   pub republish_interval_s          : i64,
+
+   /// Format used to encode RPCs on the wire. Nodes using different formats
+   /// can't understand each other.
+   pub wire_format                   : WireFormat,
+
+   /// Subnets this node accepts traffic from. Datagrams from any other source are
+   /// dropped on arrival, before being decoded. An empty list accepts any source.
+   pub allowed_cidrs                 : Vec<Subnet>,
+
+   /// Application-defined region tag of this node (e.g. a datacenter ID), advertised to
+   /// other nodes. When set, waves query candidates in the same region first among those at
+   /// the same bucket distance from the target. Wave results are still sorted by distance alone.
+   pub region                        : Option<u16>,
+
+   /// Fraction of the `k_factor` replicas of a key that must accept a store for it to 
+   /// succeed, in the range [0..1]. A value of 0 makes stores succeed as soon as they're
+   /// sent, without waiting for any response. Any other value requires at least one
+   /// replica, even if the fraction of `k_factor` rounds down to zero.
+   pub store_quorum                  : f64,
+}
+
+impl Configuration {
+   /// Reports configuration values that are accepted, but likely to make the network
+   /// unreliable, as human readable warnings. An empty list means nothing looks risky.
+   pub fn validate(&self) -> Vec<String> {
+      let mut warnings = Vec::new();
+      if self.k_factor < 3 {
+         warnings.push(format!("A k_factor of {} keeps fewer than 3 replicas of each entry, so durability is weak.", 
+                               self.k_factor));
+      }
+      // Small k factors are already warned about, regardless of the quorum.
+      if self.k_factor >= 3 && self.store_quorum > 0.0 && (self.k_factor as f64 * self.store_quorum) < 1.0 {
+         warnings.push(format!("A store_quorum of {} rounds down to no replicas with a k_factor of {}, so stores \
+                                only wait for a single replica.", self.store_quorum, self.k_factor));
+      }
+      if self.republish_interval_s >= self.base_expiration_time_hrs * 60 * 60 {
+         warnings.push(format!("A republish_interval_s of {} lets entries expire before they're republished.", 
+                               self.republish_interval_s));
+      }
+      warnings
+   }
+
+   /// Number of replicas that must accept a store for it to succeed, derived from 
+   /// `store_quorum`. It's never zero unless `store_quorum` is zero.
+   pub fn store_quorum_replicas(&self) -> usize {
+      let fraction = self.store_quorum.max(0.0).min(1.0);
+      if fraction == 0.0 {
+         0
+      } else {
+         cmp::max(1, (self.k_factor as f64 * fraction) as usize)
+      }
+   }
 }
 
 impl Default for Configuration {
@@ -135,14 +413,34 @@ impl Default for Configuration {
       Configuration {
          alpha                         : 5,
          impatience                    : 2,
+         min_responses_to_advance      : 3,
          k_factor                      : 20,
+         max_total_contacts            : 0,
+         insert_self_in_table          : true,
          max_conflicts                 : 60,
          max_storage                   : 10000,
+         max_storage_bytes             : 10 * 1024 * 1024,
+         max_entries_per_key           : 1000,
          max_storage_blob_size         : 1024,
          expiration_distance_threshold : 3,
          base_expiration_time_hrs      : 24,
          base_cache_time_mins          : 30,
+         read_cache_ttl_s              : 60,
          network_timeout_s             : 5,
+         socket_read_timeout_ms        : 200,
+         max_waves_per_sec             : 0,
+         bucket_refresh_base_mins      : 60,
+         reject_foreign_stores         : false,
+         randomize_bucket_refresh      : false,
+         enable_defensive_mode         : true,
+         reconcile_interval_s          : 60,
+         ping_retries                  : 2,
+         maintenance_interval_s        : 5,
+         republish_interval_s          : 60 * 60,
+         wire_format                   : WireFormat::Bincode,
+         allowed_cidrs                 : Vec::new(),
+         region                        : None,
+         store_quorum                  : 1.0 / 3.0,
       }
    }
 }
@@ -156,18 +454,431 @@ impl Node {
    }
 
    /// Stores an entry in the network, refreshing its expiration time back to the base value.
-   pub fn store(&self, key: SubotaiHash, entry: StorageEntry) -> SubotaiResult<()> {
+   pub fn store<K: Into<StorageKey>>(&self, key: K, entry: StorageEntry) -> SubotaiResult<()> {
+      let expiration = time::now() + time::Duration::hours(self.resources.configuration.base_expiration_time_hrs);
+      self.resources.store(key.into().0, entry, expiration)
+   }
+
+   /// Stores an entry in the network for a custom amount of time, useful for short lived
+   /// entries. The expiration is still capped at `Configuration::base_expiration_time_hrs`.
+   pub fn store_with_expiration<K: Into<StorageKey>>(&self, key: K, entry: StorageEntry, expiration: time::Duration) -> SubotaiResult<()> {
+      let max_expiration = time::Duration::hours(self.resources.configuration.base_expiration_time_hrs);
+      let expiration = time::now() + cmp::min(expiration, max_expiration);
+      self.resources.store(key.into().0, entry, expiration)
+   }
+
+   /// Pushes the local entries under a key to its replicas right away, rather than
+   /// waiting for the hourly republish. Useful after many updates to a single key.
+   pub fn republish_key<K: Into<StorageKey>>(&self, key: K) -> SubotaiResult<()> {
+      self.resources.republish_key(key.into().0)
+   }
+
+   /// Stores an entry in the network, reporting how many storage nodes were contacted and
+   /// how many accepted it. Unlike `store`, missing the storage quorum isn't an error, so
+   /// applications can apply their own acceptability threshold.
+   pub fn store_detailed<K: Into<StorageKey>>(&self, key: K, entry: StorageEntry) -> SubotaiResult<StoreReport> {
+      let expiration = time::now() + time::Duration::hours(self.resources.configuration.base_expiration_time_hrs);
+      self.resources.store_detailed(key.into().0, entry, expiration)
+   }
+
+   /// Removes an entry from the local storage and from the nodes responsible for its key,
+   /// so it doesn't have to be left to expire. Removing an entry that isn't stored is not
+   /// an error. Note that cached copies in other nodes are left to expire on their own.
+   pub fn remove<K: Into<StorageKey>>(&self, key: K, entry: &StorageEntry) -> SubotaiResult<()> {
+      self.resources.remove(key.into().0, entry.clone())
+   }
+
+   /// Stores a blob of any size, by splitting it into chunks no bigger than 
+   /// `Configuration::max_storage_blob_size` stored under keys derived from the original
+   /// one. The original key holds a manifest entry describing the chunks. Manifests are
+   /// bounded by the same size, so very large blobs are described by nested manifests.
+   pub fn store_large<K: Into<StorageKey>>(&self, key: K, data: &[u8]) -> SubotaiResult<()> {
       let expiration = time::now() + time::Duration::hours(self.resources.configuration.base_expiration_time_hrs);
-      self.resources.store(key, entry, expiration)
+      self.resources.store_large(key.into().0, data, expiration)
+   }
+
+   /// Retrieves a blob stored with `store_large`, reassembling it from its chunks. Fails
+   /// with `MissingManifest` if there is no manifest under the key, or `MissingChunk` with
+   /// the index of the first chunk that couldn't be retrieved.
+   pub fn retrieve_large<K: Into<StorageKey>>(&self, key: K) -> SubotaiResult<Vec<u8>> {
+      self.resources.retrieve_large(&key.into().0)
+   }
+
+   /// Counts how many of the nodes responsible for a key actually hold an entry right
+   /// now. Unlike the acknowledgements in a `StoreReport`, this reflects entries that
+   /// may have since been evicted or expired, so it's a better measure of durability.
+   pub fn verify_replication<K: Into<StorageKey>>(&self, key: K, entry: &StorageEntry) -> SubotaiResult<usize> {
+      self.resources.verify_replication(key.into().0, entry.clone())
+   }
+
+   /// Stores an entry in the network only if the key holds no entries yet. Returns
+   /// whether the entry was stored. Useful for unique registrations, where only the
+   /// first of several competing nodes should succeed.
+   ///
+   /// The check is made by each replica independently, so two nodes racing for the
+   /// same key at the same time may both succeed on different replicas.
+   pub fn store_if_absent<K: Into<StorageKey>>(&self, key: K, entry: StorageEntry) -> SubotaiResult<bool> {
+      let expiration = time::now() + time::Duration::hours(self.resources.configuration.base_expiration_time_hrs);
+      self.resources.store_if_absent(key.into().0, entry, expiration)
+   }
+
+   /// Appends an entry to a key in the network only if the key holds fewer than `max_entries`
+   /// entries. Returns whether the entry was appended. Useful for bounded logs, which should
+   /// never grow beyond a fixed length.
+   ///
+   /// Like `store_if_absent`, the check is made by each replica independently, so concurrent
+   /// appends to the same key may briefly push some replicas past the cap.
+   pub fn append_bounded<K: Into<StorageKey>>(&self, key: K, entry: StorageEntry, max_entries: usize) -> SubotaiResult<bool> {
+      let expiration = time::now() + time::Duration::hours(self.resources.configuration.base_expiration_time_hrs);
+      self.resources.append_bounded(key.into().0, entry, expiration, max_entries)
+   }
+
+   /// Stores an entry in the network like `store`, additionally sending it to those nodes
+   /// in the placement hint that this node knows about. This lets applications keep data
+   /// close to the nodes interested in it, while the usual replicas still hold it.
+   pub fn store_with_hint<K: Into<StorageKey>>(&self, key: K, entry: StorageEntry, placement_hint: Vec<NodeId>) -> SubotaiResult<()> {
+      let expiration = time::now() + time::Duration::hours(self.resources.configuration.base_expiration_time_hrs);
+      self.resources.store_with_hint(key.into().0, entry, expiration, placement_hint)
+   }
+
+   /// Stores an entry in the network and registers its key in the index of a namespace,
+   /// so it can later be found through `retrieve_namespace`.
+   pub fn store_namespaced<K: Into<StorageKey>>(&self, namespace: &str, key: K, entry: StorageEntry) -> SubotaiResult<()> {
+      let key = key.into();
+      try!(self.store(&key, entry));
+      self.store(Node::namespace_index(namespace), StorageEntry::Value(key.0))
+   }
+
+   /// Retrieves all keys registered under a namespace through `store_namespaced`, along
+   /// with their entries. 
+   ///
+   /// This is best-effort: the index is just another key in the network, so index
+   /// entries expire independently of their members, members that can't be retrieved
+   /// are skipped, and concurrent stores to the namespace may or may not be reflected.
+   pub fn retrieve_namespace(&self, namespace: &str) -> SubotaiResult<Vec<(SubotaiHash, Vec<StorageEntry>)>> {
+      let index = try!(self.retrieve_authoritative(&Node::namespace_index(namespace)));
+      let members = index.into_iter().filter_map(|entry| match entry {
+         StorageEntry::Value(key) => Some(key),
+         _ => None,
+      });
+
+      Ok(members.filter_map(|key| self.retrieve(&key).ok().map(|entries| (key, entries))).collect())
    }
 
-   /// Retrieves all values associated to a key from the network.
-   pub fn retrieve(&self, key: &SubotaiHash) -> SubotaiResult<Vec<StorageEntry>> {
-      self.resources.retrieve(key)
+   /// Well-known key holding the index of a namespace.
+   fn namespace_index(namespace: &str) -> SubotaiHash {
+      SubotaiHash::sha1(&format!("subotai::namespace::{}", namespace))
    }
 
-   /// Returns the hash used to identify this node in the network.
-   pub fn id(&self) -> &SubotaiHash {
+   /// Stores several entries under the same key with a single RPC per storage node, 
+   /// refreshing their expiration time back to the base value. The report details
+   /// which storage nodes accepted all, some or none of the entries.
+   pub fn mass_store<K: Into<StorageKey>>(&self, key: K, entries: Vec<StorageEntry>) -> SubotaiResult<MassStoreReport> {
+      let expiration = time::now() + time::Duration::hours(self.resources.configuration.base_expiration_time_hrs);
+      self.resources.mass_store(key.into().0, entries.into_iter().map(|entry| (entry, expiration)).collect())
+   }
+
+   /// Boosts the replication of a key by storing its current entries in up to 
+   /// `extra_replicas` additional nodes, beyond those normally responsible for it.
+   /// Returns how many of those new replicas accepted the entries.
+   ///
+   /// The extra copies are treated like any other stored entries, so they will
+   /// eventually expire unless the key keeps being reinforced or republished.
+   pub fn reinforce<K: Into<StorageKey>>(&self, key: K, extra_replicas: usize) -> SubotaiResult<usize> {
+      let key = key.into();
+      let entries = try!(self.retrieve(&key));
+      let expiration = time::now() + time::Duration::hours(self.resources.configuration.base_expiration_time_hrs);
+      self.resources.reinforce(key.0, entries.into_iter().map(|entry| (entry, expiration)).collect(), extra_replicas)
+   }
+
+   /// Retrieves only the entries under a key that were first stored after a point in time,
+   /// allowing applications to fetch deltas of frequently updated keys. Each replica 
+   /// compares against its own clock, so the cutoff is only as precise as the clocks agree,
+   /// and nodes that received an entry late (e.g. when republished) consider it new.
+   pub fn retrieve_since<K: Into<StorageKey>>(&self, key: K, since: time::Tm) -> SubotaiResult<Vec<StorageEntry>> {
+      self.resources.retrieve_since(&key.into().0, since)
+   }
+
+   /// Like `retrieve`, but if the entries can't be found, reports the closest nodes to
+   /// the key that the search queried before giving up. Useful to see where a failed
+   /// lookup terminated.
+   pub fn retrieve_or_closest<K: Into<StorageKey>>(&self, key: K) -> SubotaiResult<RetrieveOutcome> {
+      self.resources.retrieve_or_closest(&key.into().0)
+   }
+
+   /// Retrieves all values associated to a key from the network. Fails early with
+   /// `OffGridError` if the node is off grid and doesn't hold the key locally.
+   pub fn retrieve<K: Into<StorageKey>>(&self, key: K) -> SubotaiResult<Vec<StorageEntry>> {
+      self.resources.retrieve(&key.into())
+   }
+
+   /// Retrieves all values associated to a key from the network, giving up at a particular
+   /// point in time. Passing the same deadline to several operations makes them share one
+   /// overall time budget.
+   pub fn retrieve_with_deadline<K: Into<StorageKey>>(&self, key: K, deadline: time::SteadyTime) -> SubotaiResult<Vec<StorageEntry>> {
+      self.resources.retrieve_with_deadline(&key.into(), deadline)
+   }
+
+   /// Stores an entry in the network like `store`, giving up at a particular point in time.
+   pub fn store_with_deadline<K: Into<StorageKey>>(&self, key: K, entry: StorageEntry, deadline: time::SteadyTime) -> SubotaiResult<()> {
+      let expiration = time::now() + time::Duration::hours(self.resources.configuration.base_expiration_time_hrs);
+      self.resources.store_with_deadline(key.into().0, entry, expiration, deadline)
+   }
+
+   /// Finds a node in the network by its ID, giving up at a particular point in time.
+   pub fn locate_with_deadline<I: Into<NodeId>>(&self, id: I, deadline: time::SteadyTime) -> SubotaiResult<NodeInfo> {
+      self.resources.locate_with_deadline(&id.into(), deadline)
+   }
+
+   /// Finds a node in the network by its ID, failing with `SubotaiError::Cancelled` shortly
+   /// after the `cancel` flag is set from another thread. Useful to abort lookups whose
+   /// result is no longer needed.
+   pub fn locate_cancellable<I: Into<NodeId>>(&self, id: I, cancel: sync::Arc<sync::atomic::AtomicBool>) -> SubotaiResult<NodeInfo> {
+      self.resources.locate_cancellable(&id.into(), cancel)
+   }
+
+   /// Retrieves all values associated to a key from the network, along with the time
+   /// at which each of them expires. Useful to refresh data before it disappears.
+   ///
+   /// Expiration times are reported by the node that provided the entries, so they
+   /// are only as accurate as its clock.
+   pub fn retrieve_with_expiry<K: Into<StorageKey>>(&self, key: K) -> SubotaiResult<Vec<(StorageEntry, time::Tm)>> {
+      self.resources.retrieve_with_expiry(&key.into())
+   }
+
+   /// Retrieves all values associated to a key, only succeeding once `quorum` of the
+   /// nodes responsible for it return the same values. Fails with `InconsistentReplicas`
+   /// if they disagree, or with `UnresponsiveNetwork` if too few of them respond.
+   pub fn retrieve_quorum<K: Into<StorageKey>>(&self, key: K, quorum: usize) -> SubotaiResult<Vec<StorageEntry>> {
+      self.resources.retrieve_quorum(&key.into(), quorum)
+   }
+
+   /// Retrieves all values associated to a key, only accepting them from the nodes 
+   /// responsible for storing that key. Unlike `retrieve`, this ignores any cached
+   /// copies, so it's slower but never returns stale data from a cache.
+   pub fn retrieve_authoritative<K: Into<StorageKey>>(&self, key: K) -> SubotaiResult<Vec<StorageEntry>> {
+      self.resources.retrieve_authoritative(&key.into())
+   }
+
+   /// Crate version this node was built with.
+   pub fn version(&self) -> &'static str {
+      CRATE_VERSION
+   }
+
+   /// Last crate version reported by each contact this node has exchanged pings with.
+   /// Versions are forgotten once the contact leaves the routing table.
+   pub fn peer_versions(&self) -> HashMap<NodeId, String> {
+      self.resources.peer_versions.lock().unwrap().clone()
+   }
+
+   /// Fraction of the recent liveness checks on a contact (pings, bucket pruning and
+   /// wave queries) it responded to, between 0.0 and 1.0. Only the last
+   /// `RELIABILITY_WINDOW` checks are taken into account. Returns `None` if the contact
+   /// hasn't been checked yet.
+   pub fn reliability<I: Into<NodeId>>(&self, id: I) -> Option<f64> {
+      self.resources.reliability(&id.into())
+   }
+
+   /// Rough estimate of the amount of nodes in the network, based on how densely
+   /// populated the hash space around this node is.
+   pub fn estimated_network_size(&self) -> usize {
+      self.resources.estimated_network_size()
+   }
+
+   /// Probes the network for the `k_factor` closest nodes to a target, with a search
+   /// depth chosen from the estimated network size. Small networks are queried less,
+   /// and large ones more, than with the fixed depth used for maintenance.
+   pub fn probe_auto(&self, target: &SubotaiHash) -> SubotaiResult<Vec<NodeInfo>> {
+      self.resources.probe(target, self.resources.auto_probe_depth())
+   }
+
+   /// Number of RPCs sent and received by this node so far, broken down by kind
+   /// (e.g. "Ping", "StoreResponse"). Kinds never sent or received are omitted.
+   pub fn rpc_stats(&self) -> HashMap<&'static str, (u64, u64)> {
+      self.resources.rpc_stats.lock().unwrap().clone()
+   }
+
+   /// Estimates how far ahead this node's clock is from those of its peers (negative
+   /// if behind), based on the send times included in incoming pings and stores. 
+   /// Incoming expiration times are corrected by each sender's offset before storing.
+   /// Returns `None` if no peer has been heard from yet.
+   pub fn clock_skew_estimate(&self) -> Option<time::Duration> {
+      self.resources.clock_skew_estimate()
+   }
+
+   /// Fraction of this node's storage capacity in use, between 0.0 and 1.0. Useful
+   /// to shed load before storage operations start failing.
+   pub fn storage_load(&self) -> f64 {
+      self.resources.storage.load_factor()
+   }
+
+   /// Aggregates the node state, connectivity and storage usage into a single snapshot.
+   /// It only reads local data, so it's cheap enough to be polled by monitoring tools.
+   pub fn health(&self) -> HealthReport {
+      let routing_len = self.resources.contact_count();
+      HealthReport {
+         state             : self.state(),
+         routing_len       : routing_len,
+         populated_buckets : self.resources.table.populated_buckets(),
+         storage_len       : self.resources.storage.len(),
+         storage_load      : self.storage_load(),
+         uptime            : time::SteadyTime::now() - self.resources.started_at,
+         is_well_connected : self.is_alive() && routing_len >= self.resources.configuration.k_factor,
+      }
+   }
+
+   /// Compares the storage of this node with that of a peer, and if they differ, exchanges
+   /// the entries under keys only one of them holds (as long as the receiving node is one
+   /// of the replicas for the key). Returns the number of entries reconciled.
+   ///
+   /// This is done periodically with random neighbors by the maintenance thread (see
+   /// `Configuration::reconcile_interval_s`), keeping replica sets consistent between
+   /// republishes.
+   pub fn reconcile_with(&self, peer: &NodeInfo) -> SubotaiResult<usize> {
+      self.resources.reconcile_with(peer)
+   }
+
+   /// Returns all entries this node holds as short lived cached copies of values it
+   /// retrieved, grouped by key.
+   pub fn cached_entries(&self) -> Vec<(SubotaiHash, Vec<(StorageEntry, time::Tm)>)> {
+      self.resources.storage.cached_entries()
+   }
+
+   /// Returns all entries this node holds because they were stored in it, as opposed
+   /// to cached copies, grouped by key.
+   pub fn authoritative_entries(&self) -> Vec<(SubotaiHash, Vec<(StorageEntry, time::Tm)>)> {
+      self.resources.storage.authoritative_entries()
+   }
+
+   /// Returns every key for which this node currently holds live entries, whether
+   /// stored in it or cached.
+   pub fn local_keys(&self) -> Vec<SubotaiHash> {
+      self.resources.storage.keys()
+   }
+
+   /// Hash summarizing all entries held by this node. Two nodes holding the same data
+   /// produce the same digest, regardless of the expiration times of their entries.
+   pub fn storage_digest(&self) -> SubotaiHash {
+      self.resources.storage.digest()
+   }
+
+   /// Retrieves all values associated to a key, preferably from the nodes responsible 
+   /// for it. If none of them can be reached, it falls back to a regular `retrieve`, 
+   /// which may return cached copies.
+   pub fn retrieve_preferring_authoritative<K: Into<StorageKey>>(&self, key: K) -> SubotaiResult<Vec<StorageEntry>> {
+      let key = key.into();
+      match self.retrieve_authoritative(&key) {
+         Err(SubotaiError::UnresponsiveNetwork) | Err(SubotaiError::NoResponse) => self.retrieve(&key),
+         result => result,
+      }
+   }
+
+   /// Finds the stored key closest to a target in the target's neighbourhood, along with 
+   /// the node that holds it. This is best-effort: only the nodes closest to the target 
+   /// are asked, so a closer key may exist elsewhere if the network is sparsely populated 
+   /// around the target, and unresponsive nodes are ignored.
+   pub fn nearest_stored_key(&self, target: &SubotaiHash) -> SubotaiResult<Option<(NodeInfo, SubotaiHash)>> {
+      self.resources.nearest_stored_key(target)
+   }
+
+   /// Returns the locally stored keys this node is a primary replica for (that is,
+   /// it ranks among the `k_factor` closest nodes to the key), as opposed to keys
+   /// it merely caches. Every key is probed in the network, so this may take a while.
+   pub fn primary_keys(&self) -> SubotaiResult<Vec<SubotaiHash>> {
+      self.resources.primary_keys()
+   }
+
+   /// Republishes all locally stored entries to the network, blocking until each
+   /// of them has been acknowledged by enough storage nodes. Call this before 
+   /// dropping a node to make sure no data is lost with it.
+   pub fn flush(&self) -> SubotaiResult<()> {
+      self.resources.flush()
+   }
+
+   /// Returns copies of the contacts in a particular routing table bucket. Bucket
+   /// `i` holds the nodes whose distance to this node has its highest bit at `i`.
+   pub fn contacts_in_bucket(&self, index: usize) -> SubotaiResult<Vec<NodeInfo>> {
+      if index >= HASH_SIZE {
+         return Err(SubotaiError::OutOfBounds);
+      }
+      Ok(self.resources.table.nodes_from_bucket(index))
+   }
+
+   /// Returns copies of all contacts in the routing table, excluding this node.
+   pub fn contacts(&self) -> Vec<NodeInfo> {
+      self.resources.table.all_nodes().filter(|info| info.id != self.resources.id).collect()
+   }
+
+   /// Introduces a known contact into the routing table, as if it had just been seen
+   /// on the network. Useful to seed a node's routing state in tests or deployments.
+   pub fn add_contact(&self, info: NodeInfo) -> ContactUpdate {
+      self.resources.update_table(info)
+   }
+
+   /// Number of datagrams dropped on arrival for coming from outside
+   /// `Configuration::allowed_cidrs`.
+   pub fn dropped_datagrams(&self) -> usize {
+      self.resources.dropped_datagrams.load(sync::atomic::Ordering::SeqCst)
+   }
+
+   /// Returns up to `n` nodes from the local routing table, in ascending distance to
+   /// `target`. No RPCs are sent, so this only reflects what this node currently knows.
+   pub fn close_nodes(&self, target: &SubotaiHash, n: usize) -> Vec<NodeInfo> {
+      self.resources.table.closest_nodes_to(target).take(n).collect()
+   }
+
+   /// Whether a node with the given ID would make it into the routing table without
+   /// evicting any other contact. Comparing the answers of two nodes about each other
+   /// reveals routing asymmetries, useful to diagnose partial connectivity.
+   pub fn would_store<I: Into<NodeId>>(&self, other: I) -> bool {
+      self.resources.table.would_store(&other.into())
+   }
+
+   /// Index of the most distant bucket holding a contact, or `None` if the routing
+   /// table holds none. A table populated only in low buckets covers the distant 
+   /// keyspace poorly.
+   pub fn farthest_bucket(&self) -> Option<usize> {
+      self.resources.table.farthest_contact_bucket()
+   }
+
+   /// Routing table metrics, such as its size and how its nodes are distributed
+   /// among buckets. Safe to call at any time while the node runs.
+   pub fn table_info(&self) -> TableInfo {
+      let stats = self.resources.table.bucket_stats();
+      let fullest_bucket = stats.iter()
+         .enumerate()
+         .filter(|&(_, &occupancy)| occupancy > 0)
+         .max_by_key(|&(_, &occupancy)| occupancy)
+         .map(|(index, _)| index);
+
+      TableInfo {
+         len                  : stats.iter().sum(),
+         populated_buckets    : stats.iter().filter(|&&occupancy| occupancy > 0).count(),
+         fullest_bucket       : fullest_bucket,
+         oldest_probed_bucket : self.resources.table.oldest_bucket().0,
+      }
+   }
+
+   /// Time since the least recently probed bucket of the routing table was last probed,
+   /// or `None` if some bucket was never probed. Useful to confirm the maintenance thread
+   /// is keeping up with the refresh intervals.
+   pub fn oldest_bucket_age(&self) -> Option<time::Duration> {
+      match self.resources.table.oldest_bucket() {
+         (_, Some(last_probe)) => Some(time::SteadyTime::now() - last_probe),
+         (_, None) => None,
+      }
+   }
+
+   /// Enables or disables read-only mode. A read-only node keeps serving retrieves and
+   /// taking part in lookups, but rejects incoming stores with `StoreResult::ReadOnly`
+   /// and stops republishing its entries. Useful to drain a node before shutting it down.
+   pub fn set_read_only(&self, enabled: bool) {
+      self.resources.read_only.store(enabled, sync::atomic::Ordering::SeqCst);
+   }
+
+   /// Returns the ID used to identify this node in the network. As a `NodeId`, it
+   /// can't be passed by mistake where a storage key is expected.
+   pub fn id(&self) -> &NodeId {
       &self.resources.id
    }
 
@@ -181,29 +892,156 @@ impl Node {
       self.resources.state()
    }
 
+   /// Shuts the node down, blocking until all of its threads have exited. Returns true if
+   /// they did, in which case the node's sockets are closed and its ports are guaranteed
+   /// to be free once this returns.
+   ///
+   /// Short lived threads working on behalf of the node (e.g. those handling incoming
+   /// RPCs or a background bootstrap) are given at most a network timeout to finish. 
+   /// If any is still running past that, this returns false, and the ports are only
+   /// freed once it's done.
+   ///
+   /// Dropping a node shuts it down as well, but without waiting for its threads.
+   pub fn shutdown(mut self) -> bool {
+      self.resources.set_state(State::ShuttingDown);
+      for thread in self.threads.drain(..) {
+         let _ = thread.join();
+      }
+
+      let timeout = StdDuration::from_secs(self.resources.configuration.network_timeout_s as u64);
+      self.resources.detached.wait(timeout)
+   }
+
+   /// Produces an iterator that yields a notification every time this node receives a
+   /// store RPC for a particular key. Only stores routed through this node are observed, so
+   /// this is most useful when the node is one of the replicas for the key.
+   pub fn watch<K: Into<StorageKey>>(&self, key: K) -> receptions::KeyWatcher {
+      receptions::KeyWatcher::new(&self.resources, key.into().0)
+   }
+
+   /// Produces an iterator over the network updates of this node, such as nodes joining
+   /// its routing table or entries stored in it by other nodes. The iterator will block
+   /// indefinitely, unless restricted to a span of time with `during`.
+   ///
+   /// Updates about stored and removed entries are best effort: they're dropped if the
+   /// iterator falls too far behind.
+   pub fn watch_network(&self) -> receptions::NetworkUpdates {
+      receptions::NetworkUpdates::new(&self.resources)
+   }
+
+   /// Produces an iterator over the state changes of this node, such as going on grid
+   /// after a bootstrap. The iterator will block indefinitely, unless restricted to a
+   /// span of time with `during`.
+   pub fn watch_state(&self) -> receptions::StateUpdates {
+      receptions::StateUpdates::new(&self.resources)
+   }
+
    /// Produces an iterator over RPCs received by this node. The iterator will block
    /// indefinitely.
    pub fn receptions(&self) -> receptions::Receptions {
       self.resources.receptions()
    }
 
+   /// Whether the node is part of a live network, either on grid or in defensive mode.
+   fn is_alive(&self) -> bool {
+      match self.state() {
+         State::OnGrid | State::Defensive => true,
+         _ => false,
+      }
+   }
+
+   /// Pings a node, waiting at most `timeout` for its response. Useful for quick
+   /// liveness checks with a shorter deadline than the configured network timeout.
+   pub fn ping_with_timeout(&self, target: &NodeInfo, timeout: time::Duration) -> SubotaiResult<()> {
+      self.resources.ping_with_timeout(&target.address, timeout)
+   }
+
    /// Bootstraps the node from a seed IP:Port pair. Returns Ok(()) if the seed has
    /// been reached and the asynchronous bootstrap process has started. However, it 
    /// might take a bit for the node to become alive (use node::wait_until_state to 
    /// block until it's alive, if necessary).
+   ///
+   /// Bootstrapping a node that is already alive (on grid or in defensive mode) does
+   /// nothing, so it's safe to call defensively.
    pub fn bootstrap(&self, seed: &net::SocketAddr) -> SubotaiResult<()> {
-      try!(self.resources.ping(seed));
-      let bootstrap_resources = self.resources.clone();
+      self.bootstrap_from(&[*seed])
+   }
+
+   /// Bootstraps the node from several seeds at once, which makes bootstrapping resilient
+   /// to some of them being down. The asynchronous bootstrap process starts as soon as any
+   /// of them responds, and an error is only returned if none of them does.
+   pub fn bootstrap_multi(&self, seeds: &[NodeInfo]) -> SubotaiResult<()> {
+      let addresses: Vec<_> = seeds.iter().map(|seed| seed.address).collect();
+      self.bootstrap_from(&addresses)
+   }
+
+   fn bootstrap_from(&self, seeds: &[net::SocketAddr]) -> SubotaiResult<()> {
+      if self.is_alive() {
+         return Ok(());
+      }
+      try!(self.resources.ping_any(seeds));
+      Node::spawn_detached(&self.resources, |resources| {
+         let _ = Node::probe_self(&resources);
+      });
+      Ok(())
+   }
+
+   /// Runs an operation on the node's resources in a short lived thread, which 
+   /// `shutdown` waits for before returning.
+   fn spawn_detached<F>(resources: &sync::Arc<resources::Resources>, f: F)
+      where F: FnOnce(sync::Arc<resources::Resources>) + Send + 'static
+   {
+      let running = resources::DetachedThreads::start(&resources.detached);
+      let resources = resources.clone();
       thread::spawn(move || {
-         for _ in 0..BOOTSTRAP_TRIES {
-            if let Ok(_) = bootstrap_resources.probe(&bootstrap_resources.id, bootstrap_resources.configuration.k_factor) {
-               break;
-            }
-         }
-       });
+         // The resources are released before the guard, so the thread stops counting
+         // as running only once it no longer holds the sockets.
+         let _running = running;
+         f(resources)
+      });
+   }
+
+   /// Bootstraps the node from a seed IP:Port pair, blocking until the whole bootstrap 
+   /// process has finished. Unlike `bootstrap`, the node will have learned about its 
+   /// neighbourhood by the time this returns, which makes it convenient for tests.
+   pub fn bootstrap_sync(&self, seed: &net::SocketAddr) -> SubotaiResult<()> {
+      if self.is_alive() {
+         return Ok(());
+      }
+      try!(self.resources.ping(seed));
+      let closest = try!(Node::probe_self(&self.resources));
+
+      // The probe may finish before hearing from every close node, so we ping
+      // the ones we don't know about yet and wait for them to introduce themselves.
+      let unknown: Vec<_> = closest
+         .into_iter()
+         .filter(|info| self.resources.table.specific_node(&info.id).is_none())
+         .collect();
+      let responses = self.resources.receptions()
+         .of_kind(receptions::KindFilter::PingResponse)
+         .from_senders(unknown.iter().map(|info| info.id.clone()).collect())
+         .during(time::Duration::seconds(self.resources.configuration.network_timeout_s))
+         .take(unknown.len());
+
+      for info in &unknown {
+         let _ = self.resources.ping_and_forget(&info.address);
+      }
+      responses.count();
       Ok(())
    }
 
+   /// Probes the network for the node's own ID, which is the core of the bootstrap process.
+   fn probe_self(resources: &resources::Resources) -> SubotaiResult<Vec<routing::NodeInfo>> {
+      let mut result = Err(SubotaiError::UnresponsiveNetwork);
+      for _ in 0..BOOTSTRAP_TRIES {
+         result = resources.probe(&resources.id, resources.configuration.k_factor);
+         if result.is_ok() {
+            break;
+         }
+      }
+      result
+   }
+
    /// Returns if the node is already in the specified state, otherwise blocks indefinitely until
    /// that state is reached.
    pub fn wait_for_state(&self, state: State) {
@@ -220,45 +1058,127 @@ impl Node {
       }
    }
 
+   /// Runs a closure in a separate thread as soon as the node goes on grid, or right away
+   /// if it's already on grid. The closure runs at most once, and never if the node shuts 
+   /// down before reaching the network.
+   pub fn on_first_online<F: FnOnce() + Send + 'static>(&self, f: F) {
+      let updates = self.resources.state_updates.lock().unwrap().add_rx().into_iter();
+      let online = self.state() == State::OnGrid;
+
+      thread::spawn(move || {
+         if online {
+            return f();
+         }
+
+         for update in updates {
+            match update {
+               resources::StateUpdate::StateChange(State::OnGrid) => return f(),
+               resources::StateUpdate::StateChange(State::ShuttingDown) => return,
+               _ => (),
+            }
+         }
+      });
+   }
+
    /// Retrieves the node ID + address pair.
    pub fn local_info(&self) -> NodeInfo {
       self.resources.local_info()
    }
 
+   /// Produces a seed string (`hex_id@ip:port`) for this node, which other nodes
+   /// can parse through `NodeInfo::from_seed_string`. Note that the address is the
+   /// one the node is bound to, so you may have to replace the IP if it's unspecified.
+   pub fn seed_string(&self) -> String {
+      self.resources.local_info().to_seed_string()
+   }
+
+   /// Serializes the node identity, routing table and storage into a single blob, 
+   /// which can be used to restore the node elsewhere through `Factory::from_state`.
+   pub fn export_state(&self) -> SubotaiResult<Vec<u8>> {
+      let state = NodeState {
+         id       : self.resources.id.clone(),
+         contacts : self.contacts(),
+         entries  : self.resources.storage.get_all_entries()
+            .into_iter()
+            .map(|(key, entries)| (key, entries.into_iter().map(|(entry, expiration)| (entry, rpc::SerializableTime::from(expiration))).collect()))
+            .collect(),
+      };
+      Ok(try!(serde::serialize(&state, bincode::SizeLimit::Infinite)))
+   }
+
    fn with_configuration(inbound_port: u16, outbound_port: u16, configuration: Configuration) -> SubotaiResult<Node> {
-      let id = SubotaiHash::random();
+      let unspecified = net::IpAddr::V4(net::Ipv4Addr::new(0, 0, 0, 0));
+      Node::with_state(unspecified, inbound_port, outbound_port, configuration, None)
+   }
+
+   fn with_state(bind_address: net::IpAddr,
+                 inbound_port: u16,
+                 outbound_port: u16,
+                 configuration: Configuration,
+                 state: Option<NodeState>) -> SubotaiResult<Node> {
+      let id = match state {
+         Some(ref state) => state.id.clone(),
+         None => NodeId(SubotaiHash::random()),
+      };
       
       let resources = sync::Arc::new(resources::Resources {
          id                : id.clone(),
          table             : routing::Table::new(id.clone(), configuration.clone()),
-         storage           : storage::Storage::new(id, configuration.clone()),
-         inbound           : try!(net::UdpSocket::bind(("0.0.0.0", inbound_port))),
-         outbound          : try!(net::UdpSocket::bind(("0.0.0.0", outbound_port))),
+         storage           : storage::Storage::new(id.0, configuration.clone()),
+         inbound           : try!(net::UdpSocket::bind((bind_address, inbound_port))),
+         outbound          : try!(net::UdpSocket::bind((bind_address, outbound_port))),
          state             : sync::RwLock::new(State::OffGrid),
          reception_updates : sync::Mutex::new(bus::Bus::new(UPDATE_BUS_SIZE_BYTES)),
          network_updates   : sync::Mutex::new(bus::Bus::new(UPDATE_BUS_SIZE_BYTES)),
          state_updates     : sync::Mutex::new(bus::Bus::new(UPDATE_BUS_SIZE_BYTES)),
          conflicts         : sync::Mutex::new(Vec::with_capacity(configuration.max_conflicts)),
+         recent_waves      : sync::Mutex::new(VecDeque::with_capacity(configuration.max_waves_per_sec)),
+         peer_versions     : sync::Mutex::new(HashMap::new()),
+         clock_offsets     : sync::Mutex::new(HashMap::new()),
+         liveness          : sync::Mutex::new(HashMap::new()),
+         read_only         : sync::atomic::AtomicBool::new(false),
+         rpc_stats         : sync::Mutex::new(HashMap::new()),
+         codec             : rpc::codec::for_format(configuration.wire_format),
+         started_at        : time::SteadyTime::now(),
+         dropped_datagrams : sync::atomic::AtomicUsize::new(0),
+         detached          : sync::Arc::new(resources::DetachedThreads::new()),
          configuration     : configuration,
       });
 
-      resources.table.update_node(resources.local_info());
+      if resources.configuration.insert_self_in_table {
+         resources.table.update_node(resources.local_info());
+      }
 
-      try!(resources.inbound.set_read_timeout(Some(StdDuration::from_millis(SOCKET_TIMEOUT_MS))));
+      // A restored node warm-starts with its former contacts and unexpired entries.
+      if let Some(state) = state {
+         for contact in state.contacts {
+            resources.table.update_node(contact);
+         }
+
+         resources.storage.merge(state.entries
+            .into_iter()
+            .map(|(key, entries)| (key, entries.into_iter().map(|(entry, expiration)| (entry, time::Tm::from(expiration))).collect()))
+            .collect());
+      }
 
+      try!(resources.inbound.set_read_timeout(Some(StdDuration::from_millis(resources.configuration.socket_read_timeout_ms))));
+
+      let mut threads = Vec::new();
       let reception_resources = resources.clone();
-      thread::spawn(move || { Node::reception_loop(reception_resources) });
+      threads.push(thread::spawn(move || { Node::reception_loop(reception_resources) }));
 
       let conflict_resolution_resources = resources.clone();
-      thread::spawn(move || { Node::conflict_resolution_loop(conflict_resolution_resources) });
+      threads.push(thread::spawn(move || { Node::conflict_resolution_loop(conflict_resolution_resources) }));
 
       let maintenance_resources = resources.clone();
-      thread::spawn(move || { Node::maintenance_loop(maintenance_resources) });
+      threads.push(thread::spawn(move || { Node::maintenance_loop(maintenance_resources) }));
 
+      // The loop subscribes to updates before it starts, so it can't miss an early shutdown.
       let republish_resources = resources.clone();
-      thread::spawn(move || { Node::republish_loop(republish_resources) });
+      let republish_updates = resources.network_updates.lock().unwrap().add_rx();
+      threads.push(thread::spawn(move || { Node::republish_loop(republish_resources, republish_updates) }));
 
-      Ok( Node{ resources: resources } )
+      Ok( Node{ resources: resources, threads: threads } )
    }
 
    /// Receives and processes data as long as the node is alive.
@@ -271,10 +1191,11 @@ impl Node {
             break;
          }
 
-         if let Ok((_, source)) = message {
-            if let Ok(rpc) = rpc::Rpc::deserialize(&buffer) {
-               let resources_clone = resources.clone();
-               thread::spawn(move || { resources_clone.process_incoming_rpc(rpc, source) } );
+         if let Ok((size, source)) = message {
+            if !resources.accepts_traffic_from(&source) {
+               resources.dropped_datagrams.fetch_add(1, sync::atomic::Ordering::SeqCst);
+            } else if let Ok(rpc) = resources.codec.decode(&buffer[..size]) {
+               Node::spawn_detached(&resources, move |resources| { let _ = resources.process_incoming_rpc(rpc, source); } );
             }
          }
 
@@ -285,14 +1206,11 @@ impl Node {
    /// Wakes up when a new node is introduced to the network, and sends mass store RPCs
    /// with those entries which are closer to it than they are to this node.
    #[allow(unused_must_use)]
-   fn republish_loop(resources: sync::Arc<resources::Resources>) {
-      let updates = {
-         resources.network_updates.lock().unwrap().add_rx().into_iter()
-      };
-
+   fn republish_loop(resources: sync::Arc<resources::Resources>, updates: bus::BusReader<resources::NetworkUpdate>) {
       for update in updates {
          match update {
             resources::NetworkUpdate::StateChange(State::ShuttingDown) => { break; },
+            resources::NetworkUpdate::AddedNode(_) if resources.is_read_only() => (),
             resources::NetworkUpdate::AddedNode(info) => {
                let keygroups = resources.storage.get_entries_closer_to(&info.id);
                for keygroup in keygroups {
@@ -304,33 +1222,50 @@ impl Node {
       }
    }
 
-   /// Wakes up every `MAINTENANCE_SLEEP_S` seconds and refreshes the oldest bucket,
-   /// unless they are all younger than 1 hour, in which case it goes back to sleep.
+   /// Wakes up every `Configuration::maintenance_interval_s` seconds and refreshes the most
+   /// overdue bucket, if any. Closer buckets are due more often than distant ones (see 
+   /// `Configuration::bucket_refresh_base_mins`).
    ///
-   /// This loop also republishes all entries each hour, provided we haven't received
-   /// a `store` rpc for said entry in the past hour.
+   /// This loop also republishes all entries every `Configuration::republish_interval_s`,
+   /// provided we haven't received a `store` rpc for said entry since the last republish.
    #[allow(unused_must_use)]
    fn maintenance_loop(resources: sync::Arc<resources::Resources>) {
-      let hour = time::Duration::hours(1);
+      let maintenance_interval = time::Duration::seconds(resources.configuration.maintenance_interval_s);
+      let republish_interval = time::Duration::seconds(resources.configuration.republish_interval_s);
+      let reconcile_interval = time::Duration::seconds(resources.configuration.reconcile_interval_s);
       let mut last_republish = time::SteadyTime::now();
+      let mut last_reconcile = time::SteadyTime::now();
 
       loop {
-         thread::sleep(StdDuration::new(MAINTENANCE_SLEEP_S,0));
+         // The sleep is done in short steps, so a shutdown doesn't have to wait for it.
+         let wake_up = time::SteadyTime::now() + maintenance_interval;
+         while time::SteadyTime::now() < wake_up && resources.state() != State::ShuttingDown {
+            thread::sleep(StdDuration::from_millis(100));
+         }
          if let State::ShuttingDown = resources.state() {
             break;
          }
 
          let now = time::SteadyTime::now();
-         // If the oldest bucket was refreshed more than a hour ago,
-         // or it was never refreshed, prune and refresh it.
-         match resources.table.oldest_bucket() {
-            (i, None) => {resources.refresh_bucket(i);},
-            (i, Some(time)) if (now - time) > hour => {resources.refresh_bucket(i);},
-            _ => (),
+         // If a bucket was never refreshed, or hasn't been for longer than
+         // its refresh interval, prune and refresh it.
+         let base_interval = time::Duration::minutes(resources.configuration.bucket_refresh_base_mins);
+         let due_bucket = if resources.configuration.randomize_bucket_refresh {
+            resources.table.random_bucket_due_for_refresh(base_interval)
+         } else {
+            resources.table.bucket_due_for_refresh(base_interval)
+         };
+         if let Some(i) = due_bucket {
+            resources.refresh_bucket(i);
+         }
+
+         if resources.configuration.reconcile_interval_s > 0 && now - last_reconcile > reconcile_interval {
+            resources.reconcile_with_random_neighbor();
+            last_reconcile = time::SteadyTime::now();
          }
         
-         // Republish all entries that haven't entered storage in the last hour.
-         if now - last_republish > hour {
+         // Republish all entries that haven't entered storage since the last republish.
+         if now - last_republish > republish_interval && !resources.is_read_only() {
             let ready_entries = resources.storage.get_all_ready_entries();
             for keygroup in ready_entries {
                resources.mass_store(keygroup.0, keygroup.1);
@@ -346,11 +1281,18 @@ impl Node {
    /// conflict, and disposes of conflicts that haven't been resolved.
    #[allow(unused_must_use)]
    fn conflict_resolution_loop(resources: sync::Arc<resources::Resources>) {
+      if !resources.configuration.enable_defensive_mode {
+         return;
+      }
+
       loop {
          let conflicts_empty = { // Lock scope
             let mut conflicts = resources.conflicts.lock().unwrap();
             // Conflicts that weren't solved in five pings are removed.
             // This means the incoming node that caused the conflict has priority.
+            for conflict in conflicts.iter().filter(|conflict| conflict.times_pinged >= 5) {
+               resources.forget_peer(&conflict.evicted.id);
+            }
             conflicts.retain(|&routing::EvictionConflict{times_pinged, ..}| times_pinged < 5);
 
             // We ping the evicted nodes for all conflicts that remain.
@@ -368,7 +1310,7 @@ impl Node {
             State::ShuttingDown => break,
             // If all conflicts are resolved, we leave defensive mode.
             State::Defensive if conflicts_empty => { 
-               if resources.table.len() > resources.configuration.k_factor { 
+               if resources.contact_count() >= resources.configuration.k_factor { 
                      resources.set_state(State::OnGrid);
                   } else {
                      resources.set_state(State::OffGrid);
@@ -1,6 +1,8 @@
-use {bus, rpc, time, node};
+use {bus, rpc, time, node, storage, routing};
 use node::resources;
-use hash::SubotaiHash;
+use hash::{SubotaiHash, NodeId};
+use std::sync;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 /// Iterator over all RPCs received by a node. 
 ///
@@ -11,10 +13,11 @@ use hash::SubotaiHash;
 /// It is also possible to filter the iterator so it only applies to particular
 /// senders or RPC kinds without resorting to iterator adapters.
 pub struct Receptions {
-   iter          : bus::BusIntoIter<resources::ReceptionUpdate>,
+   reader        : bus::BusReader<resources::ReceptionUpdate>,
    timeout       : Option<time::SteadyTime>,
-   kind_filter   : Option<KindFilter>,
-   sender_filter : Option<Vec<SubotaiHash>>,
+   kind_filter   : Option<Vec<KindFilter>>,
+   sender_filter : Option<Vec<NodeId>>,
+   cancel        : Option<sync::Arc<AtomicBool>>,
    shutdown      : bool,
 }
 
@@ -32,6 +35,14 @@ pub enum KindFilter {
    RetrieveResponse,
    Probe,
    ProbeResponse,
+   NearestKey,
+   NearestKeyResponse,
+   Digest,
+   DigestResponse,
+   Remove,
+   RemoveResponse,
+   HasEntry,
+   HasEntryResponse,
 }
 
 impl resources::Resources {
@@ -43,10 +54,11 @@ impl resources::Resources {
 impl Receptions {
    fn new(resources: &resources::Resources) -> Receptions {
       Receptions {
-         iter          : resources.reception_updates.lock().unwrap().add_rx().into_iter(),
+         reader        : resources.reception_updates.lock().unwrap().add_rx(),
          timeout       : None,
          kind_filter   : None,
          sender_filter : None,
+         cancel        : None,
          shutdown      : false,
       }
    }
@@ -57,67 +69,272 @@ impl Receptions {
       self
    }
 
+   /// Restricts the iterator to end at a particular point in time, or earlier if 
+   /// it was already restricted to a shorter span.
+   pub fn until(mut self, deadline: time::SteadyTime) -> Receptions {
+      self.timeout = match self.timeout {
+         Some(timeout) if timeout < deadline => Some(timeout),
+         _ => Some(deadline),
+      };
+      self
+   }
+
+   /// Ends the iterator as soon as a flag is set, checked on every socket read timeout.
+   pub fn cancelled_by(mut self, cancel: sync::Arc<AtomicBool>) -> Receptions {
+      self.cancel = Some(cancel);
+      self
+   }
+
    /// Only produces a particular rpc kind.
-   pub fn of_kind(mut self, filter: KindFilter) -> Receptions {
-      self.kind_filter = Some(filter);
+   pub fn of_kind(self, filter: KindFilter) -> Receptions {
+      self.of_kinds(vec![filter])
+   }
+
+   /// Only produces rpcs of any kind within a set.
+   pub fn of_kinds(mut self, filters: Vec<KindFilter>) -> Receptions {
+      self.kind_filter = Some(filters);
       self
    }
 
    /// Only from a sender.
-   pub fn from(mut self, sender: SubotaiHash) -> Receptions {
+   pub fn from(mut self, sender: NodeId) -> Receptions {
       self.sender_filter = Some(vec![sender]);
       self
    }
 
    /// Only from a set of senders.
-   pub fn from_senders(mut self, senders: Vec<SubotaiHash>) -> Receptions {
+   pub fn from_senders(mut self, senders: Vec<NodeId>) -> Receptions {
       self.sender_filter = Some(senders);
       self
    }
 }
 
+impl Receptions {
+   /// Returns the next RPC if one has already arrived, without waiting for it.
+   pub fn try_next(&mut self) -> Option<rpc::Rpc> {
+      while !self.is_over() {
+         match self.reader.try_recv() {
+            Ok(update) => if let Some(rpc) = self.accept(update) { return Some(rpc); },
+            Err(_) => break,
+         }
+      }
+      None
+   }
+
+   fn is_over(&self) -> bool {
+      if self.cancel.as_ref().map_or(false, |cancel| cancel.load(Ordering::SeqCst)) {
+         return true;
+      }
+      match self.timeout {
+         Some(timeout) if time::SteadyTime::now() > timeout => true,
+         _ => self.shutdown,
+      }
+   }
+
+   /// Produces the RPC carried by an update, if it passes the filters.
+   fn accept(&mut self, update: resources::ReceptionUpdate) -> Option<rpc::Rpc> {
+      match update {
+         resources::ReceptionUpdate::RpcReceived(rpc) => {
+            if let Some(ref kind_filter) = self.kind_filter {
+               let kind = match rpc.kind {
+                  rpc::Kind::Ping(_)              => KindFilter::Ping,
+                  rpc::Kind::PingResponse(_)      => KindFilter::PingResponse,
+                  rpc::Kind::Store(_)             => KindFilter::Store,
+                  rpc::Kind::MassStore(_)         => KindFilter::MassStore,
+                  rpc::Kind::StoreResponse(_)     => KindFilter::StoreResponse,
+                  rpc::Kind::Locate(_)            => KindFilter::Locate,
+                  rpc::Kind::LocateResponse(_)    => KindFilter::LocateResponse,
+                  rpc::Kind::Retrieve(_)          => KindFilter::Retrieve,
+                  rpc::Kind::RetrieveResponse(_)  => KindFilter::RetrieveResponse,
+                  rpc::Kind::Probe(_)             => KindFilter::Probe,
+                  rpc::Kind::ProbeResponse(_)     => KindFilter::ProbeResponse,
+                  rpc::Kind::NearestKey(_)        => KindFilter::NearestKey,
+                  rpc::Kind::NearestKeyResponse(_) => KindFilter::NearestKeyResponse,
+                  rpc::Kind::Digest(_)            => KindFilter::Digest,
+                  rpc::Kind::DigestResponse(_)    => KindFilter::DigestResponse,
+                  rpc::Kind::Remove(_)            => KindFilter::Remove,
+                  rpc::Kind::RemoveResponse(_)    => KindFilter::RemoveResponse,
+                  rpc::Kind::HasEntry(_)          => KindFilter::HasEntry,
+                  rpc::Kind::HasEntryResponse(_)  => KindFilter::HasEntryResponse,
+               };
+               if !kind_filter.contains(&kind) {
+                  return None;
+               }
+            }
+
+            if let Some(ref sender_filter) = self.sender_filter {
+               if !sender_filter.contains(&rpc.sender.id) {
+                  return None;
+               }
+            }
+
+            Some(rpc)
+         },
+         resources::ReceptionUpdate::StateChange(node::State::ShuttingDown) => { self.shutdown = true; None },
+         _ => None,
+      }
+   }
+}
+
 impl Iterator for Receptions {
    type Item = rpc::Rpc;
 
    fn next(&mut self) -> Option<rpc::Rpc> {
-      loop {
-         if let Some(timeout) = self.timeout {
-            if time::SteadyTime::now() > timeout {
-               break;
+      while !self.is_over() {
+         if let Ok(update) = self.reader.recv() {
+            if let Some(rpc) = self.accept(update) {
+               return Some(rpc);
             }
          }
-         if self.shutdown {
-            break;
+      }
+      None
+   }
+}
+
+/// Iterator over the network updates of a node: nodes joining its routing table,
+/// changes of state, and entries stored in or removed from it by other nodes.
+///
+/// Like `Receptions`, it blocks indefinitely unless a lifespan is given, and ends 
+/// after reporting that the node is shutting down.
+pub struct NetworkUpdates {
+   reader   : bus::BusReader<resources::NetworkUpdate>,
+   timeout  : Option<time::SteadyTime>,
+   shutdown : bool,
+}
+
+impl NetworkUpdates {
+   pub fn new(resources: &resources::Resources) -> NetworkUpdates {
+      NetworkUpdates {
+         reader   : resources.network_updates.lock().unwrap().add_rx(),
+         timeout  : None,
+         shutdown : false,
+      }
+   }
+
+   /// Restricts the iterator to a particular span of time.
+   pub fn during(mut self, lifespan: time::Duration) -> NetworkUpdates {
+      self.timeout = Some(time::SteadyTime::now() + lifespan);
+      self
+   }
+}
+
+impl Iterator for NetworkUpdates {
+   type Item = resources::NetworkUpdate;
+
+   fn next(&mut self) -> Option<resources::NetworkUpdate> {
+      if self.shutdown {
+         return None;
+      }
+
+      let update = receive_until(&mut self.reader, self.timeout);
+      if let Some(resources::NetworkUpdate::StateChange(node::State::ShuttingDown)) = update {
+         self.shutdown = true;
+      }
+      update
+   }
+}
+
+/// Iterator over the state changes of a node. Like `Receptions`, it blocks 
+/// indefinitely unless a lifespan is given, and ends after reporting that 
+/// the node is shutting down.
+pub struct StateUpdates {
+   reader   : bus::BusReader<resources::StateUpdate>,
+   timeout  : Option<time::SteadyTime>,
+   shutdown : bool,
+}
+
+impl StateUpdates {
+   pub fn new(resources: &resources::Resources) -> StateUpdates {
+      StateUpdates {
+         reader   : resources.state_updates.lock().unwrap().add_rx(),
+         timeout  : None,
+         shutdown : false,
+      }
+   }
+
+   /// Restricts the iterator to a particular span of time.
+   pub fn during(mut self, lifespan: time::Duration) -> StateUpdates {
+      self.timeout = Some(time::SteadyTime::now() + lifespan);
+      self
+   }
+}
+
+impl Iterator for StateUpdates {
+   type Item = resources::StateUpdate;
+
+   fn next(&mut self) -> Option<resources::StateUpdate> {
+      if self.shutdown {
+         return None;
+      }
+
+      let update = receive_until(&mut self.reader, self.timeout);
+      if let Some(resources::StateUpdate::StateChange(node::State::ShuttingDown)) = update {
+         self.shutdown = true;
+      }
+      update
+   }
+}
+
+/// Blocks until the next update arrives, or until the timeout if there is one.
+fn receive_until<T: Clone + Sync>(reader: &mut bus::BusReader<T>, timeout: Option<time::SteadyTime>) -> Option<T> {
+   match timeout {
+      Some(timeout) => {
+         let remaining = timeout - time::SteadyTime::now();
+         if remaining <= time::Duration::zero() {
+            return None;
          }
+         reader.recv_timeout(remaining.to_std().unwrap()).ok()
+      },
+      None => reader.recv().ok(),
+   }
+}
 
-         match self.iter.next() {
-            Some(resources::ReceptionUpdate::RpcReceived(rpc)) => {
-               if let Some(ref kind_filter) = self.kind_filter {
-                  match rpc.kind {
-                     rpc::Kind::Ping                 => if *kind_filter != KindFilter::Ping { continue; },
-                     rpc::Kind::PingResponse         => if *kind_filter != KindFilter::PingResponse { continue; },
-                     rpc::Kind::Store(_)             => if *kind_filter != KindFilter::Store { continue; },
-                     rpc::Kind::MassStore(_)         => if *kind_filter != KindFilter::MassStore { continue; },
-                     rpc::Kind::StoreResponse(_)     => if *kind_filter != KindFilter::StoreResponse { continue; },
-                     rpc::Kind::Locate(_)            => if *kind_filter != KindFilter::Locate { continue; },
-                     rpc::Kind::LocateResponse(_)    => if *kind_filter != KindFilter::LocateResponse { continue; },
-                     rpc::Kind::Retrieve(_)          => if *kind_filter != KindFilter::Retrieve { continue; },
-                     rpc::Kind::RetrieveResponse(_)  => if *kind_filter != KindFilter::RetrieveResponse { continue; },
-                     rpc::Kind::Probe(_)             => if *kind_filter != KindFilter::Probe { continue; },
-                     rpc::Kind::ProbeResponse(_)     => if *kind_filter != KindFilter::ProbeResponse { continue; },
-                  }
-               }
+/// Iterator over the store RPCs for a particular key received by a node. 
+///
+/// Only updates routed through the watching node are observed, so it's mostly useful
+/// on nodes that are replicas for the key. Like `Receptions`, it blocks indefinitely
+/// unless a lifespan is given, and ends when the node shuts down.
+pub struct KeyWatcher {
+   receptions : Receptions,
+   key        : SubotaiHash,
+}
 
-               if let Some(ref sender_filter) = self.sender_filter {
-                  if !sender_filter.contains(&rpc.sender.id) {
-                     continue;
-                  }
-               }
+/// Notification of a store RPC received for a watched key.
+#[derive(Debug, Clone)]
+pub struct KeyUpdate {
+   pub sender     : routing::NodeInfo,
+   pub entry      : storage::StorageEntry,
+   pub expiration : time::Tm,
+}
 
-               return Some(rpc);
-            },
-            Some(resources::ReceptionUpdate::StateChange(node::State::ShuttingDown)) => self.shutdown = true,
-            _ => (),
+impl KeyWatcher {
+   pub fn new(resources: &resources::Resources, key: SubotaiHash) -> KeyWatcher {
+      KeyWatcher {
+         receptions : resources.receptions().of_kind(KindFilter::Store),
+         key        : key,
+      }
+   }
+
+   /// Restricts the watcher to a particular span of time.
+   pub fn during(mut self, lifespan: time::Duration) -> KeyWatcher {
+      self.receptions = self.receptions.during(lifespan);
+      self
+   }
+}
+
+impl Iterator for KeyWatcher {
+   type Item = KeyUpdate;
+
+   fn next(&mut self) -> Option<KeyUpdate> {
+      for rpc in &mut self.receptions {
+         if let rpc::Kind::Store(ref payload) = rpc.kind {
+            if payload.key == self.key {
+               return Some(KeyUpdate {
+                  sender     : rpc.sender.clone(),
+                  entry      : payload.entry.clone(),
+                  expiration : time::Tm::from(payload.expiration.clone()),
+               });
+            }
          }
       }
       None
@@ -129,6 +346,8 @@ mod tests {
     use node;
     use time;
     use super::KindFilter;
+    use std::thread;
+    use std::time::Duration as StdDuration;
 
     #[test]
     fn produces_rpcs_but_not_ticks() {
@@ -148,6 +367,39 @@ mod tests {
        assert_eq!(beta_receptions.count(),2);
     }
 
+    #[test]
+    fn filtering_by_several_kinds() {
+       let receiver = node::Node::new().unwrap();
+       let alpha = node::Node::new().unwrap();
+       let receptions = receiver
+         .receptions()
+         .during(time::Duration::seconds(1))
+         .of_kinds(vec![KindFilter::Ping, KindFilter::PingResponse]);
+
+       assert!(alpha.resources.ping(&receiver.local_info().address).is_ok());
+       assert!(alpha.resources.ping(&receiver.local_info().address).is_ok());
+       assert!(receiver.resources.ping(&alpha.local_info().address).is_ok());
+
+       assert_eq!(receptions.count(), 3);
+    }
+
+    #[test]
+    fn try_next_does_not_block_on_a_silent_node() {
+       let node = node::Node::new().unwrap();
+       let mut receptions = node.receptions();
+       let start = time::SteadyTime::now();
+       assert!(receptions.try_next().is_none());
+       assert!(time::SteadyTime::now() - start < time::Duration::milliseconds(100));
+
+       let alpha = node::Node::new().unwrap();
+       assert!(alpha.resources.ping(&node.local_info().address).is_ok());
+       let mut pings = node.receptions().of_kind(KindFilter::Ping);
+       assert!(alpha.resources.ping(&node.local_info().address).is_ok());
+       thread::sleep(StdDuration::from_millis(100));
+       assert!(pings.try_next().is_some());
+       assert!(pings.try_next().is_none());
+    }
+
     #[test]
     fn sender_filtering() {
        let receiver = node::Node::new().unwrap();
@@ -171,6 +423,26 @@ mod tests {
 
        assert_eq!(receptions.count(),1);
     }
+
+    #[test]
+    fn bootstrapping_produces_network_and_state_updates() {
+       // With a k factor of 1, learning about a single node is enough to go on grid.
+       let alpha = node::Factory::new().k_factor(1).create_node().unwrap();
+       let beta = node::Node::new().unwrap();
+       let mut network_updates = alpha.watch_network().during(time::Duration::seconds(1));
+       let mut state_updates = alpha.watch_state().during(time::Duration::seconds(1));
+
+       alpha.bootstrap(&beta.resources.local_info().address).unwrap();
+       let beta_id = beta.resources.local_info().id;
+
+       assert!(network_updates.any(|update| match update {
+          node::NetworkUpdate::AddedNode(info) => info.id == beta_id,
+          _ => false,
+       }));
+       assert!(state_updates.any(|update| match update {
+          node::StateUpdate::StateChange(state) => state == node::State::OnGrid,
+       }));
+    }
 }
 
 
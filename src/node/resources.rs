@@ -1,9 +1,11 @@
 use {hash, node, routing, storage, rpc, bus, time, SubotaiError, SubotaiResult};
 use std::{net, sync, cmp};
+use std::time::Duration as StdDuration;
 use rpc::Rpc;
-use hash::SubotaiHash;
+use hash::{SubotaiHash, NodeId};
 use node::receptions;
-use std::str::FromStr;
+use rand::{thread_rng, Rng};
+use std::collections::{VecDeque, HashMap};
 
 /// Node resources for synchronous operations.
 ///
@@ -14,7 +16,7 @@ use std::str::FromStr;
 /// The node layer above is in charge of parallelizing those operations 
 /// by spawning threads when adequate.
 pub struct Resources {
-   pub id                : SubotaiHash,
+   pub id                : NodeId,
    pub table             : routing::Table,
    pub storage           : storage::Storage,
    pub outbound          : net::UdpSocket,
@@ -23,6 +25,16 @@ pub struct Resources {
    pub network_updates   : sync::Mutex<bus::Bus<NetworkUpdate>>,
    pub state_updates     : sync::Mutex<bus::Bus<StateUpdate>>,
    pub conflicts         : sync::Mutex<Vec<routing::EvictionConflict>>,
+   pub recent_waves      : sync::Mutex<VecDeque<time::SteadyTime>>,
+   pub peer_versions     : sync::Mutex<HashMap<NodeId, String>>,
+   pub clock_offsets     : sync::Mutex<HashMap<NodeId, time::Duration>>,
+   pub liveness          : sync::Mutex<HashMap<NodeId, VecDeque<bool>>>,
+   pub read_only         : sync::atomic::AtomicBool,
+   pub rpc_stats         : sync::Mutex<HashMap<&'static str, (u64, u64)>>,
+   pub codec             : Box<rpc::codec::WireCodec>,
+   pub started_at        : time::SteadyTime,
+   pub dropped_datagrams : sync::atomic::AtomicUsize,
+   pub detached          : sync::Arc<DetachedThreads>,
    pub configuration     : node::Configuration,
    pub state             : sync::RwLock<node::State>,
 }
@@ -37,11 +49,40 @@ pub enum ReceptionUpdate {
    StateChange(node::State),
 }
 
-/// Notifies of new nodes entering the network and changes of state.
+/// Notifies of new nodes entering the network, changes of state, and entries
+/// stored in or removed from this node at the request of other nodes.
 #[derive(Clone, Debug)]
 pub enum NetworkUpdate {
    AddedNode(routing::NodeInfo),
    StateChange(node::State),
+   StoredEntry(SubotaiHash),
+   RemovedEntry(SubotaiHash),
+}
+
+/// Outcome of a mass store operation, classifying the storage nodes by ID 
+/// depending on whether they accepted all, some or none of the entries. 
+/// Nodes that didn't respond at all are considered failed.
+#[derive(Clone, Debug)]
+pub struct MassStoreReport {
+   pub fully_accepted : Vec<NodeId>,
+   pub partially      : Vec<NodeId>,
+   pub failed         : Vec<NodeId>,
+}
+
+/// Outcome of a detailed store operation: how many of the storage nodes for the key
+/// were sent the entry, and how many of them accepted it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StoreReport {
+   pub contacted : usize,
+   pub succeeded : usize,
+}
+
+/// Outcome of `Node::retrieve_or_closest`. Either the entries under the key, or the
+/// closest nodes to the key queried by the failed search.
+#[derive(Clone, Debug)]
+pub enum RetrieveOutcome {
+   Found(Vec<storage::StorageEntry>),
+   NotFound(Vec<routing::NodeInfo>),
 }
 
 /// Just notifies about state changes.
@@ -52,10 +93,9 @@ pub enum StateUpdate {
 
 impl Resources {
    pub fn local_info(&self) -> routing::NodeInfo {
-      routing::NodeInfo {
-         id      : self.id.clone(),
-         address : self.inbound.local_addr().unwrap(),
-      }
+      let mut info = routing::NodeInfo::new(self.id.clone(), self.inbound.local_addr().unwrap());
+      info.region = self.configuration.region;
+      info
    }
 
    /// Current state of the node
@@ -73,17 +113,83 @@ impl Resources {
       self.state_updates.lock().unwrap().broadcast(StateUpdate::StateChange(state));
    }
 
-   /// Pings a node via its IP address, blocking until ping response.
+   /// Pings a node via its IP address, blocking until ping response. The ping is re-sent 
+   /// up to `Configuration::ping_retries` times, each time waiting twice as long as the 
+   /// previous one, so that all attempts together take at most the network timeout.
    pub fn ping(&self, target: &net::SocketAddr) -> SubotaiResult<()> {
+      // Retries are bounded so the first attempt still gets a meaningful share of the timeout.
+      let retries = cmp::min(self.configuration.ping_retries, 8);
+      let timeout = time::Duration::seconds(self.configuration.network_timeout_s);
+      let mut attempt_timeout = timeout / ((1 << (retries + 1)) - 1);
+
+      for _ in 0..retries {
+         if let Some(responder) = try!(self.ping_once(target, attempt_timeout)) {
+            return self.record_ping_outcome(target, Some(responder));
+         }
+         attempt_timeout = attempt_timeout * 2;
+      }
+      let responder = try!(self.ping_once(target, attempt_timeout));
+      self.record_ping_outcome(target, responder)
+   }
+
+   /// Pings a node, waiting for a response only for the given timeout instead of
+   /// the configured network timeout. The ping is never re-sent.
+   pub fn ping_with_timeout(&self, target: &net::SocketAddr, timeout: time::Duration) -> SubotaiResult<()> {
+      let responder = try!(self.ping_once(target, timeout));
+      self.record_ping_outcome(target, responder)
+   }
+
+   /// Sends a single ping, and reports the node that responded to it, if any.
+   fn ping_once(&self, target: &net::SocketAddr, timeout: time::Duration) -> SubotaiResult<Option<routing::NodeInfo>> {
+      let rpc = Rpc::ping(self.local_info());
+      let responses = self.receptions()
+         .during(timeout)
+         .of_kind(receptions::KindFilter::PingResponse)
+         .filter(|rpc| Resources::responds_for(&rpc.sender.address, target))
+         .take(1);
+      try!(self.send(&rpc, target));
+      Ok(responses.last().map(|response| response.sender))
+   }
+
+   /// Records the outcome of a ping for the reliability of the pinged node, and
+   /// reports it as a result.
+   fn record_ping_outcome(&self, target: &net::SocketAddr, responder: Option<routing::NodeInfo>) -> SubotaiResult<()> {
+      match responder {
+         Some(responder) => {
+            self.record_liveness(&responder.id, true);
+            Ok(())
+         },
+         None => {
+            let pinged = |info: &routing::NodeInfo| info.address.port() == target.port() && Resources::responds_for(&info.address, target);
+            if let Some(info) = self.table.all_nodes().find(pinged) {
+               self.record_liveness(&info.id, false);
+            }
+            Err(SubotaiError::NoResponse)
+         },
+      }
+   }
+
+   /// Pings several nodes at once, succeeding as soon as any of them responds. If the
+   /// ping can't be sent to any of them, the reason it failed for the last one is reported.
+   pub fn ping_any(&self, targets: &[net::SocketAddr]) -> SubotaiResult<()> {
       let rpc = Rpc::ping(self.local_info());
-      let packet = rpc.serialize();
       let responses = self.receptions()
          .during(time::Duration::seconds(self.configuration.network_timeout_s))
          .of_kind(receptions::KindFilter::PingResponse)
-         .filter(|rpc| rpc.sender.address.ip() == target.ip() || 
-                       target.ip() == net::IpAddr::from_str("0.0.0.0").unwrap())
+         .filter(|rpc| targets.iter().any(|target| Resources::responds_for(&rpc.sender.address, target)))
          .take(1);
-      try!(self.outbound.send_to(&packet, target));
+
+      let mut sent = 0;
+      let mut last_error = None;
+      for target in targets {
+         match self.send(&rpc, target) {
+            Ok(()) => sent += 1,
+            Err(error) => last_error = Some(error),
+         }
+      }
+      if sent == 0 {
+         return Err(last_error.unwrap_or(SubotaiError::NoResponse));
+      }
 
       match responses.count() {
          1 => Ok(()),
@@ -91,45 +197,143 @@ impl Resources {
       }
    }
 
+   /// Whether a response coming from an address can be the answer to a ping sent to a target. 
+   /// A target with an unspecified IP (e.g. the address of a node bound to all interfaces)
+   /// may answer from any address.
+   fn responds_for(sender: &net::SocketAddr, target: &net::SocketAddr) -> bool {
+      sender.ip() == target.ip() || target.ip().is_unspecified()
+   }
+
    /// Sends a ping and doesn't wait for a response. Used by the maintenance threads.
    pub fn ping_and_forget(&self, target: &net::SocketAddr) -> SubotaiResult<()> {
       let rpc = Rpc::ping(self.local_info());
-      let packet = rpc.serialize();
-      try!(self.outbound.send_to(&packet, target));
+      self.send(&rpc, target)
+   }
+
+   /// Whether datagrams from a given source should be processed, according to
+   /// `Configuration::allowed_cidrs`.
+   pub fn accepts_traffic_from(&self, source: &net::SocketAddr) -> bool {
+      let allowed = &self.configuration.allowed_cidrs;
+      allowed.is_empty() || allowed.iter().any(|subnet| subnet.contains(&source.ip()))
+   }
+
+   /// Sends an RPC through the outbound socket. Fails early with `AddressFamilyMismatch`
+   /// if the target address belongs to a different family (IPv4/IPv6) than the socket,
+   /// as the socket would be unable to reach it.
+   pub fn send(&self, rpc: &Rpc, target: &net::SocketAddr) -> SubotaiResult<()> {
+      match (try!(self.outbound.local_addr()), *target) {
+         (net::SocketAddr::V4(_), net::SocketAddr::V4(_)) |
+         (net::SocketAddr::V6(_), net::SocketAddr::V6(_)) => (),
+         _ => return Err(SubotaiError::AddressFamilyMismatch),
+      }
+
+      try!(self.outbound.send_to(&try!(self.codec.encode(rpc)), target));
+      self.rpc_stats.lock().unwrap().entry(rpc.kind.name()).or_insert((0, 0)).0 += 1;
       Ok(())
    }
 
+   /// Sends an RPC as part of a multi-node operation, where unreachable peers
+   /// (due to an address family mismatch) are simply skipped.
+   fn send_skipping_mismatches(&self, rpc: &Rpc, target: &net::SocketAddr) -> SubotaiResult<()> {
+      match self.send(rpc, target) {
+         Err(SubotaiError::AddressFamilyMismatch) => Ok(()),
+         result => result,
+      }
+   }
+
    /// ReceptionUpdates the table with a new node, and starts the conflict resolution mechanism
    /// if necessary.
-   pub fn update_table(&self, info: routing::NodeInfo) {
+   pub fn update_table(&self, info: routing::NodeInfo) -> node::ContactUpdate {
       let defensive = { // Lock scope
          *self.state.read().unwrap() == node::State::Defensive
       };
 
-      let update_result = self.table.update_node(info.clone());
-
-      if let routing::UpdateResult::CausedConflict(conflict) = update_result {
-         if defensive {
-            self.table.revert_conflict(conflict);
-         } else {
-            let mut conflicts = self.conflicts.lock().unwrap();
-            conflicts.push(conflict);
-            if conflicts.len() == self.configuration.max_conflicts {
-               self.set_state(node::State::Defensive);
+      let contact_update = match self.table.update_node(info.clone()) {
+         // Without defensive mode, the newcomer simply takes the place of the evicted node.
+         routing::UpdateResult::CausedConflict(conflict) if !self.configuration.enable_defensive_mode => {
+            self.forget_peer(&conflict.evicted.id);
+            self.network_updates.lock().unwrap().broadcast(NetworkUpdate::AddedNode(info));
+            node::ContactUpdate::Added
+         },
+         routing::UpdateResult::CausedConflict(conflict) => {
+            if defensive {
+               self.forget_peer(&conflict.evictor.id);
+               self.table.revert_conflict(conflict);
+               node::ContactUpdate::Rejected
+            } else {
+               let mut conflicts = self.conflicts.lock().unwrap();
+               conflicts.push(conflict);
+               if conflicts.len() == self.configuration.max_conflicts {
+                  self.set_state(node::State::Defensive);
+               }
+               node::ContactUpdate::Conflict
             }
-         }
-      } else if let routing::UpdateResult::AddedNode = update_result {
-         self.network_updates.lock().unwrap().broadcast(NetworkUpdate::AddedNode(info));
-      }
+         },
+         routing::UpdateResult::AddedNode => {
+            self.network_updates.lock().unwrap().broadcast(NetworkUpdate::AddedNode(info));
+            node::ContactUpdate::Added
+         },
+         routing::UpdateResult::UpdatedNode => node::ContactUpdate::Updated,
+         routing::UpdateResult::TableFull => node::ContactUpdate::Rejected,
+      };
 
       let off_grid = { // Lock scope
          *self.state.read().unwrap() == node::State::OffGrid
       };
 
       // We go on grid as soon as the network is big enough.
-      if off_grid && self.table.len() > self.configuration.k_factor {
+      if off_grid && self.contact_count() >= self.configuration.k_factor {
          self.set_state(node::State::OnGrid);
       }
+
+      contact_update
+   }
+
+   /// Closest `k_factor` nodes to a target in the routing table, from which waves pick
+   /// their seeds. They are strictly sorted by distance to the target, and no two contacts
+   /// share an ID, so seeds only depend on the contents of the table. Lookups are thus
+   /// reproducible given identical routing table states.
+   pub fn initial_candidates(&self, target: &SubotaiHash) -> Vec<routing::NodeInfo> {
+      self.table
+         .closest_others_to(target)
+         .take(self.configuration.k_factor)
+         .collect()
+   }
+
+   /// Sorts wave candidates by distance to the target and removes duplicates, keeping
+   /// only the closest `2 * K_FACTOR`. This bounds the work done by a wave no matter
+   /// how many nodes the responders claim to know about.
+   pub fn bound_candidates(&self, target: &SubotaiHash, candidates: &mut Vec<routing::NodeInfo>) {
+      candidates.sort_by(|info_a, info_b| (&info_a.id ^ target).cmp(&(&info_b.id ^ target)));
+      candidates.dedup();
+      candidates.truncate(2 * self.configuration.k_factor);
+   }
+
+   /// Picks the next `ALPHA` candidates for a wave to query, among those it hasn't queried
+   /// yet. Candidates in the same region as this node go ahead of those at the same bucket
+   /// distance from the target, but `closest` itself is left sorted by distance, so the
+   /// region never changes which nodes a wave returns.
+   pub fn next_to_query(&self, 
+                        target: &SubotaiHash, 
+                        closest: &[routing::NodeInfo], 
+                        queried: &[routing::NodeInfo]) -> Vec<routing::NodeInfo> {
+      let mut pending: Vec<_> = closest
+         .iter()
+         .filter(|info| !queried.contains(info) && &info.id != &self.id)
+         .cloned()
+         .collect();
+      self.prefer_local_region(target, &mut pending);
+      pending.truncate(self.configuration.alpha);
+      pending
+   }
+
+   /// Moves candidates in the same region as this node ahead of those at the same bucket
+   /// distance from the target (i.e. whose distance has the same height). Candidates are
+   /// otherwise left in their original order. Does nothing if this node has no region.
+   fn prefer_local_region(&self, target: &SubotaiHash, candidates: &mut Vec<routing::NodeInfo>) {
+      if let Some(region) = self.configuration.region {
+         candidates.sort_by_key(|info| ((&info.id ^ target).height(), info.region != Some(region)));
+      }
    }
 
    /// Attempts to find a node through the network. This procedure will end as soon
@@ -139,16 +343,30 @@ impl Resources {
    ///
    /// For a more thorough mapping of the surroundings of a node, or if you specifically 
    /// need to know the K closest nodes to a given ID, use probe.
-   pub fn locate(&self, target: &SubotaiHash) -> SubotaiResult<routing::NodeInfo> {
+   pub fn locate(&self, target: &NodeId) -> SubotaiResult<routing::NodeInfo> {
+      self.locate_with_deadline(target, self.default_deadline())
+   }
+
+   /// Like `locate`, but gives up at a particular point in time.
+   pub fn locate_with_deadline(&self, target: &NodeId, deadline: time::SteadyTime) -> SubotaiResult<routing::NodeInfo> {
+      self.locate_from(target, deadline, None)
+   }
+
+   /// Like `locate`, but fails with `Cancelled` as soon as a flag is set.
+   pub fn locate_cancellable(&self, target: &NodeId, cancel: sync::Arc<sync::atomic::AtomicBool>) -> SubotaiResult<routing::NodeInfo> {
+      self.locate_from(target, self.default_deadline(), Some(cancel))
+   }
+
+   fn locate_from(&self, 
+                  target: &NodeId, 
+                  deadline: time::SteadyTime, 
+                  cancel: Option<sync::Arc<sync::atomic::AtomicBool>>) -> SubotaiResult<routing::NodeInfo> {
       // If the node is already present in our table, we are done early.
       if let Some(node) = self.table.specific_node(target) {
          return Ok(node);
       }
 
-      let mut closest: Vec<_> = self.table.closest_nodes_to(target)
-         .filter(|info| &info.id != &self.id)
-         .take(self.configuration.k_factor)
-         .collect();
+      let mut closest = self.initial_candidates(target);
       let seeds: Vec<_> = closest.iter().cloned().take(self.configuration.alpha).collect();
 
       // We use a wave operation to locate the node. We want to stop the wave if we
@@ -175,21 +393,18 @@ impl Resources {
             .flat_map(|vec| vec.into_iter())
             .chain(former_closest)
             .collect();
-       
-         // We restore the order and remove duplicates, to finally return the closest ALPHA.
-         closest.sort_by(|info_a, info_b| (&info_a.id ^ target).cmp(&(&info_b.id ^ target)));
-         closest.dedup();
-         WaveStrategy::Continue(closest
-            .iter()
-            .filter(|info| !queried.contains(info) && &info.id != &self.id)
-            .cloned().take(self.configuration.alpha).collect()
-         )
+         self.bound_candidates(target, &mut closest);
+
+         WaveStrategy::Continue(self.next_to_query(target, &closest, queried))
       };
 
       let rpc = Rpc::locate(self.local_info(), target.clone());
-      let timeout = time::Duration::seconds(3*self.configuration.network_timeout_s);
+      self.cancellable_wave(seeds, strategy, rpc, deadline, cancel)
+   }
 
-      self.wave(seeds, strategy, rpc, timeout)
+   /// Deadline for network-wide operations started now, when none is given.
+   fn default_deadline(&self) -> time::SteadyTime {
+      time::SteadyTime::now() + time::Duration::seconds(3*self.configuration.network_timeout_s)
    }
 
 
@@ -198,15 +413,22 @@ impl Resources {
    ///
    /// The probe will consult `depth` number of nodes to obtain that information.
    pub fn probe(&self, target: &SubotaiHash, depth: usize) -> SubotaiResult<Vec<routing::NodeInfo>> {
+      self.probe_closest(target, depth, self.configuration.k_factor)
+   }
+
+   /// Like `probe`, but returns up to `count` nodes (bounded by twice the `k_factor`) 
+   /// instead of `k_factor`.
+   pub fn probe_closest(&self, target: &SubotaiHash, depth: usize, count: usize) -> SubotaiResult<Vec<routing::NodeInfo>> {
+      self.probe_closest_with_deadline(target, depth, count, self.default_deadline())
+   }
+
+   /// Like `probe_closest`, but gives up at a particular point in time.
+   pub fn probe_closest_with_deadline(&self, target: &SubotaiHash, depth: usize, count: usize, deadline: time::SteadyTime) -> SubotaiResult<Vec<routing::NodeInfo>> {
       // We record the fact we attempted a probe for this bucket.
       self.table.mark_bucket_as_probed(target);
 
       // We start with the closest K nodes we know about.
-      let mut closest: Vec<_> = self.table
-         .closest_nodes_to(target)
-         .filter(|info| &info.id != &self.id)
-         .take(self.configuration.k_factor)
-         .collect();
+      let mut closest = self.initial_candidates(target);
 
       let seeds: Vec<_> = closest.iter().cloned().take(self.configuration.alpha).collect();
       // Strategy is similar to the `locate` wave. We keep probing the closest `ALPHA` nodes
@@ -220,48 +442,442 @@ impl Resources {
             .flat_map(|vec| vec.into_iter())
             .chain(former_closest)
             .collect();
-       
-         // We restore the order and remove duplicates, to finally return the closest ALPHA.
-         closest.sort_by(|info_a, info_b| (&info_a.id ^ target).cmp(&(&info_b.id ^ target)));
-         closest.dedup();
+         self.bound_candidates(target, &mut closest);
 
          if queried.len() >= depth {
-            WaveStrategy::Halt(closest.iter().cloned().take(self.configuration.k_factor).collect())
+            WaveStrategy::Halt(closest.iter().cloned().take(count).collect())
          } else {
-            WaveStrategy::Continue(closest
-               .iter()
-               .filter(|info| !queried.contains(info) && &info.id != &self.id)
-               .cloned().take(self.configuration.alpha).collect()
-            )
+            WaveStrategy::Continue(self.next_to_query(target, &closest, queried))
          }
       };
 
       let rpc = Rpc::probe(self.local_info(), target.clone());
-      let timeout = time::Duration::seconds(3*self.configuration.network_timeout_s);
+      self.wave(seeds, strategy, rpc, deadline)
+   }
 
-      self.wave(seeds, strategy, rpc, timeout)
+   /// Probes the network around each locally stored key, and returns those for which
+   /// this node ranks among the `K_FACTOR` closest nodes (i.e. is a primary replica).
+   pub fn primary_keys(&self) -> SubotaiResult<Vec<SubotaiHash>> {
+      let mut primary_keys = Vec::new();
+      for key in self.storage.keys() {
+         let own_distance = &self.id ^ &key;
+         let closer_nodes = try!(self.probe(&key, self.configuration.k_factor))
+            .into_iter()
+            .filter(|info| info.id != self.id && (&info.id ^ &key) < own_distance)
+            .count();
+
+         if closer_nodes < self.configuration.k_factor {
+            primary_keys.push(key);
+         }
+      }
+      Ok(primary_keys)
    }
 
-   pub fn retrieve(&self, key: &SubotaiHash) -> SubotaiResult<Vec<storage::StorageEntry>> {
-      // If the value is already present in our table, we are done early.
-      if let Some(entries) = self.storage.retrieve(key) {
-         return Ok(entries);
+   /// Asks the nodes around a target for the stored key closest to it, returning the
+   /// nearest one found (including in local storage) along with the node holding it.
+   pub fn nearest_stored_key(&self, target: &SubotaiHash) -> SubotaiResult<Option<(routing::NodeInfo, SubotaiHash)>> {
+      let candidates = try!(self.probe(target, self.configuration.k_factor));
+      let candidate_ids: Vec<_> = candidates.iter().map(|info| info.id.clone()).collect();
+
+      let responses = self
+         .receptions()
+         .of_kind(receptions::KindFilter::NearestKeyResponse)
+         .from_senders(candidate_ids)
+         .during(time::Duration::seconds(self.configuration.network_timeout_s))
+         .filter_map(|rpc| rpc.is_nearest_key_response(target).map(|nearest| (rpc.sender, nearest)))
+         .take(candidates.len());
+
+      let rpc = Rpc::nearest_key(self.local_info(), target.clone());
+      for candidate in &candidates {
+         try!(self.send_skipping_mismatches(&rpc, &candidate.address));
       }
 
-      // We start with the closest K nodes we know about.
-      let mut closest: Vec<_> = self.table
-         .closest_nodes_to(key)
-         .filter(|info| &info.id != &self.id)
+      let local = self.storage.nearest_key(target).map(|key| (self.local_info(), key));
+      Ok(responses
+         .filter_map(|(sender, nearest)| nearest.map(|key| (sender, key)))
+         .chain(local)
+         .min_by_key(|&(_, ref key)| key ^ target))
+   }
+
+   /// Compares the local storage digest with that of another node. Returns None if both
+   /// nodes hold the same data, or the key list of the other node otherwise. The key list
+   /// is requested one page at a time, so it's only consistent if the other node's keys
+   /// don't change in the meantime.
+   pub fn exchange_digest(&self, target: &routing::NodeInfo) -> SubotaiResult<Option<Vec<SubotaiHash>>> {
+      let digest = self.storage.digest();
+      let mut keys = Vec::new();
+      for page in 0.. {
+         let mut responses = self
+            .receptions()
+            .of_kind(receptions::KindFilter::DigestResponse)
+            .from(target.id.clone())
+            .during(time::Duration::seconds(self.configuration.network_timeout_s))
+            .filter_map(move |rpc| rpc.is_digest_response(page));
+
+         let rpc = Rpc::digest(self.local_info(), digest.clone(), page);
+         try!(self.send(&rpc, &target.address));
+         match responses.next() {
+            None => return Err(SubotaiError::NoResponse),
+            Some((None, _)) => return Ok(None),
+            Some((Some(page_keys), more_pages)) => {
+               keys.extend(page_keys);
+               if !more_pages {
+                  break;
+               }
+            },
+         }
+      }
+      Ok(Some(keys))
+   }
+
+   /// Compares storage digests with a peer and, if they differ, pulls the entries under keys
+   /// only the peer holds and pushes the entries under keys only this node holds. Entries are
+   /// only exchanged for keys the receiving node is a replica of, according to this node's 
+   /// routing table. Returns the number of entries reconciled.
+   ///
+   /// Note that keys held by both nodes are not compared entry by entry, so differing
+   /// entries under a shared key are left to the regular republishing.
+   pub fn reconcile_with(&self, peer: &routing::NodeInfo) -> SubotaiResult<usize> {
+      if self.is_read_only() {
+         return Ok(0);
+      }
+
+      let peer_keys = match try!(self.exchange_digest(peer)) {
+         Some(keys) => keys,
+         None => return Ok(0),
+      };
+      let local_keys = self.storage.keys();
+
+      let to_pull: Vec<_> = peer_keys
+         .iter()
+         .filter(|key| !local_keys.contains(key) && self.is_responsible_for(key))
+         .cloned()
+         .collect();
+      let to_push: Vec<_> = local_keys
+         .iter()
+         .filter(|key| !peer_keys.contains(key) && self.is_replica(peer, key))
+         .cloned()
+         .collect();
+
+      let pulled = try!(self.pull_from(peer, &to_pull));
+      let pushed = try!(self.push_to(peer, &to_push));
+      Ok(pulled + pushed)
+   }
+
+   /// Reconciles storage with a node picked randomly among the `k_factor` closest ones.
+   pub fn reconcile_with_random_neighbor(&self) -> SubotaiResult<usize> {
+      let neighbors: Vec<_> = self.table.closest_others_to(&self.id).take(self.configuration.k_factor).collect();
+      match thread_rng().choose(&neighbors) {
+         Some(neighbor) => self.reconcile_with(neighbor),
+         None => Ok(0),
+      }
+   }
+
+   /// Retrieves the entries under a set of keys from a particular node, storing them locally.
+   fn pull_from(&self, peer: &routing::NodeInfo, keys: &[SubotaiHash]) -> SubotaiResult<usize> {
+      if keys.is_empty() {
+         return Ok(0);
+      }
+
+      let responses = self
+         .receptions()
+         .of_kind(receptions::KindFilter::RetrieveResponse)
+         .from(peer.id.clone())
+         .during(time::Duration::seconds(self.configuration.network_timeout_s))
+         .filter_map(|rpc| keys.iter().filter_map(|key| rpc.successfully_retrieved(key).map(|entries| (key.clone(), entries))).next())
+         .take(keys.len());
+
+      for key in keys {
+         try!(self.send(&Rpc::retrieve(self.local_info(), key.clone()), &peer.address));
+      }
+
+      let mut pulled = 0;
+      for (key, entries) in responses {
+         for (entry, expiration) in entries {
+            if self.storage.store(&key, &entry, &expiration) == storage::StoreResult::Success {
+               pulled += 1;
+            }
+         }
+      }
+      Ok(pulled)
+   }
+
+   /// Sends the local entries under a set of keys to a particular node, returning how many
+   /// of them were accepted.
+   fn push_to(&self, peer: &routing::NodeInfo, keys: &[SubotaiHash]) -> SubotaiResult<usize> {
+      let mut rpcs = Vec::new();
+      let mut pushed = Vec::new();
+      for key in keys {
+         if let Some(entries) = self.storage.retrieve_with_expiry(key) {
+            let collection: Vec<_> = entries.into_iter().map(|(entry, time)| (entry, rpc::SerializableTime::from(time))).collect();
+            pushed.push((key.clone(), collection.len()));
+            rpcs.push(Rpc::mass_store(self.local_info(), key.clone(), collection));
+         }
+      }
+      if rpcs.is_empty() {
+         return Ok(0);
+      }
+
+      let responses = self
+         .receptions()
+         .of_kind(receptions::KindFilter::StoreResponse)
+         .from(peer.id.clone())
+         .during(time::Duration::seconds(self.configuration.network_timeout_s))
+         .filter_map(|rpc| pushed.iter().filter_map(|&(ref key, count)| rpc.is_store_response(key).map(|result| (result, count))).next())
+         .take(pushed.len());
+
+      for rpc in &rpcs {
+         try!(self.send(rpc, &peer.address));
+      }
+
+      Ok(responses
+         .filter(|&(ref result, _)| *result == storage::StoreResult::Success)
+         .map(|(_, count)| count)
+         .sum())
+   }
+
+   /// Reports whether a node ranks among the `K_FACTOR` closest nodes to a key, based
+   /// on the contents of this node's routing table.
+   fn is_replica(&self, node: &routing::NodeInfo, key: &SubotaiHash) -> bool {
+      let distance = &node.id ^ key;
+      let closer_nodes = self.table
+         .closest_others_to(key)
          .take(self.configuration.k_factor)
+         .filter(|info| (&info.id ^ key) < distance)
+         .count();
+      let closer_self = if (&self.id ^ key) < distance { 1 } else { 0 };
+      closer_nodes + closer_self < self.configuration.k_factor
+   }
+
+   /// Asks every node responsible for a key for its copy of the entries, returning the
+   /// copies received before the network timeout along with the node providing each.
+   /// The local copy is included if this node is responsible for the key.
+   pub fn retrieve_all(&self, key: &SubotaiHash) -> SubotaiResult<Vec<(routing::NodeInfo, Vec<storage::StorageEntry>)>> {
+      let mut replicas = Vec::new();
+      self.retrieve_until(key, |copy| { replicas.push(copy); false }).map(|_| replicas)
+   }
+
+   /// Retrieves the entries under a key from its replicas, succeeding as soon as `quorum`
+   /// of them return the same entries. Fails with `InconsistentReplicas` if the replicas
+   /// that responded disagree so that no quorum is possible.
+   pub fn retrieve_quorum(&self, key: &SubotaiHash, quorum: usize) -> SubotaiResult<Vec<storage::StorageEntry>> {
+      // Groups of replicas returning identical entries, with the amount of replicas in each.
+      let mut groups: Vec<(Vec<storage::StorageEntry>, usize)> = Vec::new();
+      let mut agreed = None;
+      try!(self.retrieve_until(key, |(_, entries)| {
+         let index = match groups.iter().position(|&(ref group_entries, _)| Resources::same_entries(group_entries, &entries)) {
+            Some(index) => index,
+            None => { groups.push((entries, 0)); groups.len() - 1 },
+         };
+         groups[index].1 += 1;
+         if groups[index].1 >= quorum {
+            agreed = Some(groups[index].0.clone());
+         }
+         agreed.is_some()
+      }));
+
+      match agreed {
+         Some(entries) => Ok(entries),
+         None if groups.len() > 1 => Err(SubotaiError::InconsistentReplicas),
+         None => Err(SubotaiError::UnresponsiveNetwork),
+      }
+   }
+
+   /// Feeds the copies of a key held by each of its replicas to a closure, until it returns
+   /// true or all replicas have responded (or timed out). 
+   fn retrieve_until<F>(&self, key: &SubotaiHash, mut f: F) -> SubotaiResult<()>
+      where F: FnMut((routing::NodeInfo, Vec<storage::StorageEntry>)) -> bool
+   {
+      // Probe responses may list this node, whose copy is already accounted for locally.
+      let candidates: Vec<_> = try!(self.probe(key, self.configuration.k_factor))
+         .into_iter()
+         .filter(|info| info.id != self.id)
          .collect();
+      let candidate_ids: Vec<_> = candidates.iter().map(|info| info.id.clone()).collect();
+      let local = if self.is_responsible_for(key) {
+         self.storage.retrieve(key).map(|entries| (self.local_info(), entries))
+      } else {
+         None
+      };
+
+      // Replicas that don't hold the key respond with closer nodes instead.
+      let responses = self
+         .receptions()
+         .of_kind(receptions::KindFilter::RetrieveResponse)
+         .from_senders(candidate_ids)
+         .during(time::Duration::seconds(self.configuration.network_timeout_s))
+         .filter_map(|rpc| match rpc.successfully_retrieved(key) {
+            Some(entries) => Some(Some((rpc.sender, Resources::strip_expirations(entries)))),
+            None => rpc.is_helping_retrieve(key).map(|_| None),
+         })
+         .take(candidates.len());
+
+      let rpc = Rpc::retrieve_authoritative(self.local_info(), key.clone());
+      for candidate in &candidates {
+         try!(self.send_skipping_mismatches(&rpc, &candidate.address));
+      }
+
+      for copy in local.into_iter().chain(responses.filter_map(|response| response)) {
+         if f(copy) {
+            break;
+         }
+      }
+      Ok(())
+   }
+
+   /// Whether two sets of entries are the same, regardless of order.
+   fn same_entries(a: &[storage::StorageEntry], b: &[storage::StorageEntry]) -> bool {
+      a.len() == b.len() && a.iter().all(|entry| b.contains(entry))
+   }
+
+   /// Estimates the amount of nodes in the network from the density of contacts around
+   /// this node: if the `k_factor` closest ones are within a fraction `f` of the hash space,
+   /// the network holds roughly `k_factor / f` nodes. If fewer than `k_factor` contacts are
+   /// known, the network is assumed to consist of those alone.
+   pub fn estimated_network_size(&self) -> usize {
+      let closest: Vec<_> = self.table.closest_others_to(&self.id).take(self.configuration.k_factor).collect();
+      if closest.len() < self.configuration.k_factor {
+         return closest.len() + 1;
+      }
+
+      let farthest = &closest[closest.len() - 1].id ^ &self.id;
+      let fraction = farthest.raw.iter().enumerate()
+         .fold(0f64, |sum, (i, &byte)| sum + byte as f64 * 2f64.powi(8 * i as i32 - hash::HASH_SIZE as i32));
+      let estimate = self.configuration.k_factor as f64 / fraction + 1.0;
+      if estimate >= usize::max_value() as f64 {
+         usize::max_value()
+      } else {
+         estimate as usize
+      }
+   }
+
+   /// Probe depth suited to the estimated network size. Lookups take a logarithmic
+   /// amount of steps, each of which queries `alpha` nodes. The depth never exceeds
+   /// half the network or twice the `k_factor`, and is at least `alpha`.
+   pub fn auto_probe_depth(&self) -> usize {
+      let size = self.estimated_network_size();
+      let steps = (size as f64).log2().ceil() as usize;
+      let depth = cmp::min(size / 2, cmp::min(2 * self.configuration.k_factor, steps * self.configuration.alpha));
+      cmp::max(self.configuration.alpha, depth)
+   }
+
+   /// Number of remote nodes in the routing table.
+   pub fn contact_count(&self) -> usize {
+      if self.configuration.insert_self_in_table {
+         self.table.len().saturating_sub(1)
+      } else {
+         self.table.len()
+      }
+   }
+
+   /// Reports whether this node believes itself to be among the `K_FACTOR` closest
+   /// nodes to a key, based solely on the contents of its routing table.
+   pub fn is_responsible_for(&self, key: &SubotaiHash) -> bool {
+      let own_distance = &self.id ^ key;
+      let closer_nodes = self.table
+         .closest_others_to(key)
+         .take(self.configuration.k_factor)
+         .filter(|info| (&info.id ^ key) < own_distance)
+         .count();
+      closer_nodes < self.configuration.k_factor
+   }
+
+   pub fn retrieve(&self, key: &SubotaiHash) -> SubotaiResult<Vec<storage::StorageEntry>> {
+      self.retrieve_with_expiry(key).map(Resources::strip_expirations)
+   }
+
+   /// Retrieves the value associated to a key only from the nodes responsible for it,
+   /// bypassing any cached copies along the way.
+   pub fn retrieve_authoritative(&self, key: &SubotaiHash) -> SubotaiResult<Vec<storage::StorageEntry>> {
+      self.retrieve_from(key, true, self.default_deadline()).map(Resources::strip_expirations)
+   }
+
+   /// Retrieves the value associated to a key, along with the expiration time of each
+   /// entry as reported by the node that provided it.
+   pub fn retrieve_with_expiry(&self, key: &SubotaiHash) -> SubotaiResult<Vec<(storage::StorageEntry, time::Tm)>> {
+      self.retrieve_from(key, false, self.default_deadline())
+   }
+
+   /// Like `retrieve`, but gives up at a particular point in time.
+   pub fn retrieve_with_deadline(&self, key: &SubotaiHash, deadline: time::SteadyTime) -> SubotaiResult<Vec<storage::StorageEntry>> {
+      self.retrieve_from(key, false, deadline).map(Resources::strip_expirations)
+   }
+
+   fn strip_expirations(entries: Vec<(storage::StorageEntry, time::Tm)>) -> Vec<storage::StorageEntry> {
+      entries.into_iter().map(|(entry, _)| entry).collect()
+   }
+
+   /// Retrieves the entries under a key or, if the retrieve wave can't find them, reports
+   /// the `k_factor` nodes closest to the key among those queried by the wave.
+   pub fn retrieve_or_closest(&self, key: &SubotaiHash) -> SubotaiResult<RetrieveOutcome> {
+      let mut frontier = Vec::new();
+      match self.retrieve_tracking_frontier(key, false, None, self.default_deadline(), &mut frontier) {
+         Ok(entries) => Ok(RetrieveOutcome::Found(Resources::strip_expirations(entries))),
+         Err(SubotaiError::UnresponsiveNetwork) => {
+            self.bound_candidates(key, &mut frontier);
+            frontier.truncate(self.configuration.k_factor);
+            Ok(RetrieveOutcome::NotFound(frontier))
+         },
+         Err(error) => Err(error),
+      }
+   }
+
+   fn retrieve_from(&self, key: &SubotaiHash, authoritative_only: bool, deadline: time::SteadyTime) -> SubotaiResult<Vec<(storage::StorageEntry, time::Tm)>> {
+      self.retrieve_tracking_frontier(key, authoritative_only, None, deadline, &mut Vec::new())
+   }
+
+   /// Retrieves only the entries under a key that were stored after a point in time.
+   pub fn retrieve_since(&self, key: &SubotaiHash, since: time::Tm) -> SubotaiResult<Vec<storage::StorageEntry>> {
+      self.retrieve_tracking_frontier(key, false, Some(since), self.default_deadline(), &mut Vec::new())
+         .map(Resources::strip_expirations)
+   }
+
+   /// Entries stored locally under a key, optionally only those stored after a point in time.
+   fn local_entries(&self, key: &SubotaiHash, since: Option<time::Tm>) -> Option<Vec<(storage::StorageEntry, time::Tm)>> {
+      match since {
+         Some(since) => self.storage.retrieve_since_with_expiry(key, &since),
+         None => self.storage.retrieve_with_expiry(key),
+      }
+   }
+
+   /// Retrieve wave, keeping track in `frontier` of every node queried so far.
+   fn retrieve_tracking_frontier(&self, 
+                                 key: &SubotaiHash, 
+                                 authoritative_only: bool, 
+                                 since: Option<time::Tm>,
+                                 deadline: time::SteadyTime, 
+                                 frontier: &mut Vec<routing::NodeInfo>) -> SubotaiResult<Vec<(storage::StorageEntry, time::Tm)>> {
+      // If the value is already present in our table, we are done early. Our own copy is only
+      // good enough for an authoritative retrieve if we are one of the replicas ourselves.
+      if !authoritative_only || self.is_responsible_for(key) {
+         if let Some(entries) = self.local_entries(key, since) {
+            return Ok(entries);
+         }
+      }
+
+      // A node off the grid can't reach the replicas, so there's no point in trying.
+      if let node::State::OffGrid = *self.state.read().unwrap() {
+         return Err(SubotaiError::OffGridError);
+      }
+
+      // We start with the closest K nodes we know about.
+      let mut closest = self.initial_candidates(key);
       let seeds: Vec<_> = closest.iter().cloned().take(self.configuration.alpha).collect();
       let mut cache_candidate: Option<routing::NodeInfo> = None;
 
-      let strategy = |responses: &[rpc::Rpc], queried: &[routing::NodeInfo]| -> WaveStrategy<Vec<storage::StorageEntry>> {
+      let strategy = |responses: &[rpc::Rpc], queried: &[routing::NodeInfo]| -> WaveStrategy<Vec<(storage::StorageEntry, time::Tm)>> {
+         frontier.clear();
+         frontier.extend(queried.iter().cloned());
+
          // If any parallel process, or the response from a slow node has retrieved the key,
-         // we need to break out early
-         if let Some(retrieved) = self.storage.retrieve(key) {
-            return WaveStrategy::Halt(retrieved);
+         // we need to break out early. Local copies may be stale caches, so an authoritative 
+         // retrieve only trusts the responses. Responses to this wave are cached locally
+         // with a short expiration, so they are preferred when present.
+         let found_in_responses = responses.iter().any(|rpc| rpc.successfully_retrieved_since(key, since.as_ref()).is_some());
+         if !authoritative_only && !found_in_responses {
+            if let Some(retrieved) = self.local_entries(key, since) {
+               return WaveStrategy::Halt(retrieved);
+            }
          }
          // We are interested in the combination of the nodes we knew about, plus the ones
          // we just learned from the responses, as long as we haven't queried them already.
@@ -274,36 +890,37 @@ impl Resources {
             .chain(former_closest)
             .filter(|info| !queried.contains(info) && &info.id != &self.id)
             .collect();
-         closest.sort_by(|info_a, info_b| (&info_a.id ^ key).cmp(&(&info_b.id ^ key)));
-         closest.dedup();
+         self.bound_candidates(key, &mut closest);
 
-         // The cache candidate is the closest node that hasn't found the value.
-         cache_candidate = closest.first().cloned();
+         // The cache candidate is the closest node that hasn't found the value. Authoritative
+         // retrieves don't cache, as that's exactly what they try to avoid, and neither do
+         // retrieves of the entries stored after a point in time, as they are incomplete.
+         if !authoritative_only && since.is_none() {
+            cache_candidate = closest.first().cloned();
+         }
        
          // If we found it, we cache the values and we're done.
-         if let Some(retrieved) = responses.iter().filter_map(|rpc| rpc.successfully_retrieved(key)).next() {
+         if let Some(retrieved) = responses.iter().filter_map(|rpc| rpc.successfully_retrieved_since(key, since.as_ref())).next() {
             if let Some(ref candidate) = cache_candidate {
-               let expiration = self.calculate_cache_expiration(&candidate.id, key);
-               for entry in &retrieved {
+               let cache_expiration = self.calculate_cache_expiration(&candidate.id, key);
+               for &(ref entry, expiration) in &retrieved {
+                  let expiration = cmp::min(cache_expiration, expiration);
                   let rpc = Rpc::store(self.local_info(), key.clone(), entry.clone(), rpc::SerializableTime::from(expiration));
-                  let packet = rpc.serialize();
-                  let _ = self.outbound.send_to(&packet, candidate.address);
+                  let _ = self.send(&rpc, &candidate.address);
                }
             }
             return WaveStrategy::Halt(retrieved);
          }
 
-         WaveStrategy::Continue(closest
-            .iter()
-            .filter(|info| !queried.contains(info) && &info.id != &self.id)
-            .cloned().take(self.configuration.alpha).collect()
-         )
+         WaveStrategy::Continue(self.next_to_query(key, &closest, queried))
       };
 
-      let rpc = Rpc::retrieve(self.local_info(), key.clone());
-      let timeout = time::Duration::seconds(3*self.configuration.network_timeout_s);
-
-      self.wave(seeds, strategy, rpc, timeout)
+      let rpc = match (authoritative_only, since) {
+         (true, _) => Rpc::retrieve_authoritative(self.local_info(), key.clone()),
+         (false, Some(since)) => Rpc::retrieve_since(self.local_info(), key.clone(), rpc::SerializableTime::from(since)),
+         (false, None) => Rpc::retrieve(self.local_info(), key.clone()),
+      };
+      self.wave(seeds, strategy, rpc, deadline)
    }
   
    ///// the expiration time drops substantially the further away the parent node is from the key, past
@@ -323,35 +940,65 @@ impl Resources {
    /// in the wave, outputs the next nodes to contact, and decides whether to stop 
    /// the wave by producing a Some(T) in its second return value.
    ///
-   /// The wave terminates when when the strategy function provides no new nodes, when a 
-   /// global timeout is reached, or when halt returns Some(T).
-   fn wave<T, S>(&self, seeds: Vec<routing::NodeInfo>, mut strategy: S, rpc: rpc::Rpc, timeout: time::Duration) -> SubotaiResult<T>
+   /// The wave terminates when when the strategy function provides no new nodes, when the
+   /// deadline is reached, or when halt returns Some(T).
+   pub fn wave<T, S>(&self, seeds: Vec<routing::NodeInfo>, strategy: S, rpc: rpc::Rpc, deadline: time::SteadyTime) -> SubotaiResult<T>
       where S: FnMut(&[rpc::Rpc], &[routing::NodeInfo]) -> WaveStrategy<T> {
+      self.cancellable_wave(seeds, strategy, rpc, deadline, None)
+   }
 
-      let deadline = time::SteadyTime::now() + timeout;
+   /// Like `wave`, but fails with `Cancelled` as soon as a flag is set, even in the 
+   /// middle of a round.
+   pub fn cancellable_wave<T, S>(&self, 
+                                 seeds: Vec<routing::NodeInfo>, 
+                                 mut strategy: S, 
+                                 rpc: rpc::Rpc, 
+                                 deadline: time::SteadyTime, 
+                                 cancel: Option<sync::Arc<sync::atomic::AtomicBool>>) -> SubotaiResult<T>
+      where S: FnMut(&[rpc::Rpc], &[routing::NodeInfo]) -> WaveStrategy<T> {
+      let cancelled = || cancel.as_ref().map_or(false, |cancel| cancel.load(sync::atomic::Ordering::SeqCst));
+
+      try!(self.register_wave());
       let mut nodes_to_query = seeds;
       let mut queried = Vec::<routing::NodeInfo>::new();
-      let packet = rpc.serialize();
 
-      // We loop as long as we haven't ran out of time and there is something to query.
-      while time::SteadyTime::now() < deadline && !nodes_to_query.is_empty() {
+      // We loop as long as we haven't ran out of time, there is something to query and
+      // we haven't been cancelled.
+      while time::SteadyTime::now() < deadline && !nodes_to_query.is_empty() && !cancelled() {
          // Here, we only know who to listen to, for how long, and the number of 
          // responses. Whether or not a response is interesting is down to the 
          // strategy function.
-         let senders: Vec<SubotaiHash> = nodes_to_query.iter().map(|info| &info.id).cloned().collect();
-         let responses = self.receptions()
+         // We always wait for at least one response, even if the configuration (e.g. an
+         // impatience equal to alpha) would have us advance without waiting at all.
+         let senders: Vec<NodeId> = nodes_to_query.iter().map(|info| &info.id).cloned().collect();
+         let responses_to_advance = cmp::max(1, cmp::min(nodes_to_query.len(), self.configuration.min_responses_to_advance));
+         let mut responses = self.receptions()
             .from_senders(senders)
             .during(time::Duration::seconds(self.configuration.network_timeout_s))
-            .take(cmp::min(nodes_to_query.len(), usize::saturating_sub(self.configuration.alpha, self.configuration.impatience)));
+            .until(deadline);
+         if let Some(ref cancel) = cancel {
+            responses = responses.cancelled_by(cancel.clone());
+         }
+         let responses = responses.take(responses_to_advance);
       
          // We query all the nodes with the wave RPC, and collect the responses, 
-         // ignoring any slackers based on the IMPATIENCE factor.
+         // ignoring any slackers once we have enough to advance. Nodes we can't 
+         // reach from our address family are skipped.
          for node in &nodes_to_query {
-            try!(self.outbound.send_to(&packet, node.address));
+            try!(self.send_skipping_mismatches(&rpc, &node.address));
          }
-         queried.append(&mut nodes_to_query);
          let responses: Vec<_> = responses.collect();
 
+         // If the round timed out, nodes that didn't respond in it count as unresponsive.
+         let timed_out = responses.len() < responses_to_advance;
+         for node in &nodes_to_query {
+            let responded = responses.iter().any(|rpc| rpc.sender.id == node.id);
+            if responded || timed_out {
+               self.record_liveness(&node.id, responded);
+            }
+         }
+         queried.append(&mut nodes_to_query);
+
          // We return early if Halt produces a value. Otherwise, we calculate the next
          // nodes to query and continue.
          match strategy(&responses, &queried) {
@@ -359,7 +1006,32 @@ impl Resources {
             WaveStrategy::Halt(result) => return Ok(result),
          }
       }
-      Err(SubotaiError::UnresponsiveNetwork)
+
+      if cancelled() {
+         Err(SubotaiError::Cancelled)
+      } else {
+         Err(SubotaiError::UnresponsiveNetwork)
+      }
+   }
+
+   /// Registers the start of a wave, failing if the maximum amount of
+   /// waves per second has already been reached.
+   fn register_wave(&self) -> SubotaiResult<()> {
+      if self.configuration.max_waves_per_sec == 0 {
+         return Ok(());
+      }
+
+      let now = time::SteadyTime::now();
+      let mut recent_waves = self.recent_waves.lock().unwrap();
+      while recent_waves.front().map_or(false, |&start| now - start >= time::Duration::seconds(1)) {
+         recent_waves.pop_front();
+      }
+
+      if recent_waves.len() >= self.configuration.max_waves_per_sec {
+         return Err(SubotaiError::RateLimited);
+      }
+      recent_waves.push_back(now);
+      Ok(())
    }
 
    /// Probes a random node in a bucket, refreshing it.
@@ -391,84 +1063,455 @@ impl Resources {
       }
       
       for response in responses {
+         self.record_liveness(&response.sender.id, true);
          nodes.retain(|node| node.id != response.sender.id);
       }
 
       for unresponsive_node in nodes {
          self.table.remove_node(&unresponsive_node.id);
+         self.forget_peer(&unresponsive_node.id);
       }
 
       Ok(())
    }
 
-   /// Stores entries associated to a key with a single RPC.
-   pub fn mass_store(&self, key: SubotaiHash, entries: Vec<(storage::StorageEntry, time::Tm)>) -> SubotaiResult<()> {
+   /// Stores entries associated to a key with a single RPC, and reports which of the 
+   /// storage nodes accepted all, some or none of the entries.
+   pub fn mass_store(&self, key: SubotaiHash, entries: Vec<(storage::StorageEntry, time::Tm)>) -> SubotaiResult<MassStoreReport> {
       if let node::State::OffGrid = *self.state.read().unwrap() {
          return Err(SubotaiError::OffGridError);
       }
       let storage_candidates = try!(self.probe(&key, self.configuration.k_factor));
+      let candidate_ids: Vec<_> = storage_candidates.iter().map(|info| &info.id).cloned().collect();
       let cloned_key = key.clone();
 
-      // At least one third of the store RPCs must succeed.
       let responses = self
          .receptions()
          .of_kind(receptions::KindFilter::StoreResponse)
+         .from_senders(candidate_ids.clone())
          .during(time::Duration::seconds(self.configuration.network_timeout_s))
-         .filter(|rpc| rpc.successfully_stored(&cloned_key))
-         .take(self.configuration.k_factor / 3);
+         .filter_map(|rpc| rpc.is_store_response(&cloned_key).map(|result| (rpc.sender.id, result)))
+         .take(candidate_ids.len());
       
       let collection: Vec<_> = entries.into_iter().map(|(entry, time)| (entry, rpc::SerializableTime::from(time))).collect();
       let rpc = Rpc::mass_store(self.local_info(), key, collection );
-      let packet = rpc.serialize();
 
       for candidate in &storage_candidates {
-         try!(self.outbound.send_to(&packet, candidate.address));
+         try!(self.send_skipping_mismatches(&rpc, &candidate.address));
       }
 
-      if responses.count() == self.configuration.k_factor / 3 {
-         Ok(())
-      } else {
-         Err(SubotaiError::UnresponsiveNetwork)
+      let mut report = MassStoreReport { fully_accepted: Vec::new(), partially: Vec::new(), failed: Vec::new() };
+      for (id, result) in responses {
+         match result {
+            storage::StoreResult::Success => report.fully_accepted.push(id),
+            storage::StoreResult::PartialSuccess => report.partially.push(id),
+            _ => report.failed.push(id),
+         }
       }
+
+      // Candidates that never responded are considered failed as well.
+      for id in candidate_ids {
+         if !report.fully_accepted.contains(&id) && !report.partially.contains(&id) && !report.failed.contains(&id) {
+            report.failed.push(id);
+         }
+      }
+      Ok(report)
    }
 
    pub fn store(&self, key: SubotaiHash, entry: storage::StorageEntry, expiration: time::Tm) -> SubotaiResult<()> {
+      self.store_with_hint(key, entry, expiration, Vec::new())
+   }
+
+   /// Like `store`, but also sends the entry to any nodes in the placement hint that 
+   /// are present in the routing table. Hinted nodes don't count towards the quorum.
+   pub fn store_with_hint(&self, 
+                          key: SubotaiHash, 
+                          entry: storage::StorageEntry, 
+                          expiration: time::Tm, 
+                          placement_hint: Vec<NodeId>) -> SubotaiResult<()> {
+      let rpc = Rpc::store(self.local_info(), key.clone(), entry, rpc::SerializableTime::from(expiration));
+      let deadline = self.default_deadline() + time::Duration::seconds(self.configuration.network_timeout_s);
+      self.send_store(key, rpc, placement_hint, deadline).map(|_| ())
+   }
+
+   /// Splits a blob into chunks no bigger than `max_storage_blob_size`, stores each of
+   /// them under a key derived from the original key and the chunk index, and finally
+   /// stores a manifest under the original key listing the content hash of every chunk.
+   ///
+   /// Manifests are no bigger than a chunk either, so blobs needing more chunks than fit
+   /// in one are split in a tree of manifests, each stored under a key derived from its
+   /// parent's key and its index in it.
+   pub fn store_large(&self, key: SubotaiHash, data: &[u8], expiration: time::Tm) -> SubotaiResult<()> {
+      let chunk_size = cmp::max(1, self.configuration.max_storage_blob_size);
+      let fanout = self.manifest_fanout();
+
+      // Each manifest at the top of the tree covers `span` bytes of the blob.
+      let mut span = chunk_size;
+      while data.len() > span.saturating_mul(fanout) {
+         span = span.saturating_mul(fanout);
+      }
+      self.store_manifest(key, data, span, expiration).map(|_| ())
+   }
+
+   /// Maximum number of chunks listed by a single manifest, so that it's no bigger than
+   /// `max_storage_blob_size`. It's never lower than two, so the manifest tree is finite.
+   pub fn manifest_fanout(&self) -> usize {
+      cmp::max(2, self.configuration.max_storage_blob_size / hash::HASH_SIZE_BYTES)
+   }
+
+   /// Stores the manifest for a part of a large blob, after storing everything it refers to.
+   /// Manifests covering more than a chunk of the blob per entry refer to other manifests.
+   fn store_manifest(&self, key: SubotaiHash, data: &[u8], span: usize, expiration: time::Tm) -> SubotaiResult<storage::StorageEntry> {
+      let chunk_size = cmp::max(1, self.configuration.max_storage_blob_size);
+      let mut chunk_hashes = Vec::with_capacity(data.len() / span + 1);
+      for (index, part) in data.chunks(span).enumerate() {
+         let part_key = Resources::chunk_key(&key, index);
+         let entry = if span <= chunk_size {
+            let entry = storage::StorageEntry::Blob(part.to_vec());
+            try!(self.store(part_key, entry.clone(), expiration));
+            entry
+         } else {
+            try!(self.store_manifest(part_key, part, span / self.manifest_fanout(), expiration))
+         };
+         chunk_hashes.push(entry.content_hash());
+      }
+
+      let manifest = storage::StorageEntry::Manifest { length: data.len(), chunks: chunk_hashes };
+      try!(self.store(key, manifest.clone(), expiration));
+      Ok(manifest)
+   }
+
+   /// Retrieves the manifest stored under a key by `store_large`, and reassembles the
+   /// blob from its chunks. Fails with `MissingChunk` identifying the first chunk that
+   /// couldn't be retrieved or didn't match its hash in the manifest.
+   pub fn retrieve_large(&self, key: &SubotaiHash) -> SubotaiResult<Vec<u8>> {
+      let manifest = try!(self.retrieve(key)).into_iter().filter_map(|entry| match entry {
+         storage::StorageEntry::Manifest { length, chunks } => Some((length, chunks)),
+         _ => None,
+      }).next();
+      let (length, chunk_hashes) = match manifest {
+         Some(manifest) => manifest,
+         None => return Err(SubotaiError::MissingManifest),
+      };
+
+      let mut data = Vec::with_capacity(length);
+      let mut chunks_read = 0;
+      try!(self.read_manifest(key, &chunk_hashes, &mut data, &mut chunks_read));
+      Ok(data)
+   }
+
+   /// Appends the part of a large blob described by a manifest to `data`, following any
+   /// nested manifests. Missing chunks are identified by their index in the whole blob.
+   fn read_manifest(&self, 
+                    key: &SubotaiHash, 
+                    chunk_hashes: &[SubotaiHash], 
+                    data: &mut Vec<u8>, 
+                    chunks_read: &mut usize) -> SubotaiResult<()> {
+      for (index, chunk_hash) in chunk_hashes.iter().enumerate() {
+         let part_key = Resources::chunk_key(key, index);
+         let entries = self.retrieve(&part_key).unwrap_or_else(|_| Vec::new());
+         match entries.into_iter().find(|entry| &entry.content_hash() == chunk_hash) {
+            Some(storage::StorageEntry::Blob(chunk)) => {
+               data.extend_from_slice(&chunk);
+               *chunks_read += 1;
+            },
+            Some(storage::StorageEntry::Manifest { chunks, .. }) => try!(self.read_manifest(&part_key, &chunks, data, chunks_read)),
+            _ => return Err(SubotaiError::MissingChunk { index: *chunks_read }),
+         }
+      }
+      Ok(())
+   }
+
+   /// Key under which a chunk of a large blob is stored: the hash of the original key
+   /// followed by the big endian chunk index.
+   pub fn chunk_key(key: &SubotaiHash, index: usize) -> SubotaiHash {
+      let index = index as u64;
+      let mut content = key.to_bytes().to_vec();
+      content.extend((0..8).rev().map(|byte| (index >> (byte * 8)) as u8));
+      SubotaiHash::from_content(&content)
+   }
+
+   /// Immediately stores all local entries under a key in its current replica set, 
+   /// instead of waiting for the hourly republish. Fails with `UnresponsiveNetwork`
+   /// if no replica accepted all of them. Nothing is sent if the key holds no local entries.
+   pub fn republish_key(&self, key: SubotaiHash) -> SubotaiResult<()> {
+      let entries = match self.storage.retrieve_with_expiry(&key) {
+         Some(entries) => entries,
+         None => return Ok(()),
+      };
+
+      let report = try!(self.mass_store(key, entries));
+      if report.fully_accepted.is_empty() {
+         Err(SubotaiError::UnresponsiveNetwork)
+      } else {
+         Ok(())
+      }
+   }
+
+   /// Like `store`, but waits for every storage node to respond (or time out) and reports
+   /// how many accepted the entry, instead of enforcing the storage quorum.
+   pub fn store_detailed(&self, key: SubotaiHash, entry: storage::StorageEntry, expiration: time::Tm) -> SubotaiResult<StoreReport> {
       if let node::State::OffGrid = *self.state.read().unwrap() {
          return Err(SubotaiError::OffGridError);
       }
 
       let storage_candidates = try!(self.probe(&key, self.configuration.k_factor));
+      let candidate_ids: Vec<_> = storage_candidates.iter().map(|info| &info.id).cloned().collect();
       let cloned_key = key.clone();
 
-      // At least one third of the store RPCs must succeed.
       let responses = self
          .receptions()
          .of_kind(receptions::KindFilter::StoreResponse)
+         .from_senders(candidate_ids.clone())
          .during(time::Duration::seconds(self.configuration.network_timeout_s))
-         .filter(|rpc| rpc.successfully_stored(&cloned_key))
-         .take(self.configuration.k_factor / 3);
+         .filter_map(|rpc| rpc.is_store_response(&cloned_key))
+         .take(candidate_ids.len());
 
       let rpc = Rpc::store(self.local_info(), key, entry, rpc::SerializableTime::from(expiration));
-      let packet = rpc.serialize();
+      for candidate in &storage_candidates {
+         try!(self.send_skipping_mismatches(&rpc, &candidate.address));
+      }
+
+      Ok(StoreReport {
+         contacted : storage_candidates.len(),
+         succeeded : responses.filter(|result| *result == storage::StoreResult::Success).count(),
+      })
+   }
+
+   /// Like `store`, but gives up at a particular point in time.
+   pub fn store_with_deadline(&self, key: SubotaiHash, entry: storage::StorageEntry, expiration: time::Tm, deadline: time::SteadyTime) -> SubotaiResult<()> {
+      let rpc = Rpc::store(self.local_info(), key.clone(), entry, rpc::SerializableTime::from(expiration));
+      self.send_store(key, rpc, Vec::new(), deadline).map(|_| ())
+   }
+
+   /// Stores an entry only if the key holds no entries yet in the network. Returns
+   /// whether the entry was stored, or if it was already present in some replica.
+   pub fn store_if_absent(&self, key: SubotaiHash, entry: storage::StorageEntry, expiration: time::Tm) -> SubotaiResult<bool> {
+      let rpc = Rpc::store_if_absent(self.local_info(), key.clone(), entry, rpc::SerializableTime::from(expiration));
+      let deadline = self.default_deadline() + time::Duration::seconds(self.configuration.network_timeout_s);
+      self.send_store(key, rpc, Vec::new(), deadline)
+   }
+
+   /// Stores an entry only if the key holds fewer than `max_entries` entries in the network.
+   /// Returns whether the entry was appended, or if some replica was already full.
+   pub fn append_bounded(&self, key: SubotaiHash, entry: storage::StorageEntry, expiration: time::Tm, max_entries: usize) -> SubotaiResult<bool> {
+      let rpc = Rpc::append_bounded(self.local_info(), key.clone(), entry, rpc::SerializableTime::from(expiration), max_entries);
+      let deadline = self.default_deadline() + time::Duration::seconds(self.configuration.network_timeout_s);
+      self.send_store(key, rpc, Vec::new(), deadline)
+   }
+
+   /// Removes an entry locally and from the replicas of its key. Removing an entry that
+   /// isn't present anywhere is not an error. Fails with `UnresponsiveNetwork` only if 
+   /// none of the replicas acknowledged the removal.
+   pub fn remove(&self, key: SubotaiHash, entry: storage::StorageEntry) -> SubotaiResult<()> {
+      if let node::State::OffGrid = *self.state.read().unwrap() {
+         return Err(SubotaiError::OffGridError);
+      }
+      self.storage.remove(&key, &entry);
+
+      let candidates: Vec<_> = try!(self.probe(&key, self.configuration.k_factor))
+         .into_iter()
+         .filter(|info| info.id != self.id)
+         .collect();
+      if candidates.is_empty() {
+         return Ok(());
+      }
+      let candidate_ids: Vec<_> = candidates.iter().map(|info| &info.id).cloned().collect();
+      let cloned_key = key.clone();
+
+      let responses = self
+         .receptions()
+         .of_kind(receptions::KindFilter::RemoveResponse)
+         .from_senders(candidate_ids.clone())
+         .during(time::Duration::seconds(self.configuration.network_timeout_s))
+         .filter_map(|rpc| rpc.is_remove_response(&cloned_key))
+         .take(candidate_ids.len());
+
+      let rpc = Rpc::remove(self.local_info(), key, entry);
+      for candidate in &candidates {
+         try!(self.send_skipping_mismatches(&rpc, &candidate.address));
+      }
+
+      match responses.count() {
+         0 => Err(SubotaiError::UnresponsiveNetwork),
+         _ => Ok(()),
+      }
+   }
+
+   /// Counts how many replicas of a key actually hold a specific entry, by asking each
+   /// of them directly. The local node is included in the count if it holds the entry.
+   /// Replicas that don't answer in time are assumed not to hold it.
+   pub fn verify_replication(&self, key: SubotaiHash, entry: storage::StorageEntry) -> SubotaiResult<usize> {
+      if let node::State::OffGrid = *self.state.read().unwrap() {
+         return Err(SubotaiError::OffGridError);
+      }
+      let held_locally = match self.storage.retrieve(&key) {
+         Some(entries) => entries.contains(&entry),
+         None => false,
+      };
+
+      let candidates: Vec<_> = try!(self.probe(&key, self.configuration.k_factor))
+         .into_iter()
+         .filter(|info| info.id != self.id)
+         .collect();
+      let local_count = if held_locally { 1 } else { 0 };
+      if candidates.is_empty() {
+         return Ok(local_count);
+      }
+      let candidate_ids: Vec<_> = candidates.iter().map(|info| &info.id).cloned().collect();
+      let cloned_key = key.clone();
+      let entry_hash = entry.content_hash();
+
+      let responses = self
+         .receptions()
+         .of_kind(receptions::KindFilter::HasEntryResponse)
+         .from_senders(candidate_ids.clone())
+         .during(time::Duration::seconds(self.configuration.network_timeout_s))
+         .filter_map(|rpc| rpc.is_has_entry_response(&cloned_key, &entry_hash))
+         .take(candidate_ids.len());
+
+      let rpc = Rpc::has_entry(self.local_info(), key, entry);
+      for candidate in &candidates {
+         try!(self.send_skipping_mismatches(&rpc, &candidate.address));
+      }
+
+      Ok(local_count + responses.filter(|&present| present).count())
+   }
+
+   /// Sends a store RPC to the replicas of a key and waits for the storage quorum. For 
+   /// bounded stores (`store_if_absent` and `append_bounded`), all replica responses are 
+   /// collected, and false is returned if the quorum wasn't reached because some replicas 
+   /// refused the entry for the key being already present or full.
+   fn send_store(&self, key: SubotaiHash, rpc: Rpc, placement_hint: Vec<NodeId>, deadline: time::SteadyTime) -> SubotaiResult<bool> {
+      if let node::State::OffGrid = *self.state.read().unwrap() {
+         return Err(SubotaiError::OffGridError);
+      }
+
+      let probe_deadline = cmp::min(deadline, self.default_deadline());
+      let k_factor = self.configuration.k_factor;
+      let mut storage_candidates = try!(self.probe_closest_with_deadline(&key, k_factor, k_factor, probe_deadline));
+      let candidate_ids: Vec<_> = storage_candidates.iter().map(|info| &info.id).cloned().collect();
+      let hinted: Vec<_> = placement_hint
+         .iter()
+         .filter(|id| !candidate_ids.contains(id))
+         .filter_map(|id| self.table.specific_node(id))
+         .collect();
+      storage_candidates.extend(hinted);
+      let cloned_key = key.clone();
+
+      let responses = self
+         .receptions()
+         .of_kind(receptions::KindFilter::StoreResponse)
+         .from_senders(candidate_ids.clone())
+         .during(time::Duration::seconds(self.configuration.network_timeout_s))
+         .until(deadline)
+         .filter_map(|rpc| rpc.is_store_response(&cloned_key).map(|result| (rpc.sender.id, result)))
+         .take(candidate_ids.len());
 
       for candidate in &storage_candidates {
-         try!(self.outbound.send_to(&packet, candidate.address));
+         try!(self.send_skipping_mismatches(&rpc, &candidate.address));
       }
 
-      if responses.count() == self.configuration.k_factor / 3 {
-         Ok(())
-      } else {
+      let quorum = self.store_quorum();
+      if quorum == 0 {
+         return Ok(true);
+      }
+
+      let bounded = match rpc.kind {
+         rpc::Kind::Store(ref payload) => payload.max_entries.is_some(),
+         _ => false,
+      };
+      let mut successes = 0;
+      let mut refusals = 0;
+      let mut results = Vec::new();
+      for (id, result) in responses {
+         match result {
+            storage::StoreResult::Success => successes += 1,
+            storage::StoreResult::AlreadyPresent |
+            storage::StoreResult::KeyGroupFull if bounded => refusals += 1,
+            _ => (),
+         }
+         results.push((id, result));
+         if successes == quorum && !bounded {
+            return Ok(true);
+         }
+      }
+
+      if successes >= quorum {
+         Ok(true)
+      } else if refusals > 0 {
+         Ok(false)
+      } else if results.is_empty() {
          Err(SubotaiError::UnresponsiveNetwork)
+      } else {
+         Err(SubotaiError::StoreQuorumFailed { results: results })
       }
    }
 
-   pub fn revert_conflicts_for_sender(&self, sender_id: &SubotaiHash) {
+   /// Number of replicas that must accept a store for it to succeed, derived from
+   /// `Configuration::store_quorum`.
+   pub fn store_quorum(&self) -> usize {
+      self.configuration.store_quorum_replicas()
+   }
+
+   /// Stores entries to up to `extra_replicas` nodes just beyond the `k_factor` closest 
+   /// to the key, which form its usual replica set. Returns how many of them accepted 
+   /// all entries.
+   pub fn reinforce(&self, key: SubotaiHash, entries: Vec<(storage::StorageEntry, time::Tm)>, extra_replicas: usize) -> SubotaiResult<usize> {
+      if let node::State::OffGrid = *self.state.read().unwrap() {
+         return Err(SubotaiError::OffGridError);
+      }
+      let closest = try!(self.probe_closest(&key, self.configuration.k_factor, self.configuration.k_factor + extra_replicas));
+      let extra_candidates: Vec<_> = closest
+         .into_iter()
+         .filter(|info| info.id != self.id)
+         .skip(self.configuration.k_factor)
+         .take(extra_replicas)
+         .collect();
+      let candidate_ids: Vec<_> = extra_candidates.iter().map(|info| &info.id).cloned().collect();
+      let cloned_key = key.clone();
+
+      let responses = self
+         .receptions()
+         .of_kind(receptions::KindFilter::StoreResponse)
+         .from_senders(candidate_ids.clone())
+         .during(time::Duration::seconds(self.configuration.network_timeout_s))
+         .filter_map(|rpc| rpc.is_store_response(&cloned_key))
+         .take(candidate_ids.len());
+
+      let collection: Vec<_> = entries.into_iter().map(|(entry, time)| (entry, rpc::SerializableTime::from(time))).collect();
+      let rpc = Rpc::mass_store(self.local_info(), key, collection);
+
+      for candidate in &extra_candidates {
+         try!(self.send_skipping_mismatches(&rpc, &candidate.address));
+      }
+
+      Ok(responses.filter(|result| *result == storage::StoreResult::Success).count())
+   }
+
+   /// Republishes every locally stored entry, waiting for the storage quorum
+   /// on each key. Every key is attempted even if some of them fail.
+   pub fn flush(&self) -> SubotaiResult<()> {
+      self.storage.mark_all_as_ready();
+      let mut result = Ok(());
+      for (key, entries) in self.storage.get_all_ready_entries() {
+         match self.mass_store(key, entries) {
+            Ok(ref report) if report.fully_accepted.len() >= self.store_quorum() => (),
+            Ok(_) => result = Err(SubotaiError::UnresponsiveNetwork),
+            Err(error) => result = Err(error),
+         }
+      }
+      result
+   }
+
+   pub fn revert_conflicts_for_sender(&self, sender_id: &NodeId) {
       if let Some((index, _)) = 
          self.conflicts.lock().unwrap().iter()
          .enumerate()
          .find(|&(_,&routing::EvictionConflict{ref evicted, ..})| sender_id == &evicted.id )
       {
          let conflict = self.conflicts.lock().unwrap().remove(index);
+         self.forget_peer(&conflict.evictor.id);
          self.table.revert_conflict(conflict);
       }
    }
@@ -476,10 +1519,11 @@ impl Resources {
    pub fn process_incoming_rpc(&self, mut rpc: Rpc, source: net::SocketAddr) -> SubotaiResult<()>{
       rpc.sender.address.set_ip(source.ip());
       let sender = rpc.sender.clone();
+      self.rpc_stats.lock().unwrap().entry(rpc.kind.name()).or_insert((0, 0)).1 += 1;
 
       let result = match rpc.kind {
-         rpc::Kind::Ping                           => self.handle_ping(sender),
-         rpc::Kind::PingResponse                   => self.handle_ping_response(sender),
+         rpc::Kind::Ping(ref payload)              => self.handle_ping(payload.clone(), sender),
+         rpc::Kind::PingResponse(ref payload)      => self.handle_ping_response(payload.clone(), sender),
          rpc::Kind::Locate(ref payload)            => self.handle_locate(payload.clone(), sender),
          rpc::Kind::LocateResponse(ref payload)    => self.handle_locate_response(payload.clone()),
          rpc::Kind::Probe(ref payload)             => self.handle_probe(payload.clone(), sender),
@@ -487,6 +1531,10 @@ impl Resources {
          rpc::Kind::MassStore(ref payload)         => self.handle_mass_store(payload.clone(), sender),
          rpc::Kind::Retrieve(ref payload)          => self.handle_retrieve(payload.clone(), sender),
          rpc::Kind::RetrieveResponse(ref payload)  => self.handle_retrieve_response(payload.clone()),
+         rpc::Kind::NearestKey(ref payload)        => self.handle_nearest_key(payload.clone(), sender),
+         rpc::Kind::Digest(ref payload)            => self.handle_digest(payload.clone(), sender),
+         rpc::Kind::Remove(ref payload)            => self.handle_remove(payload.clone(), sender),
+         rpc::Kind::HasEntry(ref payload)          => self.handle_has_entry(payload.clone(), sender),
          _ => Ok(()),
       };
       self.update_table(rpc.sender.clone());
@@ -494,39 +1542,89 @@ impl Resources {
       result
    }
 
-   fn handle_ping(&self, sender: routing::NodeInfo) -> SubotaiResult<()> {
+   fn handle_ping(&self, payload: sync::Arc<rpc::PingPayload>, sender: routing::NodeInfo) -> SubotaiResult<()> {
+      self.record_peer_version(&sender.id, &payload.version);
+      self.record_clock_offset(&sender.id, &payload.sent_at);
       let rpc = Rpc::ping_response(self.local_info());
-      let packet = rpc.serialize();
-      try!(self.outbound.send_to(&packet, sender.address));
+      try!(self.send(&rpc, &sender.address));
       Ok(())
    }
 
+   /// Whether the node refuses incoming stores and stops republishing its entries.
+   pub fn is_read_only(&self) -> bool {
+      self.read_only.load(sync::atomic::Ordering::SeqCst)
+   }
+
+   /// Broadcasts a change to the stored data. Unlike other network updates, it's dropped
+   /// rather than waiting if a subscriber lags behind, so slow subscribers never stall
+   /// the reception of RPCs.
+   fn notify_data_change(&self, update: NetworkUpdate) {
+      let _ = self.network_updates.lock().unwrap().try_broadcast(update);
+   }
+
+   /// Whether a store to a key should be refused because this node isn't among its replicas.
+   fn rejects_store_for(&self, key: &SubotaiHash) -> bool {
+      self.configuration.reject_foreign_stores && !self.is_responsible_for(key)
+   }
+
    fn handle_store(&self, payload: sync::Arc<rpc::StorePayload>,  sender: routing::NodeInfo) -> SubotaiResult<()> {
-      let store_result = self.storage.store(&payload.key, 
-                                            &payload.entry,
-                                            &time::Tm::from(payload.expiration.clone()));
+      let offset = self.record_clock_offset(&sender.id, &payload.sent_at);
+      let store_result = if self.is_read_only() {
+         storage::StoreResult::ReadOnly
+      } else if self.rejects_store_for(&payload.key) {
+         storage::StoreResult::NotResponsible
+      } else {
+         let expiration = time::Tm::from(payload.expiration.clone()) + offset;
+         match payload.max_entries {
+            Some(max_entries) => self.storage.append_bounded(&payload.key, &payload.entry, &expiration, max_entries),
+            None => self.storage.store(&payload.key, &payload.entry, &expiration),
+         }
+      };
+      if store_result == storage::StoreResult::Success {
+         self.notify_data_change(NetworkUpdate::StoredEntry(payload.key.clone()));
+      }
       let rpc = Rpc::store_response(self.local_info(), payload.key.clone(), store_result);
-      let packet = rpc.serialize();
-      try!(self.outbound.send_to(&packet, sender.address));
+      try!(self.send(&rpc, &sender.address));
 
       Ok(())
    }
 
    fn handle_mass_store(&self, payload: sync::Arc<rpc::MassStorePayload>, sender: routing::NodeInfo) -> SubotaiResult<()> {
-      
-      let all_stores_succeeded = payload.entries_and_expirations.iter().all(|&(ref entry, ref expiration)| {
-         self.storage.store(&payload.key, entry, &time::Tm::from(expiration.clone())) == storage::StoreResult::Success
-      });
+      let offset = self.record_clock_offset(&sender.id, &payload.sent_at);
+      if self.is_read_only() {
+         let rpc = Rpc::store_response(self.local_info(), payload.key.clone(), storage::StoreResult::ReadOnly);
+         return self.send(&rpc, &sender.address);
+      }
+      if self.rejects_store_for(&payload.key) {
+         let rpc = Rpc::store_response(self.local_info(), payload.key.clone(), storage::StoreResult::NotResponsible);
+         return self.send(&rpc, &sender.address);
+      }
 
-      let store_result = if all_stores_succeeded { 
+      // The whole batch is rejected if it doesn't fit, rather than storing as much as possible.
+      let entries: Vec<_> = payload.entries_and_expirations.iter().map(|&(ref entry, _)| entry.clone()).collect();
+      if !self.storage.can_accept_batch(&payload.key, &entries) {
+         let rpc = Rpc::store_response(self.local_info(), payload.key.clone(), storage::StoreResult::MassStoreFailed);
+         return self.send(&rpc, &sender.address);
+      }
+
+      let results: Vec<_> = payload.entries_and_expirations.iter().map(|&(ref entry, ref expiration)| {
+         self.storage.store(&payload.key, entry, &(time::Tm::from(expiration.clone()) + offset))
+      }).collect();
+
+      // A complete failure is reported with the reason the first entry was rejected.
+      let store_result = if results.iter().all(|result| *result == storage::StoreResult::Success) {
          storage::StoreResult::Success 
-      } else { 
-         storage::StoreResult::MassStoreFailed 
+      } else if results.iter().any(|result| *result == storage::StoreResult::Success) {
+         storage::StoreResult::PartialSuccess 
+      } else {
+         results[0].clone()
       };
+      if results.contains(&storage::StoreResult::Success) {
+         self.notify_data_change(NetworkUpdate::StoredEntry(payload.key.clone()));
+      }
 
       let rpc = Rpc::store_response(self.local_info(), payload.key.clone(), store_result);
-      let packet = rpc.serialize();
-      try!(self.outbound.send_to(&packet, sender.address));
+      try!(self.send(&rpc, &sender.address));
 
       Ok(())
    }
@@ -535,44 +1633,162 @@ impl Resources {
       // We respond with K_FACTOR nodes plus one, because we might be including the identity of
       // the probing node, and the probing node is interested in K_FACTOR others.
       let closest: Vec<_> = self.table
-         .closest_nodes_to(&payload.id_to_probe)
+         .closest_others_to(&payload.id_to_probe)
          .take(self.configuration.k_factor + 1)
          .collect();
 
       let rpc = Rpc::probe_response(self.local_info(),
                                     closest, 
                                     payload.id_to_probe.clone());
-      let packet = rpc.serialize();
-      try!(self.outbound.send_to(&packet, sender.address));
+      try!(self.send(&rpc, &sender.address));
       Ok(())
    }
 
-   fn handle_ping_response(&self, sender: routing::NodeInfo) -> SubotaiResult<()> {
+   fn handle_ping_response(&self, payload: sync::Arc<rpc::PingPayload>, sender: routing::NodeInfo) -> SubotaiResult<()> {
+      self.record_peer_version(&sender.id, &payload.version);
+      self.record_clock_offset(&sender.id, &payload.sent_at);
       self.revert_conflicts_for_sender(&sender.id);
       Ok(())
    }
 
+   /// Whether information about a peer is worth keeping, which is only the case for
+   /// nodes in the routing table, or about to be added to it by the RPC being handled.
+   fn tracks_peer(&self, id: &NodeId) -> bool {
+      self.table.would_store(id)
+   }
+
+   /// Forgets everything recorded about a peer that left the routing table.
+   pub fn forget_peer(&self, id: &NodeId) {
+      self.peer_versions.lock().unwrap().remove(id);
+      self.clock_offsets.lock().unwrap().remove(id);
+      self.liveness.lock().unwrap().remove(id);
+   }
+
+   fn record_peer_version(&self, id: &NodeId, version: &str) {
+      if self.tracks_peer(id) {
+         self.peer_versions.lock().unwrap().insert(id.clone(), version.to_owned());
+      }
+   }
+
+   /// Records how far ahead the local clock is from a peer's, based on the time at which
+   /// it sent an RPC. Transmission delays are assumed negligible compared to the skew.
+   /// Adding the returned offset to a time reported by the peer translates it to local time.
+   fn record_clock_offset(&self, id: &NodeId, sent_at: &rpc::SerializableTime) -> time::Duration {
+      let offset = time::now() - time::Tm::from(sent_at.clone());
+      if self.tracks_peer(id) {
+         self.clock_offsets.lock().unwrap().insert(id.clone(), offset);
+      }
+      offset
+   }
+
+   /// Records whether a contact responded to a liveness check, forgetting
+   /// the oldest check once the reliability window is full.
+   fn record_liveness(&self, id: &NodeId, responded: bool) {
+      if !self.tracks_peer(id) {
+         return;
+      }
+      let mut liveness = self.liveness.lock().unwrap();
+      let history = liveness.entry(id.clone()).or_insert_with(VecDeque::new);
+      if history.len() == node::RELIABILITY_WINDOW {
+         history.pop_front();
+      }
+      history.push_back(responded);
+   }
+
+   /// Fraction of the recorded liveness checks a contact responded to.
+   pub fn reliability(&self, id: &NodeId) -> Option<f64> {
+      let liveness = self.liveness.lock().unwrap();
+      liveness.get(id).map(|history| {
+         history.iter().filter(|&&responded| responded).count() as f64 / history.len() as f64
+      })
+   }
+
+   /// Median of the clock offsets recorded for all known peers. Positive offsets
+   /// mean the local clock is ahead of the network.
+   pub fn clock_skew_estimate(&self) -> Option<time::Duration> {
+      let mut offsets: Vec<_> = self.clock_offsets.lock().unwrap().values().cloned().collect();
+      if offsets.is_empty() {
+         return None;
+      }
+      offsets.sort();
+      Some(offsets[offsets.len() / 2])
+   }
+
    fn handle_locate(&self, payload: sync::Arc<rpc::LocatePayload>, sender: routing::NodeInfo) -> SubotaiResult<()> {
       let lookup_results = self.table.lookup(&payload.id_to_find, self.configuration.k_factor, None);
       let rpc = Rpc::locate_response(self.local_info(),
                                      payload.id_to_find.clone(),
                                      lookup_results);
-      let packet = rpc.serialize();
-      try!(self.outbound.send_to(&packet, sender.address));
+      try!(self.send(&rpc, &sender.address));
       Ok(())
    }
 
    fn handle_retrieve(&self, payload: sync::Arc<rpc::RetrievePayload>, sender: routing::NodeInfo) -> SubotaiResult<()> {
-      let result = match self.storage.retrieve(&payload.key_to_find) {
-         Some(value) => rpc::RetrieveResult::Found(value),
-         None => rpc::RetrieveResult::Closest(self.table.closest_nodes_to(&payload.key_to_find).take(self.configuration.k_factor).collect()),
+      // Nodes outside the replica set only point to closer nodes when asked for authoritative data.
+      let stored = if payload.authoritative_only && !self.is_responsible_for(&payload.key_to_find) {
+         None
+      } else {
+         self.local_entries(&payload.key_to_find, payload.since.clone().map(time::Tm::from))
+      };
+
+      let result = match stored {
+         Some(entries) => rpc::RetrieveResult::Found(entries
+            .into_iter()
+            .map(|(entry, expiration)| (entry, rpc::SerializableTime::from(expiration)))
+            .collect()),
+         None => rpc::RetrieveResult::Closest(self.table.closest_others_to(&payload.key_to_find).take(self.configuration.k_factor).collect()),
       };
 
       let rpc = Rpc::retrieve_response(self.local_info(),
                                        payload.key_to_find.clone(),
-                                       result);
-      let packet = rpc.serialize();
-      try!(self.outbound.send_to(&packet, sender.address));
+                                       result,
+                                       payload.since.clone());
+      try!(self.send(&rpc, &sender.address));
+      Ok(())
+   }
+
+   fn handle_nearest_key(&self, payload: sync::Arc<rpc::NearestKeyPayload>, sender: routing::NodeInfo) -> SubotaiResult<()> {
+      let nearest = self.storage.nearest_key(&payload.target);
+      let rpc = Rpc::nearest_key_response(self.local_info(), payload.target.clone(), nearest);
+      try!(self.send(&rpc, &sender.address));
+      Ok(())
+   }
+
+   fn handle_digest(&self, payload: sync::Arc<rpc::DigestPayload>, sender: routing::NodeInfo) -> SubotaiResult<()> {
+      let digest = self.storage.digest();
+      if digest == payload.digest {
+         let rpc = Rpc::digest_response(self.local_info(), digest, None, payload.page, false);
+         return self.send(&rpc, &sender.address);
+      }
+
+      // Keys are sorted so that pages requested separately don't overlap.
+      let mut keys = self.storage.keys();
+      keys.sort();
+      let start = cmp::min(keys.len(), payload.page.saturating_mul(node::DIGEST_PAGE_KEYS));
+      let end = cmp::min(keys.len(), start + node::DIGEST_PAGE_KEYS);
+      let more_pages = end < keys.len();
+      let rpc = Rpc::digest_response(self.local_info(), digest, Some(keys[start..end].to_vec()), payload.page, more_pages);
+      try!(self.send(&rpc, &sender.address));
+      Ok(())
+   }
+
+   fn handle_remove(&self, payload: sync::Arc<rpc::RemovePayload>, sender: routing::NodeInfo) -> SubotaiResult<()> {
+      let removed = !self.is_read_only() && self.storage.remove(&payload.key, &payload.entry);
+      if removed {
+         self.notify_data_change(NetworkUpdate::RemovedEntry(payload.key.clone()));
+      }
+      let rpc = Rpc::remove_response(self.local_info(), payload.key.clone(), removed);
+      try!(self.send(&rpc, &sender.address));
+      Ok(())
+   }
+
+   fn handle_has_entry(&self, payload: sync::Arc<rpc::HasEntryPayload>, sender: routing::NodeInfo) -> SubotaiResult<()> {
+      let present = match self.storage.retrieve(&payload.key) {
+         Some(entries) => entries.contains(&payload.entry),
+         None => false,
+      };
+      let rpc = Rpc::has_entry_response(self.local_info(), payload.key.clone(), payload.entry.content_hash(), present);
+      try!(self.send(&rpc, &sender.address));
       Ok(())
    }
 
@@ -587,18 +1803,75 @@ impl Resources {
    }
 
    fn handle_retrieve_response(&self, payload: sync::Arc<rpc::RetrieveResponsePayload>) -> SubotaiResult<()> {
+      // Entries stored after a point in time are only part of the key's entries, so they aren't cached.
+      if payload.since.is_some() {
+         return Ok(());
+      }
+
       if let rpc::RetrieveResult::Found(ref entries) = payload.result {
          // Retrieved keys are cached locally for a limited time, to guarantee succesive retrieves don't flood the network.
-         for entry in entries {
-            self.storage.store(&payload.key_to_find, entry, &(time::now() + time::Duration::minutes(1)));
+         let cache_expiration = time::now() + time::Duration::seconds(self.configuration.read_cache_ttl_s);
+         for &(ref entry, ref expiration) in entries {
+            let expiration = cmp::min(cache_expiration, time::Tm::from(expiration.clone()));
+            self.storage.cache(&payload.key_to_find, entry, &expiration);
          }
       }
       Ok(())
    }
 }
 
-enum WaveStrategy<T> {
+pub enum WaveStrategy<T> {
    Continue(Vec<routing::NodeInfo>),
    Halt(T),
 }
 
+
+/// Counts the short lived threads working on behalf of a node (e.g. those handling
+/// incoming RPCs), so a shutdown can wait for them to release the node's resources.
+pub struct DetachedThreads {
+   running  : sync::Mutex<usize>,
+   finished : sync::Condvar,
+}
+
+/// Keeps a detached thread counted as running until dropped.
+pub struct DetachedGuard {
+   threads : sync::Arc<DetachedThreads>,
+}
+
+impl DetachedThreads {
+   pub fn new() -> DetachedThreads {
+      DetachedThreads {
+         running  : sync::Mutex::new(0),
+         finished : sync::Condvar::new(),
+      }
+   }
+
+   /// Counts a new detached thread as running. It must be called before spawning 
+   /// the thread, which then holds on to the guard until it's done.
+   pub fn start(threads: &sync::Arc<DetachedThreads>) -> DetachedGuard {
+      *threads.running.lock().unwrap() += 1;
+      DetachedGuard { threads: threads.clone() }
+   }
+
+   /// Blocks until no detached threads are running, or until the timeout expires. 
+   /// Returns whether they all finished in time.
+   pub fn wait(&self, timeout: StdDuration) -> bool {
+      let deadline = time::SteadyTime::now() + time::Duration::from_std(timeout).unwrap();
+      let mut running = self.running.lock().unwrap();
+      while *running > 0 {
+         let remaining = deadline - time::SteadyTime::now();
+         if remaining <= time::Duration::zero() {
+            return false;
+         }
+         running = self.finished.wait_timeout(running, remaining.to_std().unwrap()).unwrap().0;
+      }
+      true
+   }
+}
+
+impl Drop for DetachedGuard {
+   fn drop(&mut self) {
+      *self.threads.running.lock().unwrap() -= 1;
+      self.threads.finished.notify_all();
+   }
+}
@@ -1,10 +1,11 @@
-use {node, routing, time, hash, storage};
+use {node, routing, time, hash, storage, rpc, rand, SubotaiError};
 use std::collections::VecDeque;
 use std::str::FromStr;
-use std::thread;
+use std::{thread, sync};
 use std::time::Duration as StdDuration;
 use std::net;
 use node::receptions;
+use node::resources::WaveStrategy;
 
 pub const POLL_FREQUENCY_MS: u64 = 50;
 pub const TRIES: u8 = 5;
@@ -27,6 +28,232 @@ fn node_ping() {
    assert!(alpha.resources.ping(&beta.local_info().address).is_ok());
 }
 
+#[test]
+fn custom_socket_read_timeout_still_responds_and_shuts_down() {
+   let alpha = node::Node::new().unwrap();
+   let beta  = node::Factory::new().socket_read_timeout_ms(50).create_node().unwrap();
+   assert!(alpha.resources.ping(&beta.local_info().address).is_ok());
+
+   let mut ticks = beta.resources.reception_updates.lock().unwrap().add_rx();
+   drop(beta);
+
+   // Once the reception loop notices the shutdown, ticks stop arriving.
+   thread::sleep(StdDuration::from_millis(500));
+   while ticks.try_recv().is_ok() {}
+   thread::sleep(StdDuration::from_millis(500));
+   assert!(ticks.try_recv().is_err());
+}
+
+#[test]
+fn reliability_reflects_the_ratio_of_answered_pings() {
+   let alpha = node::Node::new().unwrap();
+   let beta  = node::Node::new().unwrap();
+   let beta_info = beta.local_info();
+   assert_eq!(alpha.reliability(&beta_info.id), None);
+
+   for _ in 0..3 {
+      assert!(alpha.ping_with_timeout(&beta_info, time::Duration::seconds(1)).is_ok());
+   }
+   assert_eq!(alpha.reliability(&beta_info.id), Some(1.0));
+
+   // Once beta shuts down, pings to it time out.
+   drop(beta);
+   thread::sleep(StdDuration::from_millis(500));
+   assert!(alpha.ping_with_timeout(&beta_info, time::Duration::milliseconds(300)).is_err());
+   assert_eq!(alpha.reliability(&beta_info.id), Some(0.75));
+}
+
+#[test]
+fn cancelling_a_locate_stops_it_promptly() {
+   let alpha = node::Node::new().unwrap();
+   let beta  = node::Node::new().unwrap();
+   assert!(alpha.resources.ping(&beta.local_info().address).is_ok());
+
+   // Beta is alpha's only contact, so once it's down the lookup waits for a full network timeout.
+   drop(beta);
+   thread::sleep(StdDuration::from_millis(500));
+
+   let cancel = sync::Arc::new(sync::atomic::AtomicBool::new(false));
+   let cancel_clone = cancel.clone();
+   thread::spawn(move || {
+      thread::sleep(StdDuration::from_millis(300));
+      cancel_clone.store(true, sync::atomic::Ordering::SeqCst);
+   });
+
+   let start = time::SteadyTime::now();
+   match alpha.locate_cancellable(&hash::SubotaiHash::random(), cancel) {
+      Err(SubotaiError::Cancelled) => (),
+      other => panic!("Unexpected result: {:?}", other),
+   }
+   assert!(time::SteadyTime::now() - start < time::Duration::seconds(2));
+}
+
+#[test]
+fn pings_are_retried_after_a_dropped_packet() {
+   let alpha = node::Factory::new().network_timeout_s(3).ping_retries(2).create_node().unwrap();
+   let fake = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+   let fake_address = fake.local_addr().unwrap();
+
+   // The fake node drops the first ping it receives, and answers the second one.
+   let responder = thread::spawn(move || {
+      let mut buffer = [0u8; node::SOCKET_BUFFER_SIZE_BYTES];
+      let mut pings_received = 0;
+      loop {
+         let (size, _) = fake.recv_from(&mut buffer).unwrap();
+         let ping = rpc::Rpc::deserialize(&buffer[..size]).unwrap();
+         pings_received += 1;
+         if pings_received == 2 {
+            let info = node_info_no_net(hash::SubotaiHash::random());
+            let mut reply_address = ping.sender.address;
+            reply_address.set_ip(net::IpAddr::from_str("127.0.0.1").unwrap());
+            fake.send_to(&rpc::Rpc::ping_response(info).serialize().unwrap(), reply_address).unwrap();
+            return pings_received;
+         }
+      }
+   });
+
+   let start = time::SteadyTime::now();
+   assert!(alpha.resources.ping(&fake_address).is_ok());
+   assert_eq!(responder.join().unwrap(), 2);
+
+   // The first attempt waited for a seventh of the timeout, the second for twice as long.
+   let elapsed = time::SteadyTime::now() - start;
+   assert!(elapsed >= time::Duration::milliseconds(400));
+   assert!(elapsed < time::Duration::seconds(3));
+}
+
+#[test]
+fn rpc_stats_count_pings_on_both_sides() {
+   let alpha = node::Node::new().unwrap();
+   let beta  = node::Node::new().unwrap();
+
+   assert!(alpha.resources.ping(&beta.local_info().address).is_ok());
+   thread::sleep(StdDuration::from_millis(100));
+
+   let alpha_stats = alpha.rpc_stats();
+   let beta_stats = beta.rpc_stats();
+   assert_eq!(alpha_stats.get("Ping"), Some(&(1, 0)));
+   assert_eq!(alpha_stats.get("PingResponse"), Some(&(0, 1)));
+   assert_eq!(beta_stats.get("Ping"), Some(&(0, 1)));
+   assert_eq!(beta_stats.get("PingResponse"), Some(&(1, 0)));
+}
+
+#[test]
+fn pinging_nodes_record_each_others_version() {
+   let alpha = node::Node::new().unwrap();
+   let beta  = node::Node::new().unwrap();
+
+   assert!(alpha.resources.ping(&beta.local_info().address).is_ok());
+   assert_eq!(alpha.peer_versions().get(beta.id()), Some(&node::CRATE_VERSION.to_owned()));
+   assert_eq!(beta.peer_versions().get(alpha.id()), Some(&node::CRATE_VERSION.to_owned()));
+}
+
+#[test]
+fn peer_information_is_only_kept_for_contacts() {
+   let alpha = node::Factory::new().max_total_contacts(2).create_node().unwrap();
+   let beta  = node::Node::new().unwrap();
+   let gamma = node::Node::new().unwrap();
+
+   // Alpha's table only has room for itself and beta, so nothing about gamma is kept.
+   assert!(alpha.resources.ping(&beta.local_info().address).is_ok());
+   assert!(gamma.resources.ping(&alpha.local_info().address).is_ok());
+   assert!(alpha.peer_versions().contains_key(beta.id()));
+   assert!(!alpha.peer_versions().contains_key(gamma.id()));
+   assert!(!alpha.resources.clock_offsets.lock().unwrap().contains_key(gamma.id()));
+
+   // Once beta stops responding and is pruned, it's forgotten too.
+   let beta_id = beta.id().clone();
+   let index = alpha.resources.table.bucket_for_node(&beta_id);
+   drop(beta);
+   alpha.resources.prune_bucket(index).unwrap();
+   assert!(!alpha.peer_versions().contains_key(&beta_id));
+   assert!(!alpha.resources.clock_offsets.lock().unwrap().contains_key(&beta_id));
+   assert!(!alpha.resources.liveness.lock().unwrap().contains_key(&beta_id));
+}
+
+#[test]
+fn nodes_can_bind_to_a_specific_address() {
+   let localhost = net::IpAddr::from_str("127.0.0.1").unwrap();
+   let alpha = node::Factory::new().bind_address(localhost).create_node().unwrap();
+   let beta  = node::Factory::new().bind_address(localhost).create_node().unwrap();
+   let gamma = node::Node::new().unwrap();
+   assert_eq!(alpha.local_info().address.ip(), localhost);
+
+   assert!(alpha.resources.ping(&beta.local_info().address).is_ok());
+   assert!(alpha.resources.ping(&gamma.local_info().address).is_ok());
+   assert!(gamma.resources.ping(&alpha.local_info().address).is_ok());
+}
+
+#[test]
+fn ipv6_nodes_can_ping_and_locate_each_other() {
+   let localhost = net::IpAddr::from_str("::1").unwrap();
+   let factory = node::Factory::new().bind_address(localhost);
+   let alpha = factory.create_node().unwrap();
+   let beta  = factory.create_node().unwrap();
+   let gamma = factory.create_node().unwrap();
+   assert!(alpha.local_info().address.is_ipv6());
+
+   assert!(alpha.resources.ping(&beta.local_info().address).is_ok());
+   assert!(gamma.resources.ping(&beta.local_info().address).is_ok());
+   assert!(alpha.resources.table.specific_node(gamma.id()).is_none());
+
+   // Alpha only learns about gamma through beta.
+   let located = alpha.resources.locate(gamma.id()).unwrap();
+   assert_eq!(located.address, gamma.local_info().address);
+
+   // Nodes bound to the IPv6 unspecified address can be pinged through it.
+   let unspecified = node::Factory::new().bind_address(net::IpAddr::from_str("::").unwrap()).create_node().unwrap();
+   assert!(alpha.resources.ping(&unspecified.local_info().address).is_ok());
+}
+
+#[test]
+fn subnets_contain_addresses_under_their_prefix() {
+   let subnet = node::Subnet::from_str("10.1.0.0/15").unwrap();
+   assert!(subnet.contains(&net::IpAddr::from_str("10.0.255.1").unwrap()));
+   assert!(subnet.contains(&net::IpAddr::from_str("10.1.3.4").unwrap()));
+   assert!(!subnet.contains(&net::IpAddr::from_str("10.2.0.0").unwrap()));
+   assert!(!subnet.contains(&net::IpAddr::from_str("::1").unwrap()));
+
+   let subnet = node::Subnet::from_str("fd00::/8").unwrap();
+   assert!(subnet.contains(&net::IpAddr::from_str("fd12::1").unwrap()));
+   assert!(!subnet.contains(&net::IpAddr::from_str("fe80::1").unwrap()));
+
+   assert!(node::Subnet::from_str("0.0.0.0/0").unwrap().contains(&net::IpAddr::from_str("1.2.3.4").unwrap()));
+   assert!(node::Subnet::from_str("10.0.0.0/33").is_err());
+   assert!(node::Subnet::from_str("10.0.0.0").is_err());
+}
+
+#[test]
+fn datagrams_from_disallowed_subnets_are_dropped() {
+   let allowed = vec![node::Subnet::from_str("10.0.0.0/8").unwrap()];
+   let guarded = node::Factory::new().allowed_cidrs(allowed).create_node().unwrap();
+   let open = node::Node::new().unwrap();
+
+   let timeout = time::Duration::milliseconds(500);
+   match open.ping_with_timeout(&guarded.local_info(), timeout) {
+      Err(SubotaiError::NoResponse) => (),
+      _ => panic!("Expected the ping to be dropped"),
+   }
+   assert_eq!(guarded.dropped_datagrams(), 1);
+   assert!(guarded.resources.table.specific_node(open.id()).is_none());
+
+   let allowed = vec![node::Subnet::from_str("127.0.0.0/8").unwrap()];
+   let local = node::Factory::new().allowed_cidrs(allowed).create_node().unwrap();
+   assert!(open.ping_with_timeout(&local.local_info(), timeout).is_ok());
+   assert_eq!(local.dropped_datagrams(), 0);
+}
+
+#[test]
+fn sending_to_a_different_address_family_fails_descriptively() {
+   let node = node::Node::new().unwrap();
+   let v6_target = net::SocketAddr::from_str("[::1]:50000").unwrap();
+
+   match node.resources.ping(&v6_target) {
+      Err(SubotaiError::AddressFamilyMismatch) => (),
+      _ => panic!("Expected an address family mismatch"),
+   }
+}
+
 #[test]
 fn reception_iterator_times_out_correctly() {
    let alpha = node::Node::new().unwrap(); 
@@ -77,15 +304,109 @@ fn finding_a_nonexisting_node_in_a_simulated_network_times_out() {
    // Head finds tail in a few steps.
    let head = nodes.pop_front().unwrap();
 
-   let random_hash = hash::SubotaiHash::random();
-   assert!(head.resources.locate(&random_hash).is_err());
+   let random_id = hash::NodeId(hash::SubotaiHash::random());
+   assert!(head.resources.locate(&random_id).is_err());
+}
+
+#[test]
+fn operations_with_deadlines_stop_on_time() {
+   let node = node::Node::new().unwrap();
+   let silent: Vec<_> = (0..3).map(|_| net::UdpSocket::bind("127.0.0.1:0").unwrap()).collect();
+   for socket in &silent {
+      node.add_contact(routing::NodeInfo::new(hash::SubotaiHash::random(), socket.local_addr().unwrap()));
+   }
+
+   let start = time::SteadyTime::now();
+   assert!(node.retrieve_with_deadline(&hash::SubotaiHash::random(), start - time::Duration::seconds(1)).is_err());
+   assert!(time::SteadyTime::now() - start < time::Duration::milliseconds(100));
+
+   let start = time::SteadyTime::now();
+   let deadline = start + time::Duration::milliseconds(300);
+   assert!(node.retrieve_with_deadline(&hash::SubotaiHash::random(), deadline).is_err());
+   assert!(node.locate_with_deadline(&hash::SubotaiHash::random(), deadline).is_err());
+   let elapsed = time::SteadyTime::now() - start;
+   assert!(elapsed >= time::Duration::milliseconds(300));
+   assert!(elapsed < time::Duration::seconds(1));
+}
+
+#[test]
+fn wave_waits_for_a_response_even_when_impatience_equals_alpha() {
+   let configuration = node::Configuration {
+      alpha                    : 3,
+      impatience               : 3,
+      min_responses_to_advance : 0,
+      ..Default::default()
+   };
+   let node = node::Node::with_configuration(0, 0, configuration).unwrap();
+   let other = node::Node::new().unwrap();
+
+   let mut responses_received = 0;
+   let result = node.resources.wave(vec![other.local_info()], |responses: &[rpc::Rpc], _: &[routing::NodeInfo]| {
+      responses_received = responses.len();
+      WaveStrategy::Halt(())
+   }, rpc::Rpc::ping(node.local_info()), time::SteadyTime::now() + time::Duration::seconds(5));
+
+   assert!(result.is_ok());
+   assert_eq!(responses_received, 1);
+}
+
+#[test]
+fn wave_waits_for_the_configured_number_of_responses() {
+   for &min_responses in &[1usize, 3] {
+      let node = node::Factory::new()
+         .alpha(3)
+         .min_responses_to_advance(min_responses)
+         .create_node()
+         .unwrap();
+      let others: Vec<_> = (0..3).map(|_| node::Node::new().unwrap()).collect();
+      let seeds: Vec<_> = others.iter().map(|other| other.local_info()).collect();
+
+      let mut responses_received = 0;
+      let result = node.resources.wave(seeds, |responses: &[rpc::Rpc], _: &[routing::NodeInfo]| {
+         responses_received = responses.len();
+         WaveStrategy::Halt(())
+      }, rpc::Rpc::ping(node.local_info()), time::SteadyTime::now() + time::Duration::seconds(5));
+
+      assert!(result.is_ok());
+      assert_eq!(responses_received, min_responses);
+   }
+}
+
+#[test]
+fn excess_waves_are_rate_limited() {
+   let node = node::Factory::new().max_waves_per_sec(2).create_node().unwrap();
+
+   // The node knows no one, so the first waves fail immediately, but they count nonetheless.
+   for _ in 0..2 {
+      match node.resources.locate(&hash::NodeId(hash::SubotaiHash::random())) {
+         Err(SubotaiError::UnresponsiveNetwork) => (),
+         _ => panic!("Expected the wave to go through"),
+      }
+   }
+
+   match node.resources.locate(&hash::NodeId(hash::SubotaiHash::random())) {
+      Err(SubotaiError::RateLimited) => (),
+      _ => panic!("Expected the wave to be rate limited"),
+   }
+
+   thread::sleep(StdDuration::new(1,0));
+   match node.resources.locate(&hash::NodeId(hash::SubotaiHash::random())) {
+      Err(SubotaiError::UnresponsiveNetwork) => (),
+      _ => panic!("Expected the wave to go through after a second"),
+   }
 }
 
 fn simulated_network(network_size: usize) -> VecDeque<node::Node> {
-   let cfg: node::Configuration = Default::default();
-   assert!(network_size > cfg.k_factor, "You can't build a network with so few nodes!");
+   simulated_network_from_factory(network_size, &node::Factory::new())
+}
+
+fn simulated_network_from_factory(network_size: usize, factory: &node::Factory) -> VecDeque<node::Node> {
+   let nodes: VecDeque<node::Node> = (0..network_size).map(|_| { factory.create_node().unwrap() }).collect();
+   bootstrap_network(nodes)
+}
 
-   let nodes: VecDeque<node::Node> = (0..network_size).map(|_| { node::Node::new().unwrap() }).collect();
+fn bootstrap_network(nodes: VecDeque<node::Node>) -> VecDeque<node::Node> {
+   assert!(nodes.len() > nodes[0].configuration().k_factor, "You can't build a network with so few nodes!");
    {
       let origin = nodes.front().unwrap();
       for node in nodes.iter().skip(1) {
@@ -98,6 +419,290 @@ fn simulated_network(network_size: usize) -> VecDeque<node::Node> {
    nodes
 }
 
+#[test]
+fn health_report_reflects_a_bootstrapped_node() {
+   let fresh = node::Node::new().unwrap();
+   let report = fresh.health();
+   assert_eq!(report.state, node::State::OffGrid);
+   assert_eq!(report.routing_len, 0);
+   assert!(!report.is_well_connected);
+
+   let nodes = simulated_network(30);
+   let node = &nodes[1];
+   let expiration = time::now() + time::Duration::hours(1);
+   node.resources.storage.store(&hash::SubotaiHash::random(), &storage::StorageEntry::Value(hash::SubotaiHash::random()), &expiration);
+   let report = node.health();
+   assert_eq!(report.state, node::State::OnGrid);
+   assert_eq!(report.routing_len, node.resources.contact_count());
+   assert!(report.routing_len > 0);
+   assert!(report.populated_buckets > 0);
+   assert_eq!(report.storage_len, 1);
+   assert!(report.uptime >= time::Duration::zero());
+   assert!(report.is_well_connected);
+}
+
+#[test]
+fn table_info_describes_the_routing_table() {
+   let empty = node::Factory::new().insert_self_in_table(false).create_node().unwrap();
+   let info = empty.table_info();
+   assert_eq!((info.len, info.populated_buckets, info.fullest_bucket), (0, 0, None));
+
+   let nodes = simulated_network(30);
+   let node = &nodes[1];
+   let info = node.table_info();
+   let stats = node.resources.table.bucket_stats();
+   assert_eq!(info.len, node.resources.table.len());
+   assert!(info.populated_buckets > 0);
+   let fullest = info.fullest_bucket.unwrap();
+   assert_eq!(stats[fullest], *stats.iter().max().unwrap());
+   assert!(info.oldest_probed_bucket < hash::HASH_SIZE);
+}
+
+#[test]
+fn close_nodes_are_sorted_by_ascending_distance() {
+   let nodes = simulated_network(30);
+   let target = hash::SubotaiHash::random();
+   let close_nodes = nodes[0].close_nodes(&target, 10);
+   assert_eq!(close_nodes.len(), 10);
+
+   let distances: Vec<hash::SubotaiHash> = close_nodes.iter().map(|info| &info.id ^ &target).collect();
+   for pair in distances.windows(2) {
+      assert!(pair[0] <= pair[1]);
+   }
+}
+
+#[test]
+fn republishing_a_key_makes_it_retrievable_right_away() {
+   let nodes = simulated_network(30);
+   let key = hash::SubotaiHash::random();
+   let entry = storage::StorageEntry::Value(hash::SubotaiHash::random());
+   let expiration = time::now() + time::Duration::hours(1);
+   nodes[0].resources.storage.store(&key, &entry, &expiration);
+
+   nodes[0].republish_key(key.clone()).unwrap();
+   let holders = nodes.iter().skip(1).filter(|node| node.resources.storage.retrieve(&key).is_some()).count();
+   assert!(holders > 0);
+   assert_eq!(nodes[29].retrieve(key).unwrap(), vec![entry]);
+}
+
+#[test]
+fn seeding_picks_identical_seeds_for_identical_tables() {
+   let factory = node::Factory::new().insert_self_in_table(false);
+   let alpha = factory.create_node().unwrap();
+   let beta = factory.create_node().unwrap();
+
+   let mut contacts: Vec<routing::NodeInfo> = (0..15)
+      .map(|i| routing::NodeInfo::new(hash::SubotaiHash::random(), net::SocketAddr::from_str(&format!("127.0.0.1:{}", 40000 + i)).unwrap()))
+      .collect();
+   for contact in &contacts {
+      alpha.add_contact(contact.clone());
+   }
+   contacts.reverse();
+   for contact in &contacts {
+      beta.add_contact(contact.clone());
+   }
+
+   let target = hash::SubotaiHash::random();
+   let alpha_candidates = alpha.resources.initial_candidates(&target);
+   let beta_candidates = beta.resources.initial_candidates(&target);
+   let seed_count = alpha.configuration().alpha;
+   assert_eq!(alpha_candidates[..seed_count], beta_candidates[..seed_count]);
+   for pair in alpha_candidates.windows(2) {
+      assert!(&pair[0].id ^ &target <= &pair[1].id ^ &target);
+   }
+}
+
+#[test]
+fn would_store_only_with_room_in_the_bucket() {
+   let node = node::Factory::new().insert_self_in_table(false).create_node().unwrap();
+   let address = net::SocketAddr::from_str("127.0.0.1:40000").unwrap();
+   let mut contacts = Vec::new();
+   for _ in 0..node.configuration().k_factor {
+      let id = hash::SubotaiHash::random_at_distance(node.id(), 150);
+      node.add_contact(routing::NodeInfo::new(id.clone(), address));
+      contacts.push(id);
+   }
+
+   assert!(!node.would_store(&hash::SubotaiHash::random_at_distance(node.id(), 150)));
+   assert!(node.would_store(&contacts[0]));
+   assert!(node.would_store(&hash::SubotaiHash::random_at_distance(node.id(), 10)));
+}
+
+#[test]
+fn retrieving_off_grid_fails_promptly_unless_found_locally() {
+   let node = node::Node::new().unwrap();
+   let start = time::SteadyTime::now();
+   match node.retrieve(hash::SubotaiHash::random()) {
+      Err(SubotaiError::OffGridError) => (),
+      _ => panic!("Expected an off-grid error"),
+   }
+   assert!(time::SteadyTime::now() - start < time::Duration::seconds(1));
+
+   let key = hash::SubotaiHash::random();
+   let entry = storage::StorageEntry::Value(hash::SubotaiHash::random());
+   node.resources.storage.store(&key, &entry, &(time::now() + time::Duration::hours(1)));
+   assert_eq!(node.retrieve(key).unwrap(), vec![entry]);
+}
+
+#[test]
+fn store_quorum_decides_when_stores_succeed() {
+   let default_node = node::Node::new().unwrap();
+   assert_eq!(default_node.resources.store_quorum(), default_node.configuration().k_factor / 3);
+
+   let nodes = simulated_network(30);
+   for node in nodes.iter().skip(1) {
+      node.set_read_only(true);
+   }
+
+   let key = hash::SubotaiHash::random();
+   let entry = storage::StorageEntry::Value(hash::SubotaiHash::random());
+   match nodes[0].store(&key, entry.clone()) {
+      Err(SubotaiError::StoreQuorumFailed { .. }) => (),
+      _ => panic!("Expected the store to be rejected by read only nodes"),
+   }
+
+   let mut configuration = nodes[0].configuration().clone();
+   configuration.store_quorum = 0.0;
+   let fire_and_forget = node::Node::with_configuration(0, 0, configuration).unwrap();
+   fire_and_forget.bootstrap_sync(&nodes[0].local_info().address).unwrap();
+   assert!(fire_and_forget.store(&key, entry).is_ok());
+}
+
+#[test]
+fn detailed_stores_report_partial_success() {
+   let nodes = simulated_network(30);
+   let key = hash::SubotaiHash::random();
+   let entry = storage::StorageEntry::Value(hash::SubotaiHash::random());
+
+   let report = nodes[0].store_detailed(&key, entry.clone()).unwrap();
+   assert_eq!(report.contacted, nodes[0].configuration().k_factor);
+   assert_eq!(report.succeeded, report.contacted);
+
+   for node in nodes.iter().skip(1) {
+      node.set_read_only(true);
+   }
+   let report = nodes[0].store_detailed(&key, entry.clone()).unwrap();
+   assert_eq!(report.contacted, nodes[0].configuration().k_factor);
+   assert!(report.succeeded <= 1);
+
+   let off_grid = node::Node::new().unwrap();
+   match off_grid.store_detailed(&key, entry) {
+      Err(SubotaiError::OffGridError) => (),
+      _ => panic!("Expected an off-grid error"),
+   }
+}
+
+#[test]
+fn retrieve_or_closest_reports_where_the_search_ended() {
+   let nodes = simulated_network(30);
+   let key = hash::SubotaiHash::random();
+   let entry = storage::StorageEntry::Value(hash::SubotaiHash::random());
+
+   match nodes[1].retrieve_or_closest(&key).unwrap() {
+      node::RetrieveOutcome::NotFound(frontier) => {
+         assert!(!frontier.is_empty());
+         assert!(frontier.len() <= nodes[1].configuration().k_factor);
+         assert!(frontier.iter().all(|info| info.id != *nodes[1].id()));
+         for pair in frontier.windows(2) {
+            assert!(&pair[0].id ^ &key <= &pair[1].id ^ &key);
+         }
+      },
+      _ => panic!("Expected the key not to be found"),
+   }
+
+   nodes[0].store(&key, entry.clone()).unwrap();
+   match nodes[1].retrieve_or_closest(&key).unwrap() {
+      node::RetrieveOutcome::Found(entries) => assert_eq!(entries, vec![entry]),
+      _ => panic!("Expected the key to be found"),
+   }
+}
+
+#[test]
+fn retrieving_only_entries_stored_after_a_cutoff() {
+   let nodes = simulated_network(30);
+   let key = hash::SubotaiHash::random();
+   let old_entry = storage::StorageEntry::Value(hash::SubotaiHash::random());
+   let new_entry = storage::StorageEntry::Blob(vec![1, 2, 3]);
+
+   nodes[0].store(&key, old_entry.clone()).unwrap();
+   thread::sleep(StdDuration::from_millis(200));
+   let cutoff = time::now();
+   thread::sleep(StdDuration::from_millis(200));
+   nodes[0].store(&key, new_entry.clone()).unwrap();
+   thread::sleep(StdDuration::from_millis(200));
+
+   assert_eq!(nodes[29].retrieve_since(&key, cutoff).unwrap(), vec![new_entry]);
+   assert_eq!(nodes[29].retrieve(&key).unwrap().len(), 2);
+}
+
+#[test]
+fn oversized_mass_stores_are_rejected_as_a_whole() {
+   let receiver = node::Factory::new().max_storage(5).create_node().unwrap();
+   let sender = node::Node::new().unwrap();
+   let key = hash::SubotaiHash::random();
+   let expiration = rpc::SerializableTime::from(time::now() + time::Duration::hours(1));
+   let entries: Vec<_> = (0..10)
+      .map(|_| (storage::StorageEntry::Value(hash::SubotaiHash::random()), expiration.clone()))
+      .collect();
+
+   let responses = sender.receptions()
+      .of_kind(receptions::KindFilter::StoreResponse)
+      .during(time::Duration::seconds(1));
+   let rpc = rpc::Rpc::mass_store(sender.local_info(), key.clone(), entries);
+   sender.resources.send(&rpc, &receiver.local_info().address).unwrap();
+
+   let results: Vec<_> = responses.filter_map(|rpc| rpc.is_store_response(&key)).take(1).collect();
+   assert_eq!(results, vec![storage::StoreResult::MassStoreFailed]);
+   assert!(receiver.resources.storage.is_empty());
+}
+
+#[test]
+fn first_online_callback_fires_exactly_once() {
+   let nodes = simulated_network(30);
+   let newcomer = node::Node::new().unwrap();
+   let calls = sync::Arc::new(sync::atomic::AtomicUsize::new(0));
+
+   let calls_clone = calls.clone();
+   newcomer.on_first_online(move || { calls_clone.fetch_add(1, sync::atomic::Ordering::SeqCst); });
+   thread::sleep(StdDuration::from_millis(100));
+   assert_eq!(calls.load(sync::atomic::Ordering::SeqCst), 0);
+
+   newcomer.bootstrap(&nodes[0].local_info().address).unwrap();
+   newcomer.wait_for_state(node::State::OnGrid);
+
+   // Going through defensive mode and back on grid doesn't fire it again.
+   newcomer.resources.set_state(node::State::Defensive);
+   newcomer.resources.set_state(node::State::OnGrid);
+   thread::sleep(StdDuration::from_millis(100));
+   assert_eq!(calls.load(sync::atomic::Ordering::SeqCst), 1);
+}
+
+#[test]
+fn bootstrapping_from_several_seeds_tolerates_dead_ones() {
+   let nodes = simulated_network(30);
+   let silent = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+   let dead_seed = routing::NodeInfo::new(hash::SubotaiHash::random(), silent.local_addr().unwrap());
+
+   let newcomer = node::Node::new().unwrap();
+   newcomer.bootstrap_multi(&[dead_seed.clone(), nodes[0].local_info()]).unwrap();
+   newcomer.wait_for_state(node::State::OnGrid);
+
+   let isolated = node::Factory::new().network_timeout_s(1).create_node().unwrap();
+   match isolated.bootstrap_multi(&[dead_seed.clone(), dead_seed]) {
+      Err(SubotaiError::NoResponse) => (),
+      _ => panic!("Expected the bootstrap to fail"),
+   }
+}
+
+#[test]
+fn synchronous_bootstrap_returns_on_grid() {
+   let nodes = simulated_network(30);
+   let newcomer = node::Node::new().unwrap();
+
+   newcomer.bootstrap_sync(&nodes[0].local_info().address).unwrap();
+   assert_eq!(newcomer.state(), node::State::OnGrid);
+}
+
 #[test]
 fn updating_table_with_full_bucket_starts_the_conflict_resolution_mechanism()
 {
@@ -106,7 +711,7 @@ fn updating_table_with_full_bucket_starts_the_conflict_resolution_mechanism()
 
    node.resources.table.fill_bucket(8, cfg.k_factor as u8); // Bucket completely full
 
-   let mut id = node.id().clone();
+   let mut id = node.id().0.clone();
    id.flip_bit(8);
    id.raw[0] = 0xFF;
    let info = node_info_no_net(id);
@@ -115,6 +720,29 @@ fn updating_table_with_full_bucket_starts_the_conflict_resolution_mechanism()
    assert_eq!(node.resources.conflicts.lock().unwrap().len(), 1);
 }
 
+#[test]
+fn without_defensive_mode_newcomers_always_win_full_buckets()
+{
+   let node = node::Factory::new().enable_defensive_mode(false).create_node().unwrap();
+   let cfg  = node.resources.configuration.clone();
+
+   for index in 0..(cfg.k_factor + cfg.max_conflicts + 1) {
+      let mut id = node.id().0.clone();
+      id.flip_bit(140); // Arbitrary bucket
+      id.raw[0] = index as u8;
+      let info = node_info_no_net(id.clone());
+      let update = node.resources.update_table(info);
+      if index >= cfg.k_factor {
+         assert_eq!(update, node::ContactUpdate::Added);
+      }
+      assert!(node.resources.table.specific_node(&hash::NodeId(id)).is_some());
+   }
+
+   assert!(node.resources.conflicts.lock().unwrap().is_empty());
+   assert!(node.state() != node::State::Defensive);
+   assert_eq!(node.resources.table.nodes_from_bucket(140).len(), cfg.k_factor);
+}
+
 #[test]
 fn generating_a_conflict_causes_a_ping_to_the_evicted_node()
 {
@@ -134,7 +762,7 @@ fn generating_a_conflict_causes_a_ping_to_the_evicted_node()
       .during(time::Duration::seconds(2));
 
    // Adding a new node causes a conflict.
-   let mut id = beta.id().clone();
+   let mut id = beta.id().0.clone();
    id.raw[0] = 0xFF;
    let info = node_info_no_net(id);
    alpha.resources.update_table(info);
@@ -149,7 +777,7 @@ fn generating_too_many_conflicts_causes_the_node_to_enter_defensive_state()
    let cfg  = &node.resources.configuration;
 
    for index in 0..(cfg.k_factor + cfg.max_conflicts) {
-      let mut id = node.id().clone();
+      let mut id = node.id().0.clone();
       id.flip_bit(140); // Arbitrary bucket
       id.raw[0] = index as u8;
       let info = node_info_no_net(id);
@@ -163,19 +791,19 @@ fn generating_too_many_conflicts_causes_the_node_to_enter_defensive_state()
    }
 
    // Trying to add new conflictive nodes while in defensive state will fail.
-   let mut id = node.id().clone();
+   let mut id = node.id().0.clone();
    id.flip_bit(140); // Arbitrary bucket
    id.raw[0] = 0xFF;
    let info = node_info_no_net(id.clone());
 
    node.resources.update_table(info);
-   assert!(node.resources.table.specific_node(&id).is_none());
+   assert!(node.resources.table.specific_node(&hash::NodeId(id.clone())).is_none());
 
    // However, if they would fall in a different bucket, it's ok.
    id.flip_bit(155);
    let info = node_info_no_net(id.clone());
    node.resources.update_table(info);
-   assert!(node.resources.table.specific_node(&id).is_some());
+   assert!(node.resources.table.specific_node(&hash::NodeId(id.clone())).is_some());
 }
 
 #[test]
@@ -284,9 +912,1035 @@ fn store_retrieve_in_simulated_network()
    assert_eq!(collection_entries, retrieved_collection);
 }
 
-fn node_info_no_net(id : hash::SubotaiHash) -> routing::NodeInfo {
-   routing::NodeInfo {
-      id : id,
-      address : net::SocketAddr::from_str("0.0.0.0:0").unwrap(),
+#[test]
+fn only_keys_close_to_the_node_are_primary() {
+   let nodes = simulated_network_from_factory(30, &node::Factory::new().k_factor(5));
+   let head = &nodes[0];
+   let expiration = time::now() + time::Duration::minutes(30);
+
+   let close_key = hash::SubotaiHash::random_at_distance(head.id(), 2);
+   let far_key = hash::SubotaiHash::random_at_distance(head.id(), 159);
+   for key in &[&close_key, &far_key] {
+      head.resources.storage.store(key, &storage::StorageEntry::Value(hash::SubotaiHash::random()), &expiration);
    }
+
+   let primary_keys = head.primary_keys().unwrap();
+   assert_eq!(primary_keys, vec![close_key]);
+}
+
+#[test]
+fn flushed_entries_survive_the_node() {
+   let mut nodes = simulated_network(30);
+   let head = nodes.pop_front().unwrap();
+   let tail = nodes.pop_back().unwrap();
+
+   // The entry is only stored locally, so it only reaches the network through the flush.
+   let key = hash::SubotaiHash::random();
+   let entry = storage::StorageEntry::Value(hash::SubotaiHash::random());
+   let expiration = time::now() + time::Duration::minutes(30);
+   head.resources.storage.store(&key, &entry, &expiration);
+
+   head.flush().unwrap();
+   drop(head);
+
+   let retrieved_entries = tail.retrieve(&key).unwrap();
+   assert_eq!(retrieved_entries, vec![entry]);
+}
+
+#[test]
+fn authoritative_retrieve_bypasses_stale_caches() {
+   let nodes = simulated_network_from_factory(30, &node::Factory::new().k_factor(5));
+   let head = &nodes[0];
+
+   // The key is far from head, so head isn't one of its replicas.
+   let key = hash::SubotaiHash::random_at_distance(head.id(), 159);
+   let fresh_entry = storage::StorageEntry::Value(hash::SubotaiHash::random());
+   let stale_entry = storage::StorageEntry::Value(hash::SubotaiHash::random());
+   nodes[1].store(key.clone(), fresh_entry.clone()).unwrap();
+   // The store returns after the first acknowledgements, so we let the remaining replicas catch up.
+   thread::sleep(StdDuration::from_millis(200));
+   head.resources.storage.store(&key, &stale_entry, &(time::now() + time::Duration::minutes(1)));
+
+   assert_eq!(head.retrieve(&key).unwrap(), vec![stale_entry]);
+   assert_eq!(head.retrieve_authoritative(&key).unwrap(), vec![fresh_entry]);
+}
+
+#[test]
+fn exported_state_restores_an_equivalent_node() {
+   let nodes = simulated_network(30);
+   let head = &nodes[0];
+
+   let key = hash::SubotaiHash::random();
+   let entry = storage::StorageEntry::Value(hash::SubotaiHash::random());
+   let short_lived_key = hash::SubotaiHash::random();
+   head.resources.storage.store(&key, &entry, &(time::now() + time::Duration::minutes(30)));
+   head.resources.storage.store(&short_lived_key, &entry, &(time::now() + time::Duration::seconds(1)));
+
+   let state = head.export_state().unwrap();
+   thread::sleep(StdDuration::from_millis(1500));
+   let restored = node::Factory::from_state(&state).unwrap().create_node().unwrap();
+
+   assert_eq!(restored.id(), head.id());
+   assert!(restored.local_info().address.port() != head.local_info().address.port());
+   assert_eq!(restored.resources.table.len(), head.resources.table.len());
+   assert_eq!(restored.resources.storage.retrieve(&key), Some(vec![entry]));
+   assert_eq!(restored.resources.storage.retrieve(&short_lived_key), None);
+}
+
+#[test]
+fn nodes_can_reject_stores_for_keys_they_are_not_responsible_for() {
+   let factory = node::Factory::new().k_factor(5).reject_foreign_stores(true);
+   let nodes = simulated_network_from_factory(30, &factory);
+   let head = &nodes[0];
+   let sender = &nodes[1];
+
+   let far_key = hash::SubotaiHash::random_at_distance(head.id(), 159);
+   let close_key = hash::SubotaiHash::random_at_distance(head.id(), 0);
+   let entry = storage::StorageEntry::Value(hash::SubotaiHash::random());
+   let expiration = rpc::SerializableTime::from(time::now() + time::Duration::minutes(30));
+
+   for &(ref key, ref expected) in &[(far_key.clone(), storage::StoreResult::NotResponsible), (close_key, storage::StoreResult::Success)] {
+      let responses = sender.receptions()
+         .of_kind(receptions::KindFilter::StoreResponse)
+         .from(head.id().clone())
+         .during(time::Duration::seconds(2));
+
+      let rpc = rpc::Rpc::store(sender.local_info(), key.clone(), entry.clone(), expiration.clone());
+      sender.resources.send(&rpc, &head.local_info().address).unwrap();
+
+      let result = responses.filter_map(|rpc| rpc.is_store_response(key)).next();
+      assert_eq!(result.as_ref(), Some(expected));
+   }
+   assert!(head.resources.storage.retrieve(&far_key).is_none());
+}
+
+#[test]
+fn oversized_responses_dont_bloat_the_candidate_set() {
+   let node = node::Node::new().unwrap();
+   let k_factor = node.configuration().k_factor;
+   let target = hash::SubotaiHash::random();
+
+   // A malicious responder claims to know about many more nodes than it should, repeating some.
+   let mut bogus_nodes: Vec<_> = (0..500).map(|_| node_info_no_net(hash::SubotaiHash::random())).collect();
+   let repeated = bogus_nodes.clone();
+   bogus_nodes.extend(repeated);
+   let response = rpc::Rpc::locate_response(node_info_no_net(hash::SubotaiHash::random()),
+                                            hash::NodeId(target.clone()),
+                                            routing::LookupResult::ClosestNodes(bogus_nodes.clone()));
+
+   let mut candidates = response.is_helping_locate(&hash::NodeId(target.clone())).unwrap();
+   node.resources.bound_candidates(&target, &mut candidates);
+   assert_eq!(candidates.len(), 2 * k_factor);
+
+   bogus_nodes.sort_by(|info_a, info_b| (&info_a.id ^ &target).cmp(&(&info_b.id ^ &target)));
+   bogus_nodes.dedup();
+   assert_eq!(candidates, bogus_nodes[0..2 * k_factor].to_vec());
+}
+
+#[test]
+fn same_region_candidates_are_preferred_among_equidistant_ones() {
+   let node = node::Factory::new().region(7).create_node().unwrap();
+   let target = hash::SubotaiHash::random();
+   let in_region = |id: hash::SubotaiHash, region: Option<u16>| {
+      let mut info = node_info_no_net(id);
+      info.region = region;
+      info
+   };
+
+   let closest = in_region(hash::SubotaiHash::random_at_distance(&target, 50), Some(3));
+   let mut equidistant: Vec<_> = (0..4).map(|_| in_region(hash::SubotaiHash::random_at_distance(&target, 100), Some(3))).collect();
+   equidistant.push(in_region(hash::SubotaiHash::random_at_distance(&target, 100), Some(7)));
+   let farthest = in_region(hash::SubotaiHash::random_at_distance(&target, 150), Some(7));
+
+   let mut candidates = equidistant.clone();
+   candidates.push(farthest.clone());
+   candidates.push(closest.clone());
+   node.resources.bound_candidates(&target, &mut candidates);
+
+   // Candidates stay sorted by distance alone.
+   let mut by_distance = candidates.clone();
+   by_distance.sort_by(|info_a, info_b| (&info_a.id ^ &target).cmp(&(&info_b.id ^ &target)));
+   assert_eq!(candidates, by_distance);
+
+   // The region only breaks ties between nodes at the same bucket distance when
+   // picking which ones to query next.
+   let next = node.resources.next_to_query(&target, &candidates, &[]);
+   assert_eq!(next.len(), node.configuration().alpha);
+   assert_eq!(next[0], closest);
+   assert_eq!(next[1], equidistant[4]);
+   assert!(!next.contains(&farthest));
+
+   // The region tag is propagated along with the node's ID and address.
+   let alpha = node::Node::new().unwrap();
+   assert!(alpha.resources.ping(&node.local_info().address).is_ok());
+   assert_eq!(alpha.resources.table.specific_node(node.id()).unwrap().region, Some(7));
+}
+
+#[test]
+fn region_tags_never_change_probe_results() {
+   let cfg: node::Configuration = Default::default();
+   let nodes: VecDeque<node::Node> = (0..40)
+      .map(|i| node::Factory::new().region(if i % 2 == 0 { 7 } else { 3 }).create_node().unwrap())
+      .collect();
+   let nodes = bootstrap_network(nodes);
+
+   let target = hash::SubotaiHash::random();
+   let mut info_nodes: Vec<routing::NodeInfo> = nodes
+      .iter()
+      .map(|ref node| node.resources.local_info())
+      .collect();
+   info_nodes.sort_by(|ref info_a, ref info_b| (&info_a.id ^ &target).cmp(&(&info_b.id ^ &target)));
+   info_nodes.truncate(cfg.k_factor);
+
+   // Nodes in either region find exactly the closest nodes to the target, in distance order.
+   for node in vec![&nodes[0], &nodes[1]] {
+      let probe_results = node.resources.probe(&target, cfg.k_factor).unwrap();
+      assert_eq!(info_nodes.len(), probe_results.len());
+      for (a, b) in probe_results.iter().zip(info_nodes.iter()) {
+         assert_eq!(a.id, b.id);
+      }
+   }
+}
+
+#[test]
+fn retrieving_all_keys_in_a_namespace() {
+   let mut nodes = simulated_network(30);
+   let head = nodes.pop_front().unwrap();
+   let tail = nodes.pop_back().unwrap();
+
+   let mut stored: Vec<_> = (0..3)
+      .map(|_| (hash::SubotaiHash::random(), storage::StorageEntry::Value(hash::SubotaiHash::random())))
+      .collect();
+   for &(ref key, ref entry) in &stored {
+      head.store_namespaced("fruits", key.clone(), entry.clone()).unwrap();
+   }
+   head.store(hash::SubotaiHash::random(), storage::StorageEntry::Value(hash::SubotaiHash::random())).unwrap();
+
+   let mut retrieved: Vec<_> = tail.retrieve_namespace("fruits").unwrap()
+      .into_iter()
+      .map(|(key, mut entries)| (key, entries.pop().unwrap()))
+      .collect();
+   stored.sort_by(|a, b| a.0.cmp(&b.0));
+   retrieved.sort_by(|a, b| a.0.cmp(&b.0));
+   assert_eq!(retrieved, stored);
+   assert!(tail.retrieve_namespace("vegetables").is_err());
+}
+
+#[test]
+fn retrieve_falls_back_to_caches_when_replicas_are_down() {
+   let mut nodes = simulated_network_from_factory(30, &node::Factory::new().k_factor(5).network_timeout_s(1));
+   let head = nodes.pop_front().unwrap();
+
+   // The key is far from head, so head only holds a cached copy after retrieving it.
+   let key = hash::SubotaiHash::random_at_distance(head.id(), 159);
+   let entry = storage::StorageEntry::Value(hash::SubotaiHash::random());
+   head.store(key.clone(), entry.clone()).unwrap();
+   assert_eq!(head.retrieve_authoritative(&key).unwrap(), vec![entry.clone()]);
+
+   // All replicas go down, once any republishing triggered by the store has settled.
+   thread::sleep(StdDuration::from_millis(500));
+   nodes.retain(|node| node.resources.storage.retrieve(&key).is_none());
+   match head.retrieve_authoritative(&key) {
+      Err(SubotaiError::UnresponsiveNetwork) => (),
+      _ => panic!("No replicas should be available"),
+   }
+   assert_eq!(head.retrieve_preferring_authoritative(&key).unwrap(), vec![entry]);
+}
+
+#[test]
+fn discovering_the_nearest_stored_key_in_the_network() {
+   let mut nodes = simulated_network(30);
+   let head = nodes.pop_front().unwrap();
+   let target = hash::SubotaiHash::random();
+
+   let mut nearest_key = None;
+   for distance in &[120, 60, 90] {
+      let key = hash::SubotaiHash::random_at_distance(&target, *distance);
+      head.store(key.clone(), storage::StorageEntry::Value(hash::SubotaiHash::random())).unwrap();
+      if *distance == 60 {
+         nearest_key = Some(key);
+      }
+   }
+
+   let (holder, key) = nodes[0].nearest_stored_key(&target).unwrap().unwrap();
+   assert_eq!(Some(key.clone()), nearest_key);
+   let holder_node = nodes.iter().chain(Some(&head)).find(|node| node.id() == &holder.id).unwrap();
+   assert!(holder_node.resources.storage.retrieve(&key).is_some());
+}
+
+#[test]
+fn storage_keys_and_raw_hashes_are_interchangeable_in_the_api() {
+   let mut nodes = simulated_network(30);
+   let head = nodes.pop_front().unwrap();
+   let tail = nodes.pop_back().unwrap();
+
+   let raw_key = hash::SubotaiHash::random();
+   let typed_key = hash::StorageKey::from(&raw_key);
+   let entry = storage::StorageEntry::Value(hash::SubotaiHash::random());
+   head.store(typed_key.clone(), entry.clone()).unwrap();
+
+   assert_eq!(tail.retrieve(&raw_key).unwrap(), vec![entry.clone()]);
+   assert_eq!(tail.retrieve(&typed_key).unwrap(), vec![entry]);
+
+   // Node IDs wrap the same kind of hash, but are a different type altogether, so passing
+   // one where a key is expected doesn't compile (see the `hash::NodeId` docs).
+}
+
+#[test]
+fn mass_store_reports_partial_acceptance() {
+   // Half the nodes only accept tiny blobs, so they will only store part of the collection.
+   let picky_factory = node::Factory::new().max_storage_blob_size(4);
+   let nodes: VecDeque<_> = (0..30)
+      .map(|i| if i % 2 == 0 { node::Node::new().unwrap() } else { picky_factory.create_node().unwrap() })
+      .collect();
+   let nodes = bootstrap_network(nodes);
+   let picky_ids: Vec<_> = nodes.iter().enumerate().filter(|&(i, _)| i % 2 == 1).map(|(_, node)| node.id().clone()).collect();
+
+   let collection = vec![storage::StorageEntry::Blob(vec![0u8; 2]), storage::StorageEntry::Blob(vec![0u8; 16])];
+   let report = nodes[0].mass_store(hash::SubotaiHash::random(), collection).unwrap();
+
+   assert!(!report.fully_accepted.is_empty());
+   assert!(!report.partially.is_empty());
+   assert!(report.fully_accepted.iter().all(|id| !picky_ids.contains(id)));
+   assert!(report.partially.iter().all(|id| picky_ids.contains(id)));
+}
+
+#[test]
+fn mass_stores_rejected_as_a_whole_are_reported_as_failed() {
+   // Half the nodes have room for just two entries, so they will reject the whole collection.
+   let cramped_factory = node::Factory::new().max_storage(2);
+   let nodes: VecDeque<_> = (0..30)
+      .map(|i| if i % 2 == 0 { node::Node::new().unwrap() } else { cramped_factory.create_node().unwrap() })
+      .collect();
+   let nodes = bootstrap_network(nodes);
+   let cramped_ids: Vec<_> = nodes.iter().enumerate().filter(|&(i, _)| i % 2 == 1).map(|(_, node)| node.id().clone()).collect();
+
+   let collection: Vec<_> = (0..3).map(|_| storage::StorageEntry::Value(hash::SubotaiHash::random())).collect();
+   let report = nodes[0].mass_store(hash::SubotaiHash::random(), collection).unwrap();
+
+   assert!(report.partially.is_empty());
+   assert!(report.fully_accepted.iter().all(|id| !cramped_ids.contains(id)));
+   assert!(report.failed.iter().any(|id| cramped_ids.contains(id)));
+}
+
+#[test]
+fn failed_store_reports_results_per_node() {
+   // Most nodes have no storage room left, so the quorum can't be reached.
+   let full_factory = node::Factory::new().max_storage_bytes(0).network_timeout_s(1);
+   let nodes: VecDeque<_> = (0..30)
+      .map(|i| if i % 10 == 0 { node::Node::new().unwrap() } else { full_factory.create_node().unwrap() })
+      .collect();
+   let nodes = bootstrap_network(nodes);
+   let full_ids: Vec<_> = nodes.iter().enumerate().filter(|&(i, _)| i % 10 != 0).map(|(_, node)| node.id().clone()).collect();
+
+   match nodes[0].store(hash::SubotaiHash::random(), storage::StorageEntry::Blob(vec![0u8; 16])) {
+      Err(SubotaiError::StoreQuorumFailed { results }) => {
+         assert!(results.iter().any(|&(_, ref result)| *result == node::StoreResult::StorageFull));
+         for (id, result) in results {
+            if full_ids.contains(&id) {
+               assert_eq!(result, node::StoreResult::StorageFull);
+            }
+         }
+      },
+      _ => panic!("The store should have failed"),
+   }
+}
+
+#[test]
+fn contacts_in_bucket_match_the_filled_bucket() {
+   let node = node::Node::new().unwrap();
+   node.resources.table.fill_bucket(20, 5);
+
+   let contacts = node.contacts_in_bucket(20).unwrap();
+   assert_eq!(contacts.len(), 5);
+   assert!(contacts.iter().all(|info| node.resources.table.bucket_for_node(&info.id) == 20));
+   assert!(node.contacts_in_bucket(21).unwrap().is_empty());
+
+   match node.contacts_in_bucket(hash::HASH_SIZE) {
+      Err(SubotaiError::OutOfBounds) => (),
+      _ => panic!("Bucket index should be out of bounds"),
+   }
+}
+
+#[test]
+fn tagged_entries_keep_their_metadata_through_the_network() {
+   let mut nodes = simulated_network(30);
+   let head = nodes.pop_front().unwrap();
+   let tail = nodes.pop_back().unwrap();
+
+   let key = hash::SubotaiHash::random();
+   let entry = storage::StorageEntry::Tagged { data: vec![0x00, 0x01, 0x02], metadata: b"text/plain".to_vec() };
+   head.store(key.clone(), entry.clone()).unwrap();
+   assert_eq!(tail.retrieve(&key).unwrap(), vec![entry]);
+}
+
+#[test]
+fn added_contacts_appear_in_the_routing_table() {
+   let alpha = node::Node::new().unwrap();
+   let beta  = node::Node::new().unwrap();
+
+   assert_eq!(alpha.add_contact(beta.local_info()), node::ContactUpdate::Added);
+   assert_eq!(alpha.add_contact(beta.local_info()), node::ContactUpdate::Updated);
+   assert_eq!(alpha.contacts(), vec![beta.local_info()]);
+}
+
+#[test]
+fn expirations_from_skewed_senders_are_adjusted() {
+   let alpha = node::Node::new().unwrap();
+   let beta  = node::Node::new().unwrap();
+
+   // Beta's clock runs two hours behind, so its expirations look stale to alpha.
+   let skew = time::Duration::hours(2);
+   let beta_now = time::now() - skew;
+   let key = hash::SubotaiHash::random();
+   let payload = rpc::StorePayload {
+      key         : key.clone(),
+      entry       : storage::StorageEntry::Value(hash::SubotaiHash::random()),
+      expiration  : rpc::SerializableTime::from(beta_now + time::Duration::minutes(30)),
+      sent_at     : rpc::SerializableTime::from(beta_now),
+      max_entries : None,
+   };
+   let store = rpc::Rpc { kind: rpc::Kind::Store(sync::Arc::new(payload)), sender: beta.local_info() };
+   alpha.resources.process_incoming_rpc(store, beta.local_info().address).unwrap();
+
+   let estimate = alpha.clock_skew_estimate().unwrap();
+   assert!((estimate - skew).num_seconds().abs() < 5);
+
+   let stored = alpha.resources.storage.get_all_entries();
+   let expiration = stored.iter().find(|&&(ref stored_key, _)| *stored_key == key).unwrap().1[0].1;
+   let remaining = expiration - time::now();
+   assert!((remaining - time::Duration::minutes(30)).num_seconds().abs() < 5);
+}
+
+#[test]
+fn reinforcing_a_key_adds_replicas() {
+   let nodes = simulated_network_from_factory(30, &node::Factory::new().k_factor(5).network_timeout_s(1));
+   let key = hash::SubotaiHash::random();
+   let entry = storage::StorageEntry::Value(hash::SubotaiHash::random());
+   // Only the reinforcing node holds the entry to begin with, so every holder
+   // afterwards is one of the extra replicas.
+   nodes[0].resources.storage.store(&key, &entry, &(time::now() + time::Duration::hours(1)));
+   let holders = |nodes: &VecDeque<node::Node>| nodes.iter().skip(1).filter(|node| node.resources.storage.retrieve(&key).is_some()).count();
+   assert_eq!(holders(&nodes), 0);
+
+   let added = nodes[0].reinforce(key.clone(), 3).unwrap();
+   assert!(added > 0);
+   assert_eq!(holders(&nodes), added);
+}
+
+#[test]
+fn nodes_can_keep_themselves_out_of_their_routing_table() {
+   // The network only goes on grid if the threshold still works without the self entry.
+   let nodes = simulated_network_from_factory(30, &node::Factory::new().insert_self_in_table(false));
+   for node in &nodes {
+      assert!(node.resources.table.specific_node(node.id()).is_none());
+      assert!(node.contacts().iter().all(|info| info.id != *node.id()));
+      assert_eq!(node.contacts().len(), node.resources.table.len());
+   }
+}
+
+#[test]
+fn oldest_bucket_age_grows_until_a_refresh() {
+   let node = node::Node::new().unwrap();
+   let probe_all_buckets = || for index in 0..hash::HASH_SIZE {
+      let mut id = node.id().0.clone();
+      id.flip_bit(index);
+      node.resources.table.mark_bucket_as_probed(&id);
+   };
+   assert!(node.oldest_bucket_age().is_none());
+
+   probe_all_buckets();
+   let initial_age = node.oldest_bucket_age().unwrap();
+   thread::sleep(StdDuration::from_millis(500));
+   let later_age = node.oldest_bucket_age().unwrap();
+   assert!(later_age - initial_age >= time::Duration::milliseconds(500));
+
+   probe_all_buckets();
+   assert!(node.oldest_bucket_age().unwrap() < later_age);
+}
+
+#[test]
+fn hinted_nodes_receive_stores_outside_the_replica_set() {
+   let nodes = simulated_network_from_factory(30, &node::Factory::new().k_factor(5));
+   let head = &nodes[0];
+   let key = hash::SubotaiHash::random();
+
+   // The farthest contact from the key is never among its closest nodes.
+   let hinted = head.contacts().into_iter().max_by_key(|info| &info.id ^ &key).unwrap();
+   let entry = storage::StorageEntry::Value(hash::SubotaiHash::random());
+   head.store_with_hint(key.clone(), entry.clone(), vec![hinted.id.clone()]).unwrap();
+
+   thread::sleep(StdDuration::from_millis(500));
+   let hinted_node = nodes.iter().find(|node| *node.id() == hinted.id).unwrap();
+   assert_eq!(hinted_node.resources.storage.retrieve(&key), Some(vec![entry]));
+}
+
+#[test]
+fn retrieving_with_expiry_reports_the_stored_expiration() {
+   let mut nodes = simulated_network(30);
+   let head = nodes.pop_front().unwrap();
+   let tail = nodes.pop_back().unwrap();
+
+   let key = hash::SubotaiHash::random();
+   let entry = storage::StorageEntry::Value(hash::SubotaiHash::random());
+   let expiration = time::now() + time::Duration::minutes(30);
+   head.resources.store(key.clone(), entry.clone(), expiration).unwrap();
+
+   let retrieved = tail.retrieve_with_expiry(&key).unwrap();
+   assert_eq!(retrieved.len(), 1);
+   assert_eq!(retrieved[0].0, entry);
+   assert!((retrieved[0].1 - expiration).num_seconds().abs() < 2);
+}
+
+#[test]
+fn storing_with_expiration_honors_shorter_times_and_caps_longer_ones() {
+   let mut nodes = simulated_network(30);
+   let head = nodes.pop_front().unwrap();
+   let tail = nodes.pop_back().unwrap();
+
+   let short_key = hash::SubotaiHash::random();
+   let long_key = hash::SubotaiHash::random();
+   let entry = storage::StorageEntry::Value(hash::SubotaiHash::random());
+   head.store_with_expiration(&short_key, entry.clone(), time::Duration::minutes(5)).unwrap();
+   head.store_with_expiration(&long_key, entry.clone(), time::Duration::hours(1000)).unwrap();
+
+   let short = tail.retrieve_with_expiry(&short_key).unwrap();
+   assert!((short[0].1 - (time::now() + time::Duration::minutes(5))).num_seconds().abs() < 5);
+
+   let base = time::Duration::hours(head.configuration().base_expiration_time_hrs);
+   let long = tail.retrieve_with_expiry(&long_key).unwrap();
+   assert!((long[0].1 - (time::now() + base)).num_seconds().abs() < 5);
+}
+
+#[test]
+fn removed_entries_disappear_from_every_replica() {
+   let mut nodes = simulated_network(30);
+   let head = nodes.pop_front().unwrap();
+   let tail = nodes.pop_back().unwrap();
+
+   let key = hash::SubotaiHash::random();
+   let entry = storage::StorageEntry::Value(hash::SubotaiHash::random());
+   let other_entry = storage::StorageEntry::Blob(vec![1, 2, 3]);
+   head.store(&key, entry.clone()).unwrap();
+   head.store(&key, other_entry.clone()).unwrap();
+   thread::sleep(StdDuration::from_millis(500));
+
+   // Removing from the storing node targets the same replicas the entry was stored in.
+   head.remove(&key, &entry).unwrap();
+   thread::sleep(StdDuration::from_millis(500));
+   assert!(nodes.iter().chain(vec![&head, &tail]).all(|node| {
+      node.resources.storage.retrieve(&key).map_or(true, |entries| !entries.contains(&entry))
+   }));
+   assert_eq!(tail.retrieve(&key).unwrap(), vec![other_entry]);
+
+   // Removing an entry that isn't stored anymore is harmless.
+   assert!(tail.remove(&key, &entry).is_ok());
+}
+
+#[test]
+fn verifying_replication_counts_the_replicas_holding_an_entry() {
+   let mut nodes = simulated_network(30);
+   let head = nodes.pop_front().unwrap();
+   let tail = nodes.pop_back().unwrap();
+
+   let key = hash::SubotaiHash::random();
+   let entry = storage::StorageEntry::Value(hash::SubotaiHash::random());
+   head.store(&key, entry.clone()).unwrap();
+   thread::sleep(StdDuration::from_millis(500));
+
+   let holders = nodes.iter().chain(vec![&head, &tail]).filter(|node| {
+      node.resources.storage.retrieve(&key).map_or(false, |entries| entries.contains(&entry))
+   }).count();
+   assert!(holders > 0);
+   assert_eq!(head.verify_replication(&key, &entry).unwrap(), holders);
+
+   // An entry nobody stored isn't held by any replica.
+   let missing = storage::StorageEntry::Blob(vec![1, 2, 3]);
+   assert_eq!(head.verify_replication(&key, &missing).unwrap(), 0);
+}
+
+#[test]
+fn large_blobs_are_stored_in_chunks_and_reassembled() {
+   let factory = node::Factory::new().max_storage_blob_size(100);
+   let mut nodes = simulated_network_from_factory(30, &factory);
+   let head = nodes.pop_front().unwrap();
+   let tail = nodes.pop_back().unwrap();
+
+   let key = hash::SubotaiHash::random();
+   let data: Vec<u8> = (0..1050).map(|i| (i % 251) as u8).collect();
+   head.store_large(&key, &data).unwrap();
+   assert_eq!(tail.retrieve_large(&key).unwrap(), data);
+
+   // A manifest referring to chunks that were never stored reports the first missing one.
+   let broken_key = hash::SubotaiHash::random();
+   let manifest = storage::StorageEntry::Manifest { length: 10, chunks: vec![hash::SubotaiHash::random()] };
+   head.store(&broken_key, manifest).unwrap();
+   match tail.retrieve_large(&broken_key) {
+      Err(SubotaiError::MissingChunk { index }) => assert_eq!(index, 0),
+      other => panic!("Unexpected result: {:?}", other),
+   }
+}
+
+#[test]
+fn large_blobs_nest_manifests_once_a_single_one_would_be_too_big() {
+   // Manifests list up to two chunks with this blob size.
+   let factory = node::Factory::new().max_storage_blob_size(40);
+   let mut nodes = simulated_network_from_factory(30, &factory);
+   let head = nodes.pop_front().unwrap();
+   let tail = nodes.pop_back().unwrap();
+   assert_eq!(head.resources.manifest_fanout(), 2);
+
+   for length in vec![80, 81, 1000] {
+      let key = hash::SubotaiHash::random();
+      let data: Vec<u8> = (0..length).map(|i| (i % 251) as u8).collect();
+      head.store_large(&key, &data).unwrap();
+      assert_eq!(tail.retrieve_large(&key).unwrap(), data);
+   }
+
+   // Missing chunks are identified by their index in the whole blob, however deep they are.
+   let broken_key = hash::SubotaiHash::random();
+   let chunk = storage::StorageEntry::Blob(vec![7; 40]);
+   let nested = storage::StorageEntry::Manifest { length: 80, chunks: vec![chunk.content_hash(), hash::SubotaiHash::random()] };
+   let nested_key = node::resources::Resources::chunk_key(&broken_key, 0);
+   head.store(&node::resources::Resources::chunk_key(&nested_key, 0), chunk).unwrap();
+   head.store(&nested_key, nested.clone()).unwrap();
+   head.store(&broken_key, storage::StorageEntry::Manifest { length: 80, chunks: vec![nested.content_hash()] }).unwrap();
+   match tail.retrieve_large(&broken_key) {
+      Err(SubotaiError::MissingChunk { index }) => assert_eq!(index, 1),
+      other => panic!("Unexpected result: {:?}", other),
+   }
+}
+
+#[test]
+fn full_manifests_fit_in_a_single_rpc() {
+   let node = node::Node::new().unwrap();
+   let chunks = vec![hash::SubotaiHash::random(); node.resources.manifest_fanout()];
+   let manifest = storage::StorageEntry::Manifest { length: usize::max_value(), chunks: chunks };
+   let expiration = rpc::SerializableTime::from(time::now());
+   let rpc = rpc::Rpc::store(node.local_info(), hash::SubotaiHash::random(), manifest, expiration);
+   assert!(rpc.serialize().is_ok());
+}
+
+#[test]
+fn retrieved_entries_are_cached_for_the_configured_time() {
+   let factory = node::Factory::new().read_cache_ttl_s(600);
+   let mut nodes = simulated_network_from_factory(30, &factory);
+   let head = nodes.pop_front().unwrap();
+
+   // The head is the closest node to its own ID, so it holds a regular copy instead of a cache.
+   let key = head.id().0.clone();
+   let entry = storage::StorageEntry::Value(hash::SubotaiHash::random());
+   head.store(&key, entry.clone()).unwrap();
+   thread::sleep(StdDuration::from_millis(500));
+
+   // Any node that didn't get the entry stored by now isn't a replica, so it caches what it retrieves.
+   let retriever = nodes.iter().find(|node| node.resources.storage.retrieve(&key).is_none()).unwrap();
+   let before = time::now();
+   assert_eq!(retriever.retrieve(&key).unwrap(), vec![entry]);
+   thread::sleep(StdDuration::from_millis(200));
+
+   let cached = retriever.resources.storage.cached_entries();
+   assert_eq!(cached.len(), 1);
+   let expiration = (cached[0].1)[0].1;
+   assert!(expiration >= before + time::Duration::seconds(600));
+   assert!(expiration <= time::now() + time::Duration::seconds(600));
+}
+
+#[test]
+fn remote_stores_are_reported_as_network_updates() {
+   let mut nodes = simulated_network(30);
+   let head = nodes.pop_front().unwrap();
+   let tail = nodes.pop_back().unwrap();
+
+   // The tail is the closest node to its own ID, so it's a replica for the key.
+   let key = tail.id().0.clone();
+   let updates = tail.watch_network().during(time::Duration::seconds(3));
+   head.store(&key, storage::StorageEntry::Value(hash::SubotaiHash::random())).unwrap();
+
+   let stored_keys: Vec<_> = updates.filter_map(|update| match update {
+      node::NetworkUpdate::StoredEntry(key) => Some(key),
+      _ => None,
+   }).take(1).collect();
+   assert_eq!(stored_keys, vec![key]);
+}
+
+#[test]
+fn entries_are_republished_at_the_configured_interval() {
+   let factory = node::Factory::new().maintenance_interval_s(1).republish_interval_s(2);
+   let mut nodes = simulated_network_from_factory(30, &factory);
+   let head = nodes.pop_front().unwrap();
+   let tail = nodes.pop_back().unwrap();
+
+   // The tail is the closest node to its own ID, so it's a replica for the key.
+   let key = tail.id().0.clone();
+   let republishes = tail.receptions().of_kind(receptions::KindFilter::MassStore).during(time::Duration::seconds(10));
+   head.store(&key, storage::StorageEntry::Value(hash::SubotaiHash::random())).unwrap();
+
+   // Entries are only republished once a full interval has passed without being stored again.
+   let republished = republishes.filter(|rpc| match rpc.kind {
+      rpc::Kind::MassStore(ref payload) => payload.key == key,
+      _ => false,
+   }).take(1).count();
+   assert_eq!(republished, 1);
+}
+
+#[test]
+fn watching_a_key_reports_stores_to_it() {
+   let mut nodes = simulated_network(30);
+   let head = nodes.pop_front().unwrap();
+   let tail = nodes.pop_back().unwrap();
+
+   // The tail is the closest node to its own ID, so it's a replica for the key.
+   let key = tail.id().0.clone();
+   let entry = storage::StorageEntry::Value(hash::SubotaiHash::random());
+   let watcher = tail.watch(&key).during(time::Duration::seconds(3));
+
+   head.resources.store(hash::SubotaiHash::random(), entry.clone(), time::now() + time::Duration::minutes(5)).unwrap();
+   head.resources.store(key.clone(), entry.clone(), time::now() + time::Duration::minutes(5)).unwrap();
+
+   let updates: Vec<_> = watcher.take(1).collect();
+   assert_eq!(updates.len(), 1);
+   assert_eq!(updates[0].entry, entry);
+   assert_eq!(&updates[0].sender.id, head.id());
+}
+
+#[test]
+fn retrieved_values_are_cached_apart_from_stored_ones() {
+   let alpha = node::Node::new().unwrap();
+   let beta  = node::Node::new().unwrap();
+   let stored_key = hash::SubotaiHash::random();
+   let retrieved_key = hash::SubotaiHash::random();
+   let entry = storage::StorageEntry::Value(hash::SubotaiHash::random());
+   let expiration = time::now() + time::Duration::minutes(30);
+   alpha.resources.storage.store(&stored_key, &entry, &expiration);
+   beta.resources.storage.store(&retrieved_key, &entry, &expiration);
+
+   let responses = alpha.receptions()
+      .of_kind(receptions::KindFilter::RetrieveResponse)
+      .during(time::Duration::seconds(1))
+      .take(1);
+   alpha.resources.send(&rpc::Rpc::retrieve(alpha.local_info(), retrieved_key.clone()), &beta.local_info().address).unwrap();
+   assert_eq!(responses.count(), 1);
+
+   let cached = alpha.cached_entries();
+   let authoritative = alpha.authoritative_entries();
+   assert_eq!(cached.len(), 1);
+   assert_eq!(cached[0].0, retrieved_key);
+   assert_eq!(authoritative.len(), 1);
+   assert_eq!(authoritative[0].0, stored_key);
+}
+
+#[test]
+fn shutting_down_frees_the_ports() {
+   let node = node::Node::new().unwrap();
+   let other = node::Node::new().unwrap();
+   assert!(node.resources.ping(&other.local_info().address).is_ok());
+   let inbound = node.resources.inbound.local_addr().unwrap();
+   let outbound = node.resources.outbound.local_addr().unwrap();
+
+   let start = time::SteadyTime::now();
+   assert!(node.shutdown());
+   assert!(time::SteadyTime::now() - start < time::Duration::seconds(2));
+   assert!(net::UdpSocket::bind(inbound).is_ok());
+   assert!(net::UdpSocket::bind(outbound).is_ok());
+}
+
+#[test]
+fn shutting_down_waits_for_detached_threads_only_up_to_a_network_timeout() {
+   let node = node::Factory::new().network_timeout_s(1).create_node().unwrap();
+   let finished = sync::Arc::new(sync::atomic::AtomicBool::new(false));
+   let finished_clone = finished.clone();
+   node::Node::spawn_detached(&node.resources, move |_| {
+      thread::sleep(StdDuration::from_millis(300));
+      finished_clone.store(true, sync::atomic::Ordering::SeqCst);
+   });
+   node::Node::spawn_detached(&node.resources, |_| thread::sleep(StdDuration::from_secs(10)));
+
+   let start = time::SteadyTime::now();
+   assert!(!node.shutdown());
+   assert!(finished.load(sync::atomic::Ordering::SeqCst));
+   assert!(time::SteadyTime::now() - start < time::Duration::seconds(3));
+}
+
+#[test]
+fn read_only_nodes_reject_stores_but_serve_retrieves() {
+   let mut nodes = simulated_network_from_factory(30, &node::Factory::new().network_timeout_s(1));
+   let head = nodes.pop_front().unwrap();
+   let tail = nodes.pop_back().unwrap();
+
+   let key = hash::SubotaiHash::random();
+   let entry = storage::StorageEntry::Value(hash::SubotaiHash::random());
+   head.store(key.clone(), entry.clone()).unwrap();
+   thread::sleep(StdDuration::from_millis(500));
+
+   for node in nodes.iter().chain(vec![&head, &tail]) {
+      node.set_read_only(true);
+   }
+
+   match head.store(hash::SubotaiHash::random(), entry.clone()) {
+      Err(SubotaiError::StoreQuorumFailed { results }) => 
+         assert!(results.iter().all(|&(_, ref result)| *result == node::StoreResult::ReadOnly)),
+      _ => panic!("Read only nodes should reject stores"),
+   }
+   assert_eq!(tail.retrieve(&key).unwrap(), vec![entry]);
+}
+
+#[test]
+fn quorum_retrieve_succeeds_when_replicas_agree() {
+   let mut nodes = simulated_network(30);
+   let head = nodes.pop_front().unwrap();
+   let tail = nodes.pop_back().unwrap();
+
+   let key = hash::SubotaiHash::random();
+   let entry = storage::StorageEntry::Value(hash::SubotaiHash::random());
+   head.store(key.clone(), entry.clone()).unwrap();
+   thread::sleep(StdDuration::from_millis(500));
+
+   assert_eq!(tail.retrieve_quorum(&key, 3).unwrap(), vec![entry]);
+}
+
+#[test]
+fn quorum_retrieve_fails_when_replicas_disagree() {
+   let mut nodes = simulated_network_from_factory(30, &node::Factory::new().network_timeout_s(1));
+   let head = nodes.pop_front().unwrap();
+   let tail = nodes.pop_back().unwrap();
+
+   let key = hash::SubotaiHash::random();
+   let entry = storage::StorageEntry::Value(hash::SubotaiHash::random());
+   head.store(key.clone(), entry.clone()).unwrap();
+   thread::sleep(StdDuration::from_millis(500));
+
+   // Half the replicas get an extra entry behind the network's back.
+   let expiration = time::now() + time::Duration::minutes(30);
+   let diverging = storage::StorageEntry::Value(hash::SubotaiHash::random());
+   for (i, node) in nodes.iter().chain(vec![&head, &tail]).filter(|node| node.resources.storage.retrieve(&key).is_some()).enumerate() {
+      if i % 2 == 0 {
+         node.resources.storage.store(&key, &diverging, &expiration);
+      }
+   }
+
+   match tail.retrieve_quorum(&key, 15) {
+      Err(SubotaiError::InconsistentReplicas) => (),
+      _ => panic!("Replicas should disagree"),
+   }
+}
+
+#[test]
+fn quorum_retrieve_does_not_count_the_local_copy_twice() {
+   let mut nodes = simulated_network_from_factory(30, &node::Factory::new().network_timeout_s(1));
+   let tail = nodes.pop_back().unwrap();
+
+   // Only the retrieving node holds the key, which is as close to it as possible.
+   let key = hash::SubotaiHash::random_at_distance(&tail.resources.id, 2);
+   let entry = storage::StorageEntry::Value(hash::SubotaiHash::random());
+   tail.resources.storage.store(&key, &entry, &(time::now() + time::Duration::minutes(30)));
+
+   let replicas = tail.resources.retrieve_all(&key).unwrap();
+   assert_eq!(replicas.len(), 1);
+   assert_eq!(replicas[0].0.id, tail.resources.id);
+   assert!(tail.retrieve_quorum(&key, 2).is_err());
+}
+
+#[test]
+fn state_waits_and_receptions_listen_on_their_own_buses() {
+   let alpha = node::Node::new().unwrap();
+   let beta  = node::Node::new().unwrap();
+
+   // State changes reach `wait_for_state` through the state bus.
+   let resources = alpha.resources.clone();
+   thread::spawn(move || {
+      thread::sleep(StdDuration::from_millis(200));
+      resources.set_state(node::State::Defensive);
+   });
+   alpha.wait_for_state(node::State::Defensive);
+
+   // Incoming RPCs reach `receptions` through the reception bus.
+   let pings = alpha.receptions()
+      .of_kind(receptions::KindFilter::Ping)
+      .during(time::Duration::seconds(1))
+      .take(1);
+   beta.resources.ping_and_forget(&alpha.local_info().address).unwrap();
+   assert_eq!(pings.count(), 1);
+}
+
+#[test]
+fn probe_depth_adapts_to_the_network_size() {
+   let small_network = simulated_network(25);
+   let small = &small_network[0];
+   assert!(small.estimated_network_size() < 200);
+   assert!(small.probe_auto(&hash::SubotaiHash::random()).is_ok());
+
+   // A table densely populated around the node suggests a huge network.
+   let large = node::Node::new().unwrap();
+   for _ in 0..large.configuration().k_factor {
+      let mut id = large.id().0.clone();
+      id.raw[0] = rand::random();
+      id.raw[1] = rand::random();
+      large.resources.table.update_node(node_info_no_net(id));
+   }
+   assert!(large.estimated_network_size() > 1_000_000);
+
+   assert!(small.resources.auto_probe_depth() < large.resources.auto_probe_depth());
+   assert_eq!(large.resources.auto_probe_depth(), 2 * large.configuration().k_factor);
+}
+
+#[test]
+fn storing_if_absent_only_succeeds_once() {
+   let mut nodes = simulated_network(30);
+   let head = nodes.pop_front().unwrap();
+   let tail = nodes.pop_back().unwrap();
+
+   let key = hash::SubotaiHash::random();
+   let first = storage::StorageEntry::Value(hash::SubotaiHash::random());
+   let second = storage::StorageEntry::Value(hash::SubotaiHash::random());
+   assert!(head.store_if_absent(key.clone(), first.clone()).unwrap());
+   thread::sleep(StdDuration::from_millis(500));
+
+   assert!(!tail.store_if_absent(key.clone(), second).unwrap());
+   thread::sleep(StdDuration::from_millis(500));
+   assert_eq!(tail.retrieve_authoritative(&key).unwrap(), vec![first]);
+}
+
+#[test]
+fn a_single_occupied_replica_does_not_refuse_a_bounded_store() {
+   let mut nodes = simulated_network(30);
+   let tail = nodes.pop_back().unwrap();
+
+   // Only the replica closest to the key already holds an entry for it.
+   let key = hash::SubotaiHash::random();
+   let occupied = nodes.iter().min_by_key(|node| node.id() ^ &key).unwrap();
+   let expiration = time::now() + time::Duration::hours(1);
+   let previous = storage::StorageEntry::Value(hash::SubotaiHash::random());
+   occupied.resources.storage.store(&key, &previous, &expiration);
+
+   let entry = storage::StorageEntry::Value(hash::SubotaiHash::random());
+   assert!(tail.store_if_absent(key.clone(), entry).unwrap());
+}
+
+#[test]
+fn bounded_appends_stop_at_the_cap() {
+   let mut nodes = simulated_network(30);
+   let head = nodes.pop_front().unwrap();
+   let tail = nodes.pop_back().unwrap();
+
+   let key = hash::SubotaiHash::random();
+   let entries: Vec<_> = (0..4).map(|_| storage::StorageEntry::Value(hash::SubotaiHash::random())).collect();
+   for entry in &entries[..3] {
+      assert!(head.append_bounded(key.clone(), entry.clone(), 3).unwrap());
+   }
+   thread::sleep(StdDuration::from_millis(500));
+
+   assert!(!tail.append_bounded(key.clone(), entries[3].clone(), 3).unwrap());
+   thread::sleep(StdDuration::from_millis(500));
+   let mut stored = tail.retrieve_authoritative(&key).unwrap();
+   stored.sort_by_key(|entry| entry.content_hash());
+   let mut expected = entries[..3].to_vec();
+   expected.sort_by_key(|entry| entry.content_hash());
+   assert_eq!(stored, expected);
+}
+
+#[test]
+fn bootstrapping_a_live_node_does_nothing() {
+   let alpha = node::Node::new().unwrap();
+   let beta  = node::Node::new().unwrap();
+   let beta_seed = beta.local_info().address;
+
+   assert!(alpha.bootstrap(&beta_seed).is_ok());
+   thread::sleep(StdDuration::from_millis(200));
+   alpha.resources.set_state(node::State::OnGrid);
+   let stats = alpha.rpc_stats();
+
+   assert!(alpha.bootstrap(&beta_seed).is_ok());
+   thread::sleep(StdDuration::from_millis(200));
+   assert_eq!(alpha.rpc_stats().get("Ping"), stats.get("Ping"));
+   assert_eq!(alpha.rpc_stats().get("Probe"), stats.get("Probe"));
+}
+
+#[test]
+fn ping_with_timeout_gives_up_quickly_on_silent_nodes() {
+   let alpha = node::Node::new().unwrap();
+   let beta  = node::Node::new().unwrap();
+   assert!(alpha.ping_with_timeout(&beta.local_info(), time::Duration::seconds(1)).is_ok());
+
+   // A bound socket that never answers.
+   let silent = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+   let target = routing::NodeInfo::new(hash::SubotaiHash::random(), silent.local_addr().unwrap());
+   let start = time::SteadyTime::now();
+   match alpha.ping_with_timeout(&target, time::Duration::milliseconds(100)) {
+      Err(SubotaiError::NoResponse) => (),
+      _ => panic!("Expected the ping to time out"),
+   }
+   assert!(time::SteadyTime::now() - start < time::Duration::seconds(1));
+}
+
+#[test]
+fn digest_exchange_reports_keys_on_divergence() {
+   let alpha = node::Node::new().unwrap();
+   let beta  = node::Node::new().unwrap();
+   let key = hash::SubotaiHash::random();
+   let entry = storage::StorageEntry::Blob(vec![1, 2, 3]);
+   let expiration = time::now() + time::Duration::minutes(30);
+   alpha.resources.storage.store(&key, &entry, &expiration);
+   beta.resources.storage.store(&key, &entry, &(expiration + time::Duration::minutes(1)));
+
+   assert_eq!(alpha.storage_digest(), beta.storage_digest());
+   assert_eq!(alpha.resources.exchange_digest(&beta.local_info()).unwrap(), None);
+
+   let extra_key = hash::SubotaiHash::random();
+   beta.resources.storage.store(&extra_key, &entry, &expiration);
+   assert!(alpha.storage_digest() != beta.storage_digest());
+
+   let mut beta_keys = alpha.resources.exchange_digest(&beta.local_info()).unwrap().unwrap();
+   beta_keys.sort();
+   let mut expected = vec![key, extra_key];
+   expected.sort();
+   assert_eq!(beta_keys, expected);
+}
+
+#[test]
+fn digest_exchange_pages_key_lists_too_big_for_a_datagram() {
+   let alpha = node::Node::new().unwrap();
+   let beta  = node::Node::new().unwrap();
+   let entry = storage::StorageEntry::Blob(vec![1, 2, 3]);
+   let expiration = time::now() + time::Duration::minutes(30);
+
+   // Enough keys that listing them all would overflow a single RPC.
+   let mut keys: Vec<_> = (0..3500).map(|_| hash::SubotaiHash::random()).collect();
+   for key in &keys {
+      beta.resources.storage.store(key, &entry, &expiration);
+   }
+
+   let mut beta_keys = alpha.resources.exchange_digest(&beta.local_info()).unwrap().unwrap();
+   beta_keys.sort();
+   keys.sort();
+   assert_eq!(beta_keys, keys);
+}
+
+#[test]
+fn reconciliation_converges_divergent_replicas() {
+   let factory = node::Factory::new().reconcile_interval_s(0);
+   let alpha = factory.create_node().unwrap();
+   let beta  = factory.create_node().unwrap();
+
+   let expiration = time::now() + time::Duration::minutes(30);
+   let shared = hash::SubotaiHash::random();
+   let only_alpha = hash::SubotaiHash::random();
+   let only_beta = hash::SubotaiHash::random();
+   let entry = storage::StorageEntry::Blob(vec![1, 2, 3]);
+   for node in &[&alpha, &beta] {
+      node.resources.storage.store(&shared, &entry, &expiration);
+   }
+   alpha.resources.storage.store(&only_alpha, &entry, &expiration);
+   alpha.resources.storage.store(&only_alpha, &storage::StorageEntry::Blob(vec![4]), &expiration);
+   beta.resources.storage.store(&only_beta, &entry, &expiration);
+   assert!(alpha.storage_digest() != beta.storage_digest());
+
+   assert_eq!(alpha.reconcile_with(&beta.local_info()).unwrap(), 3);
+   assert_eq!(alpha.storage_digest(), beta.storage_digest());
+   assert_eq!(beta.resources.storage.retrieve(&only_alpha).unwrap().len(), 2);
+   assert_eq!(alpha.reconcile_with(&beta.local_info()).unwrap(), 0);
+}
+
+fn node_info_no_net<I: Into<hash::NodeId>>(id : I) -> routing::NodeInfo {
+   routing::NodeInfo::new(id, net::SocketAddr::from_str("0.0.0.0:0").unwrap())
 }
@@ -1,9 +1,11 @@
 use std::{net, mem, sync, iter};
-use {hash, time, node};
+use std::str::FromStr;
+use {hash, time, node, SubotaiError, SubotaiResult};
 use std::cmp::PartialEq;
 use hash::HASH_SIZE;
-use hash::SubotaiHash;
+use hash::{SubotaiHash, NodeId};
 use std::collections::VecDeque;
+use rand::{thread_rng, Rng};
 
 #[cfg(test)]
 mod tests;
@@ -16,15 +18,19 @@ mod tests;
 /// be resolved later.
 pub struct Table {
    buckets       : Vec<sync::RwLock<Bucket> >,
-   parent_id     : SubotaiHash,
+   parent_id     : NodeId,
    configuration : node::Configuration
 }
 
 /// ID - Address pair that identifies a unique Subotai node in the network.
+///
+/// Nodes may also carry an application-defined region tag (e.g. a datacenter ID),
+/// which other nodes in the same region use to prefer them as wave candidates.
 #[derive(Serialize, Deserialize, Debug, Clone, Eq)]
 pub struct NodeInfo {
-   pub id      : SubotaiHash,
+   pub id      : NodeId,
    pub address : net::SocketAddr,
+   pub region  : Option<u16>,
 }
 
 /// Result of a table lookup. 
@@ -50,15 +56,18 @@ pub enum UpdateResult {
    /// There wasn't an entry for the node and the bucket was full,
    /// so it has been added, evicting an older node.
    CausedConflict(EvictionConflict),
+   /// There wasn't an entry for the node, but the table already holds 
+   /// `max_total_contacts` nodes, so it has been ignored.
+   TableFull,
 }
 
 impl Table {
    /// Constructs a routing table based on a parent node id. Other nodes
    /// will be stored in this table based on their distance to the node id provided.
-   pub fn new(id: hash::SubotaiHash, configuration: node::Configuration) -> Table {
+   pub fn new<I: Into<NodeId>>(id: I, configuration: node::Configuration) -> Table {
       Table { 
          buckets       : (0..HASH_SIZE).map(|_| sync::RwLock::new(Bucket::with_capacity(configuration.k_factor))).collect(),
-         parent_id     : id,
+         parent_id     : id.into(),
          configuration : configuration,
       }
    }
@@ -72,6 +81,22 @@ impl Table {
       self.len() == 0
    }
 
+   /// Returns the number of buckets holding at least one node.
+   pub fn populated_buckets(&self) -> usize {
+      self.bucket_stats().into_iter().filter(|&occupancy| occupancy > 0).count()
+   }
+
+   /// Returns the index of the most distant bucket holding at least one node, or
+   /// `None` if the table is empty.
+   pub fn farthest_contact_bucket(&self) -> Option<usize> {
+      self.bucket_stats().into_iter().rposition(|occupancy| occupancy > 0)
+   }
+
+   /// Returns the number of nodes held by each bucket, indexed by bucket.
+   pub fn bucket_stats(&self) -> Vec<usize> {
+      self.buckets.iter().map(|bucket| bucket.read().unwrap().entries.len()).collect()
+   }
+
    /// Inserts a node in the routing table. Employs least-recently-seen eviction
    /// by kicking out the oldest node in case the bucket is full, and registering
    /// an eviction conflict that can be revised later.
@@ -82,13 +107,19 @@ impl Table {
    /// mode, that is adopted when too many conflicts happen in a short period
    /// of time. Defensive mode causes the node to reject any updates that would
    /// cause conflicts until a given time period has elapsed.
+   ///
+   /// If the table holds `max_total_contacts` nodes, only known nodes are updated.
    pub fn update_node(&self, info: NodeInfo) -> UpdateResult {
       let mut result = UpdateResult::AddedNode;
+      let max_contacts = self.configuration.max_total_contacts;
+      let table_full = max_contacts > 0 && self.len() >= max_contacts;
       let index = self.bucket_for_node(&info.id);
       let mut bucket = self.buckets[index].write().unwrap();
 
       if bucket.entries.contains(&info) {
          result = UpdateResult::UpdatedNode;
+      } else if table_full {
+         return UpdateResult::TableFull;
       }
 
       bucket.entries.retain(|stored_info| info.id != stored_info.id);
@@ -106,8 +137,23 @@ impl Table {
       result
    }
 
+   /// Reports whether a node would be stored without evicting any other, which is
+   /// the case if it's already known, or if its bucket has room and the table isn't
+   /// at its `max_total_contacts` cap.
+   pub fn would_store(&self, id: &NodeId) -> bool {
+      let max_contacts = self.configuration.max_total_contacts;
+      let table_full = max_contacts > 0 && self.len() >= max_contacts;
+      let bucket = self.buckets[self.bucket_for_node(id)].read().unwrap();
+
+      if bucket.entries.iter().any(|info| &info.id == id) {
+         true
+      } else {
+         !table_full && bucket.entries.len() < self.configuration.k_factor
+      }
+   }
+
    /// Removes a node from the routing table, if present.
-   pub fn remove_node(&self, id: &hash::SubotaiHash) {
+   pub fn remove_node(&self, id: &NodeId) {
       let index = self.bucket_for_node(id);
       let mut bucket = self.buckets[index].write().unwrap();
       bucket.entries.retain(|stored_info| id != &stored_info.id);
@@ -135,11 +181,11 @@ impl Table {
    /// Kademlia paper with bucket splitting, but it avoids the necessity of
    /// splitting the buckets, reducing the amount of dynamic allocations
    /// needed. 
-   pub fn lookup(&self, id: &SubotaiHash, n: usize, blacklist: Option<&Vec<SubotaiHash>>) -> LookupResult {
+   pub fn lookup(&self, id: &NodeId, n: usize, blacklist: Option<&Vec<NodeId>>) -> LookupResult {
       match self.specific_node(id) {
          Some(info) => LookupResult::Found(info),
          None =>  {
-            let closest: Vec<NodeInfo> = self.closest_nodes_to(id)
+            let closest: Vec<NodeInfo> = self.closest_others_to(id)
                .filter(|info| Self::is_allowed(&info.id, blacklist))
                .take(n)
                .collect();
@@ -153,7 +199,7 @@ impl Table {
       }
    }
 
-   fn is_allowed(id: &SubotaiHash, blacklist: Option<&Vec<SubotaiHash>>) -> bool {
+   fn is_allowed(id: &NodeId, blacklist: Option<&Vec<NodeId>>) -> bool {
       if let Some(blacklist) = blacklist {
          !blacklist.contains(id)
       } else {
@@ -188,6 +234,16 @@ impl Table {
    /// Buckets already visited may be modified elsewhere through iteraton, 
    /// and unvisited buckets may accrue new nodes.
    pub fn closest_nodes_to<'a,'b>(&'a self, id: &'b SubotaiHash) -> ClosestNodesTo<'a,'b> {
+      self.closest_nodes_iterator(id, false)
+   }
+
+   /// Same as `closest_nodes_to`, but the parent node is never included,
+   /// even if it's present in the table.
+   pub fn closest_others_to<'a,'b>(&'a self, id: &'b SubotaiHash) -> ClosestNodesTo<'a,'b> {
+      self.closest_nodes_iterator(id, true)
+   }
+
+   fn closest_nodes_iterator<'a,'b>(&'a self, id: &'b SubotaiHash, skip_parent: bool) -> ClosestNodesTo<'a,'b> {
       let distance = &self.parent_id ^ id;
       let descent  = distance.clone().into_ones().rev();
       let ascent   = distance.into_zeroes();
@@ -198,20 +254,21 @@ impl Table {
          reference      : id,
          lookup_order   : lookup_order,
          current_bucket : Vec::with_capacity(self.configuration.k_factor),
+         skip_parent    : skip_parent,
       }
    }
 
    /// Returns a table entry for the specific node with a given hash.
-   pub fn specific_node(&self, id: &SubotaiHash) -> Option<NodeInfo> {
+   pub fn specific_node(&self, id: &NodeId) -> Option<NodeInfo> {
       let index = self.bucket_for_node(id);
       let entries = &self.buckets[index].read().unwrap().entries;
-      entries.iter().find(|info| *id == info.id).cloned()
+      entries.iter().find(|info| id == &info.id).cloned()
    }
 
    /// Returns the appropriate position for a node, by computing
    /// the index where their prefix starts differing.
    pub fn bucket_for_node(&self, id: &SubotaiHash) -> usize {
-       (&self.parent_id ^ id).height().unwrap_or(0)
+       self.parent_id.distance_to(id).unwrap_or(0)
    }
 
    pub fn revert_conflict(&self, conflict: EvictionConflict) {
@@ -230,6 +287,83 @@ impl Table {
       bucket.last_probe = Some(time::SteadyTime::now());
    }
 
+   /// Returns the index of the bucket most overdue for a refresh, if any. Buckets
+   /// that have never been probed are always due.
+   ///
+   /// Bucket indices are the height of the xor distance to the parent node, so
+   /// lower indices are closer. See `refresh_interval` for how often each is due.
+   pub fn bucket_due_for_refresh(&self, base_interval: time::Duration) -> Option<usize> {
+      self.bucket_due_for_refresh_at(base_interval, time::SteadyTime::now())
+   }
+
+   fn bucket_due_for_refresh_at(&self, base_interval: time::Duration, now: time::SteadyTime) -> Option<usize> {
+      let times: Vec<Option<time::SteadyTime>> = self.buckets.iter()
+         .map(|bucket| bucket.read().unwrap().last_probe)
+         .collect();
+
+      if let Some(index) = times.iter().position(|option| option.is_none()) {
+         return Some(index);
+      }
+
+      self.overdue_buckets_at(base_interval, now)
+         .into_iter()
+         .max_by_key(|&(_, overdue)| overdue)
+         .map(|(index, _)| index)
+   }
+
+   /// Like `bucket_due_for_refresh`, but picks randomly among the buckets due for a refresh,
+   /// with a probability proportional to how overdue they are. This makes maintenance
+   /// probes harder to predict for an observer.
+   pub fn random_bucket_due_for_refresh(&self, base_interval: time::Duration) -> Option<usize> {
+      self.random_bucket_due_for_refresh_at(base_interval, time::SteadyTime::now())
+   }
+
+   fn random_bucket_due_for_refresh_at(&self, base_interval: time::Duration, now: time::SteadyTime) -> Option<usize> {
+      let mut rng = thread_rng();
+      let never_probed: Vec<_> = self.buckets.iter()
+         .enumerate()
+         .filter(|&(_, bucket)| bucket.read().unwrap().last_probe.is_none())
+         .map(|(index, _)| index)
+         .collect();
+
+      if !never_probed.is_empty() {
+         return rng.choose(&never_probed).cloned();
+      }
+
+      let overdue = self.overdue_buckets_at(base_interval, now);
+      let total_weight: i64 = overdue.iter().map(|&(_, overdue)| overdue.num_seconds() + 1).sum();
+      if total_weight == 0 {
+         return None;
+      }
+
+      let mut pick = rng.gen_range(0, total_weight);
+      for (index, overdue) in overdue {
+         let weight = overdue.num_seconds() + 1;
+         if pick < weight {
+            return Some(index);
+         }
+         pick -= weight;
+      }
+      None
+   }
+
+   /// Indices of the buckets that have gone unprobed for longer than their refresh interval,
+   /// paired with how long they are overdue.
+   fn overdue_buckets_at(&self, base_interval: time::Duration, now: time::SteadyTime) -> Vec<(usize, time::Duration)> {
+      self.buckets.iter()
+         .map(|bucket| bucket.read().unwrap().last_probe)
+         .enumerate()
+         .filter_map(|(index, time)| time.map(|time| (index, (now - time) - Table::refresh_interval(index, base_interval))))
+         .filter(|&(_, overdue)| overdue > time::Duration::zero())
+         .collect()
+   }
+
+   /// Interval after which a bucket is due for a refresh. It doubles every 40 bucket 
+   /// indices, so distant buckets are refreshed up to eight times less often.
+   pub fn refresh_interval(index: usize, base_interval: time::Duration) -> time::Duration {
+      base_interval * (1 << (index / 40))
+   }
+
    /// Returns the bucket index and the time for the bucket that we haven't
    /// probed for the longest. None on the second tuple value would mean the bucket
    /// has never been probed.
@@ -262,6 +396,7 @@ pub struct ClosestNodesTo<'a, 'b> {
    reference      : &'b hash::SubotaiHash,     
    lookup_order   : iter::Chain<iter::Rev<hash::IntoOnes>, hash::IntoZeroes>,
    current_bucket : Vec<NodeInfo>,
+   skip_parent    : bool,
 }
 
 /// Represents a conflict derived from attempting to insert a node in a full
@@ -269,7 +404,7 @@ pub struct ClosestNodesTo<'a, 'b> {
 #[derive(Debug,Clone)]
 pub struct EvictionConflict {
    pub evicted      : NodeInfo,
-   evictor          : NodeInfo,
+   pub evictor      : NodeInfo,
    pub times_pinged : u8,
 }
 
@@ -284,6 +419,31 @@ struct Bucket {
    last_probe : Option<time::SteadyTime>,
 }
 
+impl NodeInfo {
+   /// Builds a NodeInfo from its components.
+   pub fn new<I: Into<NodeId>>(id: I, address: net::SocketAddr) -> NodeInfo {
+      NodeInfo { id: id.into(), address: address, region: None }
+   }
+
+   /// Produces a seed string of the form `hex_id@ip:port`, which can be shared
+   /// with other nodes to bootstrap from this one.
+   pub fn to_seed_string(&self) -> String {
+      format!("{}@{}", self.id.to_hex(), self.address)
+   }
+
+   /// Parses a seed string of the form `hex_id@ip:port`, as produced by `to_seed_string`.
+   pub fn from_seed_string(seed: &str) -> SubotaiResult<NodeInfo> {
+      let mut parts = seed.splitn(2, '@');
+      let id = parts.next().and_then(SubotaiHash::from_hex);
+      let address = parts.next().and_then(|address| net::SocketAddr::from_str(address).ok());
+
+      match (id, address) {
+         (Some(id), Some(address)) => Ok(NodeInfo::new(id, address)),
+         _ => Err(SubotaiError::ParseError),
+      }
+   }
+}
+
 impl PartialEq for NodeInfo {
    fn eq(&self, other: &Self) -> bool {
       self.id.eq(&other.id)
@@ -299,6 +459,20 @@ impl<'a, 'b> Iterator for ClosestNodesTo<'a, 'b> {
    type Item = NodeInfo;
 
    fn next(&mut self) -> Option<NodeInfo> {
+      loop {
+         let node = self.next_in_order();
+         if let Some(ref info) = node {
+            if self.skip_parent && info.id == self.table.parent_id {
+               continue;
+            }
+         }
+         return node;
+      }
+   }
+}
+
+impl<'a, 'b> ClosestNodesTo<'a, 'b> {
+   fn next_in_order(&mut self) -> Option<NodeInfo> {
       if !self.current_bucket.is_empty() {
          return self.current_bucket.pop();
       }
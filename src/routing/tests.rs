@@ -1,15 +1,12 @@
 use super::*;
 use std::net;
 use std::str::FromStr;
-use hash::SubotaiHash;
+use hash::{SubotaiHash, NodeId};
 use hash::HASH_SIZE;
 use rand::{thread_rng, Rng};
 
-fn node_info_no_net(id : SubotaiHash) -> NodeInfo {
-   NodeInfo {
-      id : id,
-      address : net::SocketAddr::from_str("0.0.0.0:0").unwrap(),
-   }
+fn node_info_no_net<I: Into<NodeId>>(id : I) -> NodeInfo {
+   NodeInfo::new(id, net::SocketAddr::from_str("0.0.0.0:0").unwrap())
 }
 
 #[test]
@@ -34,6 +31,39 @@ fn measuring_table_length() {
    assert_eq!(50, table.len() + conflicts);
 }
 
+#[test]
+fn counting_populated_buckets() {
+   let id = SubotaiHash::random();
+   let table = Table::new(id.clone(), Default::default());
+   assert_eq!(table.populated_buckets(), 0);
+
+   // Buckets at low distances hold few possible IDs, so they're avoided to prevent collisions.
+   for &distance in &[30, 30, 50, 120] {
+      table.update_node(node_info_no_net(SubotaiHash::random_at_distance(&id, distance)));
+   }
+   assert_eq!(table.len(), 4);
+   assert_eq!(table.populated_buckets(), 3);
+
+   let stats = table.bucket_stats();
+   assert_eq!(stats.len(), HASH_SIZE);
+   assert_eq!((stats[30], stats[50], stats[120]), (2, 1, 1));
+   assert_eq!(stats.iter().sum::<usize>(), table.len());
+}
+
+#[test]
+fn reporting_the_farthest_contact_bucket() {
+   let id = SubotaiHash::random();
+   let table = Table::new(id.clone(), Default::default());
+   assert_eq!(table.farthest_contact_bucket(), None);
+
+   table.update_node(node_info_no_net(SubotaiHash::random_at_distance(&id, 12)));
+   table.update_node(node_info_no_net(SubotaiHash::random_at_distance(&id, 3)));
+   assert_eq!(table.farthest_contact_bucket(), Some(12));
+
+   table.update_node(node_info_no_net(SubotaiHash::random_at_distance(&id, 140)));
+   assert_eq!(table.farthest_contact_bucket(), Some(140));
+}
+
 #[test]
 fn inserting_and_removing() {
    let table = Table::new(SubotaiHash::random(), Default::default());
@@ -64,6 +94,38 @@ fn inserting_in_a_full_bucket_causes_eviction_conflict() {
    }
 }
 
+#[test]
+fn table_rejects_new_nodes_beyond_the_contact_cap() {
+   let mut configuration : node::Configuration = Default::default();
+   configuration.max_total_contacts = 10;
+   let table = Table::new(SubotaiHash::random(), configuration);
+
+   let mut known = Vec::new();
+   while table.len() < 10 {
+      let info = node_info_no_net(SubotaiHash::random());
+      if let UpdateResult::AddedNode = table.update_node(info.clone()) {
+         known.push(info);
+      }
+   }
+
+   for _ in 0..20 {
+      match table.update_node(node_info_no_net(SubotaiHash::random())) {
+         UpdateResult::TableFull => (),
+         _ => panic!(),
+      }
+   }
+   assert_eq!(10, table.len());
+
+   // Known nodes are still refreshed.
+   for info in known {
+      match table.update_node(info) {
+         UpdateResult::UpdatedNode => (),
+         _ => panic!(),
+      }
+   }
+   assert_eq!(10, table.len());
+}
+
 #[test]
 fn lookup_for_a_stored_node() { 
    let table = Table::new(SubotaiHash::random(), Default::default());
@@ -80,7 +142,7 @@ fn lookup_for_self() {
    let node = node_info_no_net(parent_id.clone());
    table.update_node(node.clone());
 
-   assert_eq!(table.lookup(&parent_id, 20, None), LookupResult::Found(node));
+   assert_eq!(table.lookup(&NodeId(parent_id), 20, None), LookupResult::Found(node));
 }
 
 #[test]
@@ -92,7 +154,7 @@ fn ascending_lookup_on_a_sparse_table() {
    }
    let mut id = parent_id;
    id.flip_bit(8); // Bucket 8
-   if let LookupResult::ClosestNodes(nodes) = table.lookup(&id, 5, None) {
+   if let LookupResult::ClosestNodes(nodes) = table.lookup(&NodeId::from(&id), 5, None) {
       assert_eq!(nodes.len(), 5);
 
       // Ensure they are ordered by ascending distance
@@ -115,7 +177,7 @@ fn descending_lookup_on_a_sparse_table() {
    let mut id = parent_id;
    id.flip_bit(51); // Bucket 51
    id.raw[0] = 0xFF;
-   if let LookupResult::ClosestNodes(nodes) = table.lookup(&id, 5, None) {
+   if let LookupResult::ClosestNodes(nodes) = table.lookup(&NodeId::from(&id), 5, None) {
       assert_eq!(nodes.len(), 5);
 
       // Ensure they are ordered by ascending distance
@@ -138,7 +200,7 @@ fn lookup_on_a_sparse_table() {
    let mut id = parent_id;
    id.flip_bit(25); // Bucket 25
    id.raw[0] = 0xFF;
-   if let LookupResult::ClosestNodes(nodes) = table.lookup(&id, 5, None) {
+   if let LookupResult::ClosestNodes(nodes) = table.lookup(&NodeId::from(&id), 5, None) {
       assert_eq!(nodes.len(), 5);
 
       // Ensure they are ordered by ascending distance
@@ -161,11 +223,11 @@ fn lookup_with_blacklist() {
       table.update_node(node.clone());
    }
   
-   let blacklist = blacklist.iter().map(|info: &NodeInfo| info.id.clone()).collect::<Vec<SubotaiHash>>();
+   let blacklist = blacklist.iter().map(|info: &NodeInfo| info.id.clone()).collect::<Vec<NodeId>>();
 
    table.update_node(normal_node.clone());
    
-   if let LookupResult::ClosestNodes(mut nodes) = table.lookup(&SubotaiHash::random(), 5, Some(&blacklist)) {
+   if let LookupResult::ClosestNodes(mut nodes) = table.lookup(&NodeId(SubotaiHash::random()), 5, Some(&blacklist)) {
       assert_eq!(nodes.len(), 1);
       assert_eq!(nodes.pop().unwrap().id, normal_node.id);
    } else {
@@ -187,7 +249,7 @@ fn efficient_bounce_lookup_on_a_randomized_table() {
    // We construct an origin node from which to calculate distances for the lookup.
    let mut id = parent_id.clone();
    id.mutate_random_bits(20);
-   if let LookupResult::ClosestNodes(nodes) = table.lookup(&id, 20, None) {
+   if let LookupResult::ClosestNodes(nodes) = table.lookup(&NodeId::from(&id), 20, None) {
       assert_eq!(nodes.len(), 20);
 
       // Ensure they are ordered by ascending distance by comparing to a brute force
@@ -213,6 +275,61 @@ fn efficient_bounce_lookup_on_a_randomized_table() {
    }
 }
 
+#[test]
+fn parent_never_appears_in_closest_others() {
+   let parent_id = SubotaiHash::random();
+   let table = Table::new(parent_id.clone(), Default::default());
+   table.update_node(node_info_no_net(parent_id.clone()));
+   for _ in 0..50 {
+      table.update_node(node_info_no_net(SubotaiHash::random()));
+   }
+
+   for target in &[parent_id.clone(), SubotaiHash::random()] {
+      assert!(table.closest_nodes_to(target).any(|info| *info.id == parent_id));
+      assert!(table.closest_others_to(target).all(|info| *info.id != parent_id));
+      assert_eq!(table.closest_others_to(target).count(), table.len() - 1);
+   }
+}
+
+#[test]
+fn constructor_matches_struct_literal() {
+   let id = SubotaiHash::random();
+   let address = net::SocketAddr::from_str("10.0.0.1:40000").unwrap();
+   let literal = NodeInfo { id : NodeId(id.clone()), address : address, region : None };
+   let constructed = NodeInfo::new(id, address);
+   assert_eq!(constructed, literal);
+   assert_eq!(constructed.address, literal.address);
+}
+
+#[test]
+fn seed_string_round_trip() {
+   let info = NodeInfo::new(SubotaiHash::random(), net::SocketAddr::from_str("192.168.1.100:50000").unwrap());
+   let seed = info.to_seed_string();
+   assert!(seed.ends_with("@192.168.1.100:50000"));
+
+   let parsed = NodeInfo::from_seed_string(&seed).unwrap();
+   assert_eq!(parsed.id, info.id);
+   assert_eq!(parsed.address, info.address);
+}
+
+#[test]
+fn malformed_seed_strings_are_rejected() {
+   let id = SubotaiHash::random().to_hex();
+   let malformed = vec![
+      String::new(),
+      id.clone(),
+      format!("{}@", id),
+      format!("{}@192.168.1.100", id),
+      format!("{}@not_an_address:50000", id),
+      format!("{}@192.168.1.100:50000", &id[2..]),
+      "@192.168.1.100:50000".to_string(),
+   ];
+
+   for seed in malformed {
+      assert!(NodeInfo::from_seed_string(&seed).is_err());
+   }
+}
+
 #[test]
 fn oldest_bucket_returns_the_first_bucket_that_never_got_probed() {
    let table = Table::new(SubotaiHash::random(), Default::default());
@@ -240,12 +357,63 @@ fn oldest_bucket_when_all_buckets_were_probed() {
    assert!(time.is_some());
 }
 
+#[test]
+fn closer_buckets_are_refreshed_more_often() {
+   let table = Table::new(SubotaiHash::random(), Default::default());
+   let base_interval = time::Duration::minutes(60);
+   let start = time::SteadyTime::now();
+   for bucket in &table.buckets {
+      bucket.write().unwrap().last_probe = Some(start);
+   }
+
+   // We simulate sixteen hours of maintenance cycles, one per minute.
+   let mut refreshes = vec![0u32; HASH_SIZE];
+   for minute in 1..(16 * 60 + 1) {
+      let now = start + time::Duration::minutes(minute);
+      while let Some(index) = table.bucket_due_for_refresh_at(base_interval, now) {
+         refreshes[index] += 1;
+         table.buckets[index].write().unwrap().last_probe = Some(now);
+      }
+   }
+
+   assert_eq!(refreshes[0], 15);
+   assert_eq!(refreshes[39], 15);
+   assert_eq!(refreshes[40], 7);
+   assert_eq!(refreshes[80], 3);
+   assert_eq!(refreshes[159], 1);
+}
+
+#[test]
+fn random_refresh_targets_vary_and_favour_stale_buckets() {
+   let table = Table::new(SubotaiHash::random(), Default::default());
+   let base_interval = time::Duration::minutes(60);
+   let start = time::SteadyTime::now();
+   for bucket in &table.buckets {
+      bucket.write().unwrap().last_probe = Some(start);
+   }
+
+   // After ten hours, every bucket is due, but the closest ones are the most overdue.
+   let now = start + time::Duration::hours(10);
+   let picks: Vec<_> = (0..500).map(|_| table.random_bucket_due_for_refresh_at(base_interval, now).unwrap()).collect();
+   let deterministic = table.bucket_due_for_refresh_at(base_interval, now).unwrap();
+
+   assert!(picks.iter().any(|&index| index != deterministic));
+   let close_picks = picks.iter().filter(|&&index| index < 40).count();
+   let distant_picks = picks.iter().filter(|&&index| index >= 120).count();
+   assert!(close_picks > distant_picks);
+
+   // Within the bucket, the probe target is random too.
+   let first_target = SubotaiHash::random_at_distance(&table.parent_id, 100);
+   let second_target = SubotaiHash::random_at_distance(&table.parent_id, 100);
+   assert!(first_target != second_target);
+}
+
 impl Table {
    pub fn fill_bucket(&self, bucket_index : usize, fill_quantity : u8) {
       // Otherwise this helper function becomes quite complex.
       assert!(bucket_index > 7);
       for i in 0..fill_quantity {
-         let mut id = self.parent_id.clone();
+         let mut id = self.parent_id.0.clone();
          id.flip_bit(bucket_index);
 
          id.raw[0] = i as u8;
@@ -0,0 +1,106 @@
+//! Wire formats for RPCs. All nodes in a network must agree on the codec,
+//! as there is no negotiation between them.
+
+use {node, SubotaiResult};
+use super::Rpc;
+#[cfg(feature = "msgpack")]
+use {rmp_serde, SubotaiError};
+
+/// Encodes RPCs into datagrams and back.
+pub trait WireCodec : Send + Sync {
+   fn encode(&self, rpc: &Rpc) -> SubotaiResult<Vec<u8>>;
+   fn decode(&self, bytes: &[u8]) -> SubotaiResult<Rpc>;
+}
+
+/// Compact binary format, only understood by other Subotai nodes. Used by default.
+pub struct BincodeCodec;
+
+impl WireCodec for BincodeCodec {
+   fn encode(&self, rpc: &Rpc) -> SubotaiResult<Vec<u8>> {
+      Ok(try!(rpc.serialize()))
+   }
+
+   fn decode(&self, bytes: &[u8]) -> SubotaiResult<Rpc> {
+      Ok(try!(Rpc::deserialize(bytes)))
+   }
+}
+
+/// Self-describing MessagePack format, easier to implement by non-Rust clients.
+#[cfg(feature = "msgpack")]
+pub struct MsgpackCodec;
+
+#[cfg(feature = "msgpack")]
+impl WireCodec for MsgpackCodec {
+   fn encode(&self, rpc: &Rpc) -> SubotaiResult<Vec<u8>> {
+      rmp_serde::to_vec(rpc).map_err(|_| SubotaiError::MalformedRpc)
+   }
+
+   fn decode(&self, bytes: &[u8]) -> SubotaiResult<Rpc> {
+      rmp_serde::from_slice(bytes).map_err(|_| SubotaiError::MalformedRpc)
+   }
+}
+
+/// Constructs the codec for a given wire format.
+pub fn for_format(format: node::WireFormat) -> Box<WireCodec> {
+   match format {
+      node::WireFormat::Bincode => Box::new(BincodeCodec),
+      #[cfg(feature = "msgpack")]
+      node::WireFormat::Msgpack => Box::new(MsgpackCodec),
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use super::super::Rpc;
+   use hash::{SubotaiHash, NodeId, HASH_SIZE_BYTES};
+   use std::net;
+   use std::str::FromStr;
+   use {routing, storage, time};
+
+   fn representative_rpcs() -> Vec<Rpc> {
+      let sender = routing::NodeInfo {
+         id      : NodeId(SubotaiHash::random()),
+         address : net::SocketAddr::from_str("127.0.0.1:50000").unwrap(),
+         region  : Some(3),
+      };
+      let expiration = super::super::SerializableTime::from(time::now());
+      vec![
+         Rpc::ping(sender.clone()),
+         Rpc::ping_response(sender.clone()),
+         Rpc::locate(sender.clone(), NodeId(SubotaiHash::random())),
+         Rpc::store(sender.clone(), SubotaiHash::random(), storage::StorageEntry::Blob(vec![1, 2, 3]), expiration),
+         Rpc::digest_response(sender.clone(), SubotaiHash::random(), Some(vec![SubotaiHash::random()]), 1, true),
+      ]
+   }
+
+   fn round_trip(codec: &WireCodec) {
+      for rpc in representative_rpcs() {
+         assert_eq!(rpc, codec.decode(&codec.encode(&rpc).unwrap()).unwrap());
+      }
+   }
+
+   #[test]
+   fn bincode_round_trip() {
+      round_trip(&BincodeCodec);
+   }
+
+   #[cfg(feature = "msgpack")]
+   #[test]
+   fn msgpack_round_trip() {
+      round_trip(&MsgpackCodec);
+   }
+
+   #[test]
+   fn garbage_fails_to_decode() {
+      assert!(BincodeCodec.decode(&[0xff; 4]).is_err());
+   }
+
+   #[test]
+   fn rpcs_too_big_for_a_datagram_fail_to_encode() {
+      let sender = representative_rpcs()[0].sender.clone();
+      let keys = vec![SubotaiHash::random(); node::SOCKET_BUFFER_SIZE_BYTES / HASH_SIZE_BYTES];
+      let rpc = Rpc::digest_response(sender, SubotaiHash::random(), Some(keys), 0, false);
+      assert!(BincodeCodec.encode(&rpc).is_err());
+   }
+}
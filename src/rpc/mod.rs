@@ -6,7 +6,9 @@
 use bincode::serde;
 use {routing, bincode, node, storage, time};
 use std::sync::Arc;
-use hash::SubotaiHash;
+use hash::{SubotaiHash, NodeId};
+
+pub mod codec;
 
 /// Serializable struct implementation of an RPC.
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
@@ -22,37 +24,54 @@ impl Rpc {
    /// sender, and expect a response indicating that the receiving node
    /// is alive.
    pub fn ping(sender: routing::NodeInfo) -> Rpc {
-      Rpc { kind: Kind::Ping, sender: sender }
+      let payload = Arc::new(PingPayload { version: node::CRATE_VERSION.to_owned(), sent_at: SerializableTime::from(time::now()) });
+      Rpc { kind: Kind::Ping(payload), sender: sender }
    }
 
    /// Constructs a ping response. 
    pub fn ping_response(sender: routing::NodeInfo) -> Rpc {
-      Rpc { kind: Kind::PingResponse, sender: sender }
+      let payload = Arc::new(PingPayload { version: node::CRATE_VERSION.to_owned(), sent_at: SerializableTime::from(time::now()) });
+      Rpc { kind: Kind::PingResponse(payload), sender: sender }
    }
 
    /// Constructs an RPC asking for a the results of a table node lookup. The objective
    /// of this RPC is to locate a particular node while minimizing network traffic. In other
    /// words, the process short-circuits when the target node is found.
-   pub fn locate(sender: routing::NodeInfo, id_to_find: SubotaiHash) -> Rpc {
+   pub fn locate(sender: routing::NodeInfo, id_to_find: NodeId) -> Rpc {
       let payload = Arc::new(LocatePayload { id_to_find: id_to_find });
       Rpc { kind: Kind::Locate(payload), sender: sender }
    }
 
    /// Constructs an RPC with the response to a locate RPC.
-   pub fn locate_response(sender: routing::NodeInfo, id_to_find: SubotaiHash, result: routing::LookupResult) -> Rpc {
+   pub fn locate_response(sender: routing::NodeInfo, id_to_find: NodeId, result: routing::LookupResult) -> Rpc {
       let payload = Arc::new(LocateResponsePayload { id_to_find: id_to_find, result: result} );
       Rpc { kind: Kind::LocateResponse(payload), sender: sender }
    }
 
    /// Constructs an RPC asking for a the results of a storage lookup.  
    pub fn retrieve(sender: routing::NodeInfo, key_to_find: SubotaiHash) -> Rpc {
-      let payload = Arc::new(RetrievePayload { key_to_find: key_to_find });
+      let payload = Arc::new(RetrievePayload { key_to_find: key_to_find, authoritative_only: false, since: None });
+      Rpc { kind: Kind::Retrieve(payload), sender: sender }
+   }
+
+   /// Constructs a RPC asking only for the values associated to a key that were
+   /// stored after a point in time.
+   pub fn retrieve_since(sender: routing::NodeInfo, key_to_find: SubotaiHash, since: SerializableTime) -> Rpc {
+      let payload = Arc::new(RetrievePayload { key_to_find: key_to_find, authoritative_only: false, since: Some(since) });
+      Rpc { kind: Kind::Retrieve(payload), sender: sender }
+   }
+
+   /// Constructs a RPC asking for the value associated to a key, which will only be
+   /// provided by nodes that belong to the set of `K_FACTOR` replicas for that key.
+   pub fn retrieve_authoritative(sender: routing::NodeInfo, key_to_find: SubotaiHash) -> Rpc {
+      let payload = Arc::new(RetrievePayload { key_to_find: key_to_find, authoritative_only: true, since: None });
       Rpc { kind: Kind::Retrieve(payload), sender: sender }
    }
 
-   /// Constructs an RPC asking for a the results of a storage lookup.
-   pub fn retrieve_response(sender: routing::NodeInfo, key_to_find: SubotaiHash, result: RetrieveResult) -> Rpc {
-      let payload = Arc::new(RetrieveResponsePayload { key_to_find: key_to_find, result: result });
+   /// Constructs an RPC asking for a the results of a storage lookup. Responses to
+   /// retrieves of entries stored after a point in time carry that point in time.
+   pub fn retrieve_response(sender: routing::NodeInfo, key_to_find: SubotaiHash, result: RetrieveResult, since: Option<SerializableTime>) -> Rpc {
+      let payload = Arc::new(RetrieveResponsePayload { key_to_find: key_to_find, result: result, since: since });
       Rpc { kind: Kind::RetrieveResponse(payload), sender: sender }
    }
 
@@ -74,14 +93,45 @@ impl Rpc {
 
    /// Constructs a store RPC. It asks the receiving node to store a key->value pair.
    pub fn store(sender: routing::NodeInfo, key: SubotaiHash, entry: storage::StorageEntry, expiration: SerializableTime) -> Rpc {
-      let payload = Arc::new(StorePayload { key: key, entry: entry, expiration: expiration });     
+      Rpc::store_with(sender, key, entry, expiration, None)
+   }
+
+   /// Constructs a store RPC that only succeeds if the receiving node holds no entries
+   /// for the key yet.
+   pub fn store_if_absent(sender: routing::NodeInfo, key: SubotaiHash, entry: storage::StorageEntry, expiration: SerializableTime) -> Rpc {
+      Rpc::store_with(sender, key, entry, expiration, Some(1))
+   }
+
+   /// Constructs a store RPC that only succeeds if the receiving node holds fewer than
+   /// `max_entries` entries for the key.
+   pub fn append_bounded(sender: routing::NodeInfo, 
+                         key: SubotaiHash, 
+                         entry: storage::StorageEntry, 
+                         expiration: SerializableTime, 
+                         max_entries: usize) -> Rpc {
+      Rpc::store_with(sender, key, entry, expiration, Some(max_entries))
+   }
+
+   fn store_with(sender: routing::NodeInfo, key: SubotaiHash, entry: storage::StorageEntry, expiration: SerializableTime, max_entries: Option<usize>) -> Rpc {
+      let payload = Arc::new(StorePayload { 
+         key         : key,
+         entry       : entry,
+         expiration  : expiration,
+         sent_at     : SerializableTime::from(time::now()),
+         max_entries : max_entries,
+      });
       Rpc { kind: Kind::Store(payload), sender: sender }
    }
+
    /// Constructs a mass store RPC. It asks the receiving node to store several key->value pairs
    pub fn mass_store(sender: routing::NodeInfo, 
                      key: SubotaiHash, 
                      entries_and_expirations: Vec<(storage::StorageEntry, SerializableTime)>) -> Rpc {
-      let payload = Arc::new(MassStorePayload { key: key, entries_and_expirations: entries_and_expirations });     
+      let payload = Arc::new(MassStorePayload { 
+         key                     : key,
+         entries_and_expirations : entries_and_expirations,
+         sent_at                 : SerializableTime::from(time::now()),
+      });
       Rpc { kind: Kind::MassStore(payload), sender: sender }
    }
 
@@ -91,9 +141,65 @@ impl Rpc {
       Rpc { kind: Kind::StoreResponse(payload), sender: sender }
    }
 
-   /// Serializes an RPC to be send over TCP. 
-   pub fn serialize(&self) -> Vec<u8> {
-       serde::serialize(&self, bincode::SizeLimit::Bounded(node::SOCKET_BUFFER_SIZE_BYTES as u64)).unwrap()
+   /// Constructs a remove RPC. It asks the receiving node to drop an entry under a key.
+   pub fn remove(sender: routing::NodeInfo, key: SubotaiHash, entry: storage::StorageEntry) -> Rpc {
+      let payload = Arc::new(RemovePayload { key: key, entry: entry });
+      Rpc { kind: Kind::Remove(payload), sender: sender }
+   }
+
+   /// Constructs the response to a remove RPC, reporting whether the entry was present.
+   pub fn remove_response(sender: routing::NodeInfo, key: SubotaiHash, removed: bool) -> Rpc {
+      let payload = Arc::new(RemoveResponsePayload { key: key, removed: removed });
+      Rpc { kind: Kind::RemoveResponse(payload), sender: sender }
+   }
+
+   /// Constructs a has entry RPC. It asks the receiving node whether it holds a
+   /// specific entry under a key.
+   pub fn has_entry(sender: routing::NodeInfo, key: SubotaiHash, entry: storage::StorageEntry) -> Rpc {
+      let payload = Arc::new(HasEntryPayload { key: key, entry: entry });
+      Rpc { kind: Kind::HasEntry(payload), sender: sender }
+   }
+
+   /// Constructs the response to a has entry RPC, identifying the entry by its content hash.
+   pub fn has_entry_response(sender: routing::NodeInfo, key: SubotaiHash, entry_hash: SubotaiHash, present: bool) -> Rpc {
+      let payload = Arc::new(HasEntryResponsePayload { key: key, entry_hash: entry_hash, present: present });
+      Rpc { kind: Kind::HasEntryResponse(payload), sender: sender }
+   }
+
+   /// Constructs an RPC asking for the stored key closest to a target.
+   pub fn nearest_key(sender: routing::NodeInfo, target: SubotaiHash) -> Rpc {
+      let payload = Arc::new(NearestKeyPayload { target: target });
+      Rpc { kind: Kind::NearestKey(payload), sender: sender }
+   }
+
+   /// Constructs the response to a nearest key RPC, with the closest key the sender holds, if any.
+   pub fn nearest_key_response(sender: routing::NodeInfo, target: SubotaiHash, nearest: Option<SubotaiHash>) -> Rpc {
+      let payload = Arc::new(NearestKeyResponsePayload { target: target, nearest: nearest });
+      Rpc { kind: Kind::NearestKeyResponse(payload), sender: sender }
+   }
+
+   /// Constructs an RPC carrying the digest of the sender's storage, asking the receiver
+   /// to compare it against its own. On mismatch, the receiver responds with the given 
+   /// page of its key list.
+   pub fn digest(sender: routing::NodeInfo, digest: SubotaiHash, page: usize) -> Rpc {
+      let payload = Arc::new(DigestPayload { digest: digest, page: page });
+      Rpc { kind: Kind::Digest(payload), sender: sender }
+   }
+
+   /// Constructs the response to a digest RPC. If the digests didn't match, it includes a
+   /// page of the list of keys held by the sender so that both nodes can reconcile their data.
+   pub fn digest_response(sender: routing::NodeInfo, 
+                          digest: SubotaiHash, 
+                          keys: Option<Vec<SubotaiHash>>, 
+                          page: usize, 
+                          more_pages: bool) -> Rpc {
+      let payload = Arc::new(DigestResponsePayload { digest: digest, keys: keys, page: page, more_pages: more_pages });
+      Rpc { kind: Kind::DigestResponse(payload), sender: sender }
+   }
+
+   /// Serializes an RPC to be send over TCP. Fails if it wouldn't fit in a socket buffer.
+   pub fn serialize(&self) -> serde::SerializeResult<Vec<u8>> {
+       serde::serialize(&self, bincode::SizeLimit::Bounded(node::SOCKET_BUFFER_SIZE_BYTES as u64))
    }
 
    /// Deserializes into an RPC structure.
@@ -103,7 +209,7 @@ impl Rpc {
 
    /// Reports whether the RPC is a LocateResponse that found
    /// a particular node. If it was, returns the node.
-   pub fn successfully_located(&self, id: &SubotaiHash) -> Option<routing::NodeInfo> {
+   pub fn successfully_located(&self, id: &NodeId) -> Option<routing::NodeInfo> {
       if let Kind::LocateResponse(ref payload) = self.kind {
          match payload.result {
             routing::LookupResult::Found(ref node) if &payload.id_to_find == id => return Some(node.clone()),
@@ -115,7 +221,7 @@ impl Rpc {
 
    /// Reports whether the RPC is a LocateResponse that failed to locate.
    /// If so, provides the closest nodes.
-   pub fn is_helping_locate(&self, id: &SubotaiHash) -> Option<Vec<routing::NodeInfo>> {
+   pub fn is_helping_locate(&self, id: &NodeId) -> Option<Vec<routing::NodeInfo>> {
       if let Kind::LocateResponse(ref payload) = self.kind {
          match payload.result {
             routing::LookupResult::ClosestNodes(ref nodes) if &payload.id_to_find == id => return Some(nodes.clone()),
@@ -126,11 +232,23 @@ impl Rpc {
    }
 
    /// Reports whether the RPC is a RetrieveResponse that found
-   /// a particular key.
-   pub fn successfully_retrieved(&self, key: &SubotaiHash) -> Option<Vec<storage::StorageEntry>> {
+   /// a particular key. If so, provides the entries along with
+   /// their expiration times.
+   pub fn successfully_retrieved(&self, key: &SubotaiHash) -> Option<Vec<(storage::StorageEntry, time::Tm)>> {
+      self.successfully_retrieved_since(key, None)
+   }
+
+   /// Like `successfully_retrieved`, but only for responses to a retrieve of the
+   /// entries stored after a point in time (or to a regular retrieve, if `None`).
+   pub fn successfully_retrieved_since(&self, key: &SubotaiHash, since: Option<&time::Tm>) -> Option<Vec<(storage::StorageEntry, time::Tm)>> {
       if let Kind::RetrieveResponse(ref payload) = self.kind {
+         if payload.since != since.map(|since| SerializableTime::from(*since)) {
+            return None;
+         }
          match payload.result {
-            RetrieveResult::Found(ref entries) if &payload.key_to_find == key => return Some(entries.clone()),
+            RetrieveResult::Found(ref entries) if &payload.key_to_find == key => {
+               return Some(entries.iter().map(|&(ref entry, ref expiration)| (entry.clone(), time::Tm::from(expiration.clone()))).collect())
+            },
             _ => return None,
          }
       }
@@ -147,6 +265,17 @@ impl Rpc {
       false
    }
 
+   /// Reports whether the RPC is a StoreResponse for a particular key.
+   /// If so, provides the result of the store operation.
+   pub fn is_store_response(&self, key: &SubotaiHash) -> Option<storage::StoreResult> {
+      if let Kind::StoreResponse(ref payload) = self.kind {
+         if &payload.key == key {
+            return Some(payload.result.clone());
+         }
+      }
+      None
+   }
+
    /// Reports whether the RPC is a RetrieveResponse looking
    /// for a particular key
    pub fn is_helping_retrieve(&self, key: &SubotaiHash) -> Option<Vec<routing::NodeInfo>> {
@@ -167,13 +296,58 @@ impl Rpc {
       }
       None
    }
+
+   /// Reports whether the RPC is a NearestKeyResponse for a particular target.
+   /// If so, provides the nearest key the sender holds, if any.
+   pub fn is_nearest_key_response(&self, target: &SubotaiHash) -> Option<Option<SubotaiHash>> {
+      if let Kind::NearestKeyResponse(ref payload) = self.kind {
+         if &payload.target == target {
+            return Some(payload.nearest.clone());
+         }
+      }
+      None
+   }
+
+   /// Reports whether the RPC is a RemoveResponse for a particular key.
+   /// If so, provides whether the entry was present and removed.
+   pub fn is_remove_response(&self, key: &SubotaiHash) -> Option<bool> {
+      if let Kind::RemoveResponse(ref payload) = self.kind {
+         if &payload.key == key {
+            return Some(payload.removed);
+         }
+      }
+      None
+   }
+
+   /// Reports whether the RPC is a HasEntryResponse for a particular key and entry.
+   /// If so, provides whether the sender holds the entry.
+   pub fn is_has_entry_response(&self, key: &SubotaiHash, entry_hash: &SubotaiHash) -> Option<bool> {
+      if let Kind::HasEntryResponse(ref payload) = self.kind {
+         if &payload.key == key && &payload.entry_hash == entry_hash {
+            return Some(payload.present);
+         }
+      }
+      None
+   }
+
+   /// Reports whether the RPC is a DigestResponse for a given page. If so, provides that
+   /// page of the key list of the sender in case of a digest mismatch, or None if the digests
+   /// matched, along with whether any pages follow.
+   pub fn is_digest_response(&self, page: usize) -> Option<(Option<Vec<SubotaiHash>>, bool)> {
+      if let Kind::DigestResponse(ref payload) = self.kind {
+         if payload.page == page {
+            return Some((payload.keys.clone(), payload.more_pages));
+         }
+      }
+      None
+   }
 }
 
 /// Types of Subotai RPCs. Some of them contain reference counted payloads.
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
 pub enum Kind {
-   Ping,
-   PingResponse,
+   Ping(Arc<PingPayload>),
+   PingResponse(Arc<PingPayload>),
    Store(Arc<StorePayload>),
    MassStore(Arc<MassStorePayload>),
    StoreResponse(Arc<StoreResponsePayload>),
@@ -182,14 +356,51 @@ pub enum Kind {
    Retrieve(Arc<RetrievePayload>),
    RetrieveResponse(Arc<RetrieveResponsePayload>),
    Probe(Arc<ProbePayload>),
-   ProbeResponse(Arc<ProbeResponsePayload>)
+   ProbeResponse(Arc<ProbeResponsePayload>),
+   NearestKey(Arc<NearestKeyPayload>),
+   NearestKeyResponse(Arc<NearestKeyResponsePayload>),
+   Digest(Arc<DigestPayload>),
+   DigestResponse(Arc<DigestResponsePayload>),
+   Remove(Arc<RemovePayload>),
+   RemoveResponse(Arc<RemoveResponsePayload>),
+   HasEntry(Arc<HasEntryPayload>),
+   HasEntryResponse(Arc<HasEntryResponsePayload>),
+}
+
+impl Kind {
+   /// Name of the RPC kind, without its payload.
+   pub fn name(&self) -> &'static str {
+      match *self {
+         Kind::Ping(_)               => "Ping",
+         Kind::PingResponse(_)       => "PingResponse",
+         Kind::Store(_)              => "Store",
+         Kind::MassStore(_)          => "MassStore",
+         Kind::StoreResponse(_)      => "StoreResponse",
+         Kind::Locate(_)             => "Locate",
+         Kind::LocateResponse(_)     => "LocateResponse",
+         Kind::Retrieve(_)           => "Retrieve",
+         Kind::RetrieveResponse(_)   => "RetrieveResponse",
+         Kind::Probe(_)              => "Probe",
+         Kind::ProbeResponse(_)      => "ProbeResponse",
+         Kind::NearestKey(_)         => "NearestKey",
+         Kind::NearestKeyResponse(_) => "NearestKeyResponse",
+         Kind::Digest(_)             => "Digest",
+         Kind::DigestResponse(_)     => "DigestResponse",
+         Kind::Remove(_)             => "Remove",
+         Kind::RemoveResponse(_)     => "RemoveResponse",
+         Kind::HasEntry(_)           => "HasEntry",
+         Kind::HasEntryResponse(_)   => "HasEntryResponse",
+      }
+   }
 }
 
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
 pub struct StorePayload {
-   pub key        : SubotaiHash,
-   pub entry      : storage::StorageEntry,
-   pub expiration : SerializableTime,
+   pub key         : SubotaiHash,
+   pub entry       : storage::StorageEntry,
+   pub expiration  : SerializableTime,
+   pub sent_at     : SerializableTime,
+   pub max_entries : Option<usize>,
 }
 
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
@@ -201,37 +412,47 @@ pub struct StoreResponsePayload {
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
 pub struct MassStorePayload {
    pub key                     : SubotaiHash,
-   pub entries_and_expirations : Vec<(storage::StorageEntry, SerializableTime)>
+   pub entries_and_expirations : Vec<(storage::StorageEntry, SerializableTime)>,
+   pub sent_at                 : SerializableTime,
 }
 
 /// Includes the ID to find and the amount of nodes required.
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
 pub struct LocatePayload {
-   pub id_to_find    : SubotaiHash,
+   pub id_to_find    : NodeId,
 }
 
 /// Includes the ID to find and the results of the table lookup.
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
 pub struct LocateResponsePayload {
-   pub id_to_find : SubotaiHash,
+   pub id_to_find : NodeId,
    pub result     : routing::LookupResult,
 }
 
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
 pub enum RetrieveResult {
-   Found(Vec<storage::StorageEntry>),
+   Found(Vec<(storage::StorageEntry, SerializableTime)>),
    Closest(Vec<routing::NodeInfo>),
 }
 
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
+pub struct PingPayload {
+   pub version : String,
+   pub sent_at : SerializableTime,
+}
+
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
 pub struct RetrievePayload {
-   pub key_to_find : SubotaiHash,
+   pub key_to_find        : SubotaiHash,
+   pub authoritative_only : bool,
+   pub since              : Option<SerializableTime>,
 }
 
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
 pub struct RetrieveResponsePayload {
    pub key_to_find : SubotaiHash,
    pub result      : RetrieveResult,
+   pub since       : Option<SerializableTime>,
 }
 
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
@@ -248,6 +469,58 @@ pub struct ProbeResponsePayload {
    pub nodes        : Vec<routing::NodeInfo>,
 }
 
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
+pub struct NearestKeyPayload {
+   pub target : SubotaiHash,
+}
+
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
+pub struct NearestKeyResponsePayload {
+   pub target  : SubotaiHash,
+   pub nearest : Option<SubotaiHash>,
+}
+
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
+pub struct DigestPayload {
+   pub digest : SubotaiHash,
+   pub page   : usize,
+}
+
+/// Includes the digest of the responder, and a page of its key list if it didn't
+/// match the digest of the requester.
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
+pub struct DigestResponsePayload {
+   pub digest     : SubotaiHash,
+   pub keys       : Option<Vec<SubotaiHash>>,
+   pub page       : usize,
+   pub more_pages : bool,
+}
+
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
+pub struct RemovePayload {
+   pub key   : SubotaiHash,
+   pub entry : storage::StorageEntry,
+}
+
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
+pub struct RemoveResponsePayload {
+   pub key     : SubotaiHash,
+   pub removed : bool,
+}
+
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
+pub struct HasEntryPayload {
+   pub key   : SubotaiHash,
+   pub entry : storage::StorageEntry,
+}
+
+#[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
+pub struct HasEntryResponsePayload {
+   pub key        : SubotaiHash,
+   pub entry_hash : SubotaiHash,
+   pub present    : bool,
+}
+
 #[derive(Serialize, Deserialize, Eq, PartialEq, Debug, Clone)]
 pub struct SerializableTime {
    tm_sec    : i32,
@@ -310,7 +583,7 @@ mod tests {
    #[test]
    fn serdes_for_ping() {
       let ping = Rpc::ping(node_info_no_net(SubotaiHash::random()));
-      let serialized_ping = ping.serialize();
+      let serialized_ping = ping.serialize().unwrap();
       let deserialized_ping = Rpc::deserialize(&serialized_ping).unwrap();
       assert_eq!(ping, deserialized_ping);
    }
@@ -323,7 +596,7 @@ mod tests {
                              SubotaiHash::random(),
                              storage::StorageEntry::Blob(Vec::<u8>::new()),
                              serializable_now);
-      let deserialized_store = Rpc::deserialize(&store.serialize()).unwrap();
+      let deserialized_store = Rpc::deserialize(&store.serialize().unwrap()).unwrap();
       if let Kind::Store(ref payload) = deserialized_store.kind {
          assert_eq!(now, time::Tm::from(payload.expiration.clone()));
       } else {
@@ -332,9 +605,6 @@ mod tests {
    }
 
    fn node_info_no_net(id : SubotaiHash) -> routing::NodeInfo {
-      routing::NodeInfo {
-         id : id,
-         address : net::SocketAddr::from_str("0.0.0.0:0").unwrap(),
-      }
+      routing::NodeInfo::new(id, net::SocketAddr::from_str("0.0.0.0:0").unwrap())
    }
 }
@@ -0,0 +1,45 @@
+//! Sources of the current time for the storage, so expiration can be tested
+//! without waiting on the system clock.
+
+use time;
+#[cfg(test)]
+use std::sync::Mutex;
+
+/// Provides the current time.
+pub trait Clock : Send + Sync {
+   fn now(&self) -> time::Tm;
+}
+
+/// Clock backed by the system time. Used by default.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+   fn now(&self) -> time::Tm {
+      time::now()
+   }
+}
+
+/// Clock that only moves when told to.
+#[cfg(test)]
+pub struct MockClock {
+   now : Mutex<time::Tm>,
+}
+
+#[cfg(test)]
+impl MockClock {
+   pub fn new(start: time::Tm) -> MockClock {
+      MockClock { now: Mutex::new(start) }
+   }
+
+   pub fn advance(&self, duration: time::Duration) {
+      let mut now = self.now.lock().unwrap();
+      *now = *now + duration;
+   }
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+   fn now(&self) -> time::Tm {
+      *self.now.lock().unwrap()
+   }
+}
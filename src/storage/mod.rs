@@ -1,15 +1,58 @@
-use {time, node};
-use hash::SubotaiHash;
+use {time, node, bincode};
+use bincode::serde;
+use hash::{SubotaiHash, HASH_SIZE_BYTES};
 use std::collections::HashMap;
-use std::sync::RwLock;
+use std::sync::{Arc, RwLock};
 use std::cmp;
 
+pub mod clock;
+mod signature;
+
+use self::clock::Clock;
+
 /// This is the data type that can be stored and retrieved in the Subotai network, 
-/// consisting of either another hash or a binary blob.
+/// consisting of either another hash, a binary blob, a binary blob accompanied
+/// by application-defined metadata (e.g. content type or source), a binary blob
+/// signed by the holder of an ed25519 key pair, or the manifest of a large blob split
+/// into chunks stored under separate keys (see `Node::store_large`).
+///
+/// When built with the `ed25519` feature, nodes refuse to store signed entries whose
+/// signature doesn't verify against the embedded public key.
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub enum StorageEntry {
    Value(SubotaiHash),
    Blob(Vec<u8>),
+   Tagged { data: Vec<u8>, metadata: Vec<u8> },
+   Signed { data: Vec<u8>, pubkey: Vec<u8>, sig: Vec<u8> },
+   Manifest { length: usize, chunks: Vec<SubotaiHash> },
+}
+
+impl StorageEntry {
+   /// Size in bytes of the entry's payload.
+   pub fn size(&self) -> usize {
+      match *self {
+         StorageEntry::Value(_) => HASH_SIZE_BYTES,
+         StorageEntry::Blob(ref vec) => vec.len(),
+         StorageEntry::Tagged { ref data, ref metadata } => data.len() + metadata.len(),
+         StorageEntry::Signed { ref data, ref pubkey, ref sig } => data.len() + pubkey.len() + sig.len(),
+         StorageEntry::Manifest { ref chunks, .. } => chunks.len() * HASH_SIZE_BYTES,
+      }
+   }
+
+   /// Whether the entry is signed, and its signature verifies against its public key.
+   /// Always false unless the crate is built with the `ed25519` feature.
+   pub fn signature_verified(&self) -> bool {
+      match *self {
+         StorageEntry::Signed { ref data, ref pubkey, ref sig } => signature::verify(data, pubkey, sig),
+         _ => false,
+      }
+   }
+
+   /// SHA-1 hash of the serialized entry, identifying its contents.
+   pub fn content_hash(&self) -> SubotaiHash {
+      let serialized = serde::serialize(self, bincode::SizeLimit::Infinite).unwrap();
+      SubotaiHash::from_content(&serialized)
+   }
 }
 
 /// Storage entry wrapper that includes management information.
@@ -18,6 +61,8 @@ struct ExtendedEntry {
    entry           : StorageEntry,
    expiration      : time::Tm,
    republish_ready : bool,
+   is_cache        : bool,
+   last_modified   : time::Tm,
 }
 
 /// Groups of extended entries classified by key.
@@ -27,22 +72,38 @@ pub struct Storage {
    key_groups    : RwLock<HashMap<SubotaiHash, KeyGroup> >,
    parent_id     : SubotaiHash,
    configuration : node::Configuration,
+   clock         : Arc<Clock>,
 }
 
+/// Outcome of a store operation on a single node.
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub enum StoreResult {
    Success,
    StorageFull,
    BlobTooBig,
    MassStoreFailed,
+   NotResponsible,
+   ReadOnly,
+   AlreadyPresent,
+   BadSignature,
+   KeyGroupFull,
+   /// Only some of the entries in a mass store were accepted.
+   PartialSuccess,
 }
 
 impl Storage {
    pub fn new(parent_id: SubotaiHash, configuration: node::Configuration) -> Storage {
+      Storage::with_clock(parent_id, configuration, Arc::new(clock::SystemClock))
+   }
+
+   /// Constructs a storage that reads the current time from a particular clock, 
+   /// against which all expiration times are compared.
+   pub fn with_clock(parent_id: SubotaiHash, configuration: node::Configuration, clock: Arc<Clock>) -> Storage {
       Storage {
          key_groups    : RwLock::new(HashMap::with_capacity(configuration.max_storage)),
          parent_id     : parent_id,
          configuration : configuration,
+         clock         : clock,
       }
    }
   
@@ -55,50 +116,229 @@ impl Storage {
       self.len() == 0
    }
 
+   /// Returns the combined size in bytes of all entries.
+   pub fn size_bytes(&self) -> usize {
+      self.key_groups.read().unwrap().values().flat_map(|group| group.iter()).map(|ext| ext.entry.size()).sum()
+   }
+
+   /// Fraction of the storage capacity in use, between 0.0 and 1.0. It's the highest of the
+   /// entry count relative to `max_storage`, and the byte count relative to `max_storage_bytes`.
+   pub fn load_factor(&self) -> f64 {
+      let entry_load = self.len() as f64 / cmp::max(1, self.configuration.max_storage) as f64;
+      let byte_load = self.size_bytes() as f64 / cmp::max(1, self.configuration.max_storage_bytes) as f64;
+      f64::min(1.0, f64::max(entry_load, byte_load))
+   }
+
+   /// Returns all keys that currently hold at least one live entry.
+   pub fn keys(&self) -> Vec<SubotaiHash> {
+      self.clear_expired_entries();
+      self.key_groups.read().unwrap().keys().cloned().collect()
+   }
+
+   /// Whether a key currently holds at least one live entry.
+   pub fn contains_key(&self, key: &SubotaiHash) -> bool {
+      self.clear_expired_entries();
+      self.key_groups.read().unwrap().contains_key(key)
+   }
+
+   /// Hash summarizing every live entry held, so that two nodes can cheaply find out whether
+   /// they hold the same data. It's computed over the sorted keys, each followed by the sorted
+   /// content hashes of its entries. Expiration times are not taken into account, as they
+   /// naturally differ between replicas.
+   pub fn digest(&self) -> SubotaiHash {
+      let mut all_entries = self.get_all_entries();
+      all_entries.sort_by(|&(ref a, _), &(ref b, _)| a.cmp(b));
+
+      let mut summary = Vec::<u8>::new();
+      for (key, entries) in all_entries {
+         let mut entry_hashes: Vec<_> = entries.iter().map(|&(ref entry, _)| entry.content_hash()).collect();
+         entry_hashes.sort();
+         summary.extend_from_slice(&key.to_bytes());
+         for hash in entry_hashes {
+            summary.extend_from_slice(&hash.to_bytes());
+         }
+      }
+      SubotaiHash::from_content(&summary)
+   }
+
+   /// Returns the stored key with the smallest XOR distance to a target.
+   pub fn nearest_key(&self, target: &SubotaiHash) -> Option<SubotaiHash> {
+      self.keys().into_iter().min_by_key(|key| key ^ target)
+   }
+
    /// Retrieves all entries in a key_group.
    pub fn retrieve(&self, key: &SubotaiHash) -> Option<Vec<StorageEntry>> {
+      self.retrieve_with_expiry(key).map(|entries| entries.into_iter().map(|(entry, _)| entry).collect())
+   }
+
+   /// Retrieves all entries under a key, along with their expiration times.
+   pub fn retrieve_with_expiry(&self, key: &SubotaiHash) -> Option<Vec<(StorageEntry, time::Tm)>> {
       self.clear_expired_entries();
-      if let Some(key_group) = self.key_groups.read().unwrap().get(key) {
-         Some(key_group.iter().cloned().map(|extended| extended.entry).collect())
-      } else {
+      // Entries may expire between the sweep and the read, so we check again as we copy them.
+      let now = self.clock.now();
+      let entries: Vec<_> = match self.key_groups.read().unwrap().get(key) {
+         Some(key_group) => key_group.iter()
+            .filter(|extended| now < extended.expiration)
+            .map(|extended| (extended.entry.clone(), extended.expiration))
+            .collect(),
+         None => return None,
+      };
+
+      if entries.is_empty() {
          None
+      } else {
+         Some(entries)
       }
    }
 
+   /// Retrieves the entries under a key first stored after a point in time, along with
+   /// their expiration times. Refreshing an entry that was already stored (e.g. when it's
+   /// republished) doesn't count as a modification. Returns `None` only if the key holds
+   /// no entries at all.
+   pub fn retrieve_since_with_expiry(&self, key: &SubotaiHash, since: &time::Tm) -> Option<Vec<(StorageEntry, time::Tm)>> {
+      self.clear_expired_entries();
+      let now = self.clock.now();
+      match self.key_groups.read().unwrap().get(key) {
+         Some(key_group) if key_group.iter().any(|extended| now < extended.expiration) => Some(key_group.iter()
+            .filter(|extended| now < extended.expiration && extended.last_modified > *since)
+            .map(|extended| (extended.entry.clone(), extended.expiration))
+            .collect()),
+         _ => None,
+      }
+   }
+
+   /// Retrieves the entries under a key first stored after a point in time.
+   pub fn entries_since(&self, key: &SubotaiHash, since: &time::Tm) -> Vec<StorageEntry> {
+      self.retrieve_since_with_expiry(key, since)
+         .map_or(Vec::new(), |entries| entries.into_iter().map(|(entry, _)| entry).collect())
+   }
+
    /// Stores an entry in a key_group, with an expiration date, if it wasn't present already.
    /// If it was present, it keeps the latest expiration time and marks as not ready for republishing.
    pub fn store(&self, key: &SubotaiHash, entry: &StorageEntry, expiration: &time::Tm) -> StoreResult {
+      self.store_with(key, entry, expiration, None, false)
+   }
+
+   /// Stores an entry only if the key holds no live entries, reporting `AlreadyPresent` otherwise.
+   pub fn store_if_absent(&self, key: &SubotaiHash, entry: &StorageEntry, expiration: &time::Tm) -> StoreResult {
+      self.store_with(key, entry, expiration, Some(1), false)
+   }
+
+   /// Stores an entry only if the key holds fewer than `max_entries` live entries, 
+   /// reporting `KeyGroupFull` otherwise.
+   pub fn append_bounded(&self, key: &SubotaiHash, entry: &StorageEntry, expiration: &time::Tm, max_entries: usize) -> StoreResult {
+      match self.store_with(key, entry, expiration, Some(max_entries), false) {
+         StoreResult::AlreadyPresent => StoreResult::KeyGroupFull,
+         result => result,
+      }
+   }
+
+   /// Stores an entry as a cached copy of a retrieved value. If the same entry is
+   /// also stored regularly, it's no longer considered a cached copy.
+   pub fn cache(&self, key: &SubotaiHash, entry: &StorageEntry, expiration: &time::Tm) -> StoreResult {
+      self.store_with(key, entry, expiration, None, true)
+   }
+
+   /// Reports whether a batch of entries under a key would fit in the storage as a whole,
+   /// without exceeding `max_storage`, `max_storage_bytes` nor `max_entries_per_key`. 
+   /// Entries already stored under the key don't count, as storing them again only 
+   /// refreshes them.
+   pub fn can_accept_batch(&self, key: &SubotaiHash, entries: &[StorageEntry]) -> bool {
+      let key_groups = self.key_groups.read().unwrap();
+      let mut new_entries: Vec<&StorageEntry> = Vec::new();
+      for entry in entries {
+         let stored = key_groups.get(key).map_or(false, |group| group.iter().any(|ext| ext.entry == *entry));
+         if !stored && !new_entries.contains(&entry) {
+            new_entries.push(entry);
+         }
+      }
+
+      let group_length = key_groups.get(key).map_or(0, |group| group.len()) + new_entries.len();
+      let length = key_groups.values().map(|group| group.len()).sum::<usize>() + new_entries.len();
+      let size_bytes = key_groups.values().flat_map(|group| group.iter()).map(|ext| ext.entry.size()).sum::<usize>() + 
+         new_entries.iter().map(|entry| entry.size()).sum::<usize>();
+      length <= self.configuration.max_storage && 
+         size_bytes <= self.configuration.max_storage_bytes &&
+         group_length <= self.configuration.max_entries_per_key
+   }
+
+   /// Removes an entry from a key group, dropping the key altogether if it was its last
+   /// entry. Returns whether the entry was present.
+   pub fn remove(&self, key: &SubotaiHash, entry: &StorageEntry) -> bool {
+      let mut key_groups = self.key_groups.write().unwrap();
+      let (removed, now_empty) = match key_groups.get_mut(key) {
+         Some(key_group) => {
+            let initial_length = key_group.len();
+            key_group.retain(|ext| ext.entry != *entry);
+            (key_group.len() < initial_length, key_group.is_empty())
+         },
+         None => return false,
+      };
+
+      if now_empty {
+         key_groups.remove(key);
+      }
+      removed
+   }
+
+   fn store_with(&self, 
+                 key: &SubotaiHash, 
+                 entry: &StorageEntry, 
+                 expiration: &time::Tm, 
+                 max_entries: Option<usize>, 
+                 is_cache: bool) -> StoreResult {
       if self.is_big_blob(entry) {
          return StoreResult::BlobTooBig;
       }
 
-      // Expiration time is clamped to a reasonable value.
-      let expiration = cmp::min(*expiration, time::now() + time::Duration::hours(self.configuration.base_expiration_time_hrs));
+      if let StorageEntry::Signed { .. } = *entry {
+         if signature::enabled() && !entry.signature_verified() {
+            return StoreResult::BadSignature;
+         }
+      }
+
+      // Expiration time is capped at the base expiration time. Shorter ones are honored.
+      let expiration = cmp::min(*expiration, self.clock.now() + time::Duration::hours(self.configuration.base_expiration_time_hrs));
       let initial_length = self.len();
+      let exceeds_bytes = self.size_bytes() + entry.size() > self.configuration.max_storage_bytes;
 
+      let now = self.clock.now();
       let mut key_groups = self.key_groups.write().unwrap();
+      if let Some(max_entries) = max_entries {
+         let live_entries = key_groups.get(key).map_or(0, |group| group.iter().filter(|ext| now < ext.expiration).count());
+         if live_entries >= max_entries {
+            return StoreResult::AlreadyPresent;
+         }
+      }
+
       if key_groups.contains_key(key) {
          let key_group = key_groups.get_mut(key).unwrap();
          let already_existed = if let Some(preexisting_pair) = key_group.iter_mut().find(|stored_pair| stored_pair.entry == *entry) {
             preexisting_pair.expiration = cmp::max(preexisting_pair.expiration, expiration); // Take the latest expiration time.
             preexisting_pair.republish_ready = false;
+            preexisting_pair.is_cache = preexisting_pair.is_cache && is_cache;
             true
          } else {
             false
          };
          if !already_existed {
-            if initial_length > self.configuration.max_storage {
+            if initial_length > self.configuration.max_storage || exceeds_bytes {
                return StoreResult::StorageFull;
             }
+            if key_group.len() >= self.configuration.max_entries_per_key {
+               return StoreResult::KeyGroupFull;
+            }
             let new_entry = ExtendedEntry {
                entry           : entry.clone(),
                expiration      : expiration,
                republish_ready : false,
+               is_cache        : is_cache,
+               last_modified   : now,
             };
             key_group.push(new_entry);
          }
       } else {
-         if initial_length > self.configuration.max_storage {
+         if initial_length > self.configuration.max_storage || exceeds_bytes {
             return StoreResult::StorageFull;
          }
          let mut key_group = KeyGroup::new();
@@ -106,6 +346,8 @@ impl Storage {
                entry           : entry.clone(),
                expiration      : expiration,
                republish_ready : false,
+               is_cache        : is_cache,
+               last_modified   : now,
          };
          key_group.push(new_entry);
          key_groups.insert(key.clone(), key_group);
@@ -115,13 +357,16 @@ impl Storage {
 
    fn is_big_blob(&self, entry: &StorageEntry) -> bool {
       match *entry {
-         StorageEntry::Blob(ref vec) => vec.len() > self.configuration.max_storage_blob_size,
+         StorageEntry::Blob(_) | 
+         StorageEntry::Tagged { .. } | 
+         StorageEntry::Signed { .. } | 
+         StorageEntry::Manifest { .. } => entry.size() > self.configuration.max_storage_blob_size,
          _ => false,
       }
    }
 
    fn clear_expired_entries(&self) {
-      let now = time::now();
+      let now = self.clock.now();
       let mut key_groups = self.key_groups.write().unwrap();
       for mut key_group in key_groups.values_mut() {
          key_group.retain(|&ExtendedEntry{ expiration, .. }| now < expiration);
@@ -160,6 +405,51 @@ impl Storage {
          .collect()
    }
 
+   /// Stores a whole set of entries, as produced by `get_all_entries` or `get_all_ready_entries`.
+   /// Entries already present keep the latest expiration time, and expired ones are skipped.
+   pub fn merge(&self, other: Vec<(SubotaiHash, Vec<(StorageEntry, time::Tm)>)>) {
+      let now = self.clock.now();
+      for (key, entries) in other {
+         for (entry, expiration) in entries {
+            if expiration > now {
+               self.store(&key, &entry, &expiration);
+            }
+         }
+      }
+   }
+
+   /// Retrieves all live keys and associated data, regardless of their republishing status.
+   pub fn get_all_entries(&self) -> Vec<(SubotaiHash, Vec<(StorageEntry, time::Tm)>)> {
+      self.clear_expired_entries();
+      self.key_groups
+         .read()
+         .unwrap()
+         .iter()
+         .map(|(key, keygroup)| (key.clone(), keygroup.iter().cloned().map(|ext| (ext.entry, ext.expiration)).collect::<Vec<_>>()))
+         .collect()
+   }
+
+   /// Retrieves all live entries held as cached copies of retrieved values.
+   pub fn cached_entries(&self) -> Vec<(SubotaiHash, Vec<(StorageEntry, time::Tm)>)> {
+      self.entries_where(|ext| ext.is_cache)
+   }
+
+   /// Retrieves all live entries stored regularly, as opposed to cached copies.
+   pub fn authoritative_entries(&self) -> Vec<(SubotaiHash, Vec<(StorageEntry, time::Tm)>)> {
+      self.entries_where(|ext| !ext.is_cache)
+   }
+
+   fn entries_where<F: Fn(&ExtendedEntry) -> bool>(&self, condition: F) -> Vec<(SubotaiHash, Vec<(StorageEntry, time::Tm)>)> {
+      self.clear_expired_entries();
+      self.key_groups
+         .read()
+         .unwrap()
+         .iter()
+         .map(|(key, group)| (key.clone(), group.iter().filter(|ext| condition(ext)).map(|ext| (ext.entry.clone(), ext.expiration)).collect::<Vec<_>>()))
+         .filter(|&(_, ref entries)| !entries.is_empty())
+         .collect()
+   }
+
    /// Retrieves all keys and associated data ready for republishing
    pub fn get_all_ready_entries(&self) -> Vec<(SubotaiHash, Vec<(StorageEntry, time::Tm)>)>  {
       self.clear_expired_entries();
@@ -186,6 +476,162 @@ mod tests {
    use {time, node};
    use hash::SubotaiHash;
 
+   #[test]
+   fn digest_only_depends_on_held_data() {
+      let first = default_storage();
+      let second = default_storage();
+      assert_eq!(first.digest(), second.digest());
+
+      let key = SubotaiHash::random();
+      let entries = vec![StorageEntry::Value(SubotaiHash::random()), StorageEntry::Blob(vec![1, 2, 3])];
+      let expiration = time::now() + time::Duration::minutes(30);
+      for entry in &entries {
+         first.store(&key, entry, &expiration);
+      }
+      // Different order and expiration.
+      for entry in entries.iter().rev() {
+         second.store(&key, entry, &(expiration + time::Duration::minutes(5)));
+      }
+      assert_eq!(first.digest(), second.digest());
+
+      second.store(&SubotaiHash::random(), &StorageEntry::Blob(vec![4]), &expiration);
+      assert!(first.digest() != second.digest());
+   }
+
+   #[test]
+   fn cached_copies_are_told_apart() {
+      let storage = default_storage();
+      let key = SubotaiHash::random();
+      let entry = StorageEntry::Value(SubotaiHash::random());
+      let expiration = time::now() + time::Duration::minutes(1);
+      storage.cache(&key, &entry, &expiration);
+      assert_eq!(storage.cached_entries().len(), 1);
+      assert!(storage.authoritative_entries().is_empty());
+
+      // Caching a stored entry doesn't demote it, but storing a cached one promotes it.
+      storage.store(&key, &entry, &expiration);
+      storage.cache(&key, &entry, &expiration);
+      assert!(storage.cached_entries().is_empty());
+      assert_eq!(storage.authoritative_entries()[0].0, key);
+   }
+
+   #[test]
+   fn enumerating_live_keys() {
+      let clock = Arc::new(clock::MockClock::new(time::now()));
+      let storage = Storage::with_clock(SubotaiHash::random(), Default::default(), clock.clone());
+      let keys: Vec<_> = (0..5).map(|_| SubotaiHash::random()).collect();
+      let short_lived = SubotaiHash::random();
+      for key in &keys {
+         storage.store(key, &StorageEntry::Value(SubotaiHash::random()), &(clock.now() + time::Duration::hours(2)));
+      }
+      storage.store(&short_lived, &StorageEntry::Value(SubotaiHash::random()), &(clock.now() + time::Duration::minutes(10)));
+      assert!(storage.contains_key(&short_lived));
+
+      clock.advance(time::Duration::minutes(30));
+      let mut stored_keys = storage.keys();
+      stored_keys.sort();
+      let mut expected = keys.clone();
+      expected.sort();
+      assert_eq!(stored_keys, expected);
+      assert!(keys.iter().all(|key| storage.contains_key(key)));
+      assert!(!storage.contains_key(&short_lived));
+   }
+
+   #[test]
+   fn retrieving_entries_modified_since_a_cutoff() {
+      let clock = Arc::new(clock::MockClock::new(time::now()));
+      let storage = Storage::with_clock(SubotaiHash::random(), Default::default(), clock.clone());
+      let key = SubotaiHash::random();
+      let (old, new) = (StorageEntry::Value(SubotaiHash::random()), StorageEntry::Blob(vec![1, 2, 3]));
+      let expiration = clock.now() + time::Duration::hours(2);
+
+      storage.store(&key, &old, &expiration);
+      clock.advance(time::Duration::minutes(10));
+      let cutoff = clock.now();
+      clock.advance(time::Duration::minutes(10));
+      storage.store(&key, &new, &expiration);
+      // Refreshing doesn't count as a modification.
+      storage.store(&key, &old, &expiration);
+
+      assert_eq!(storage.entries_since(&key, &cutoff), vec![new]);
+      assert!(storage.entries_since(&key, &clock.now()).is_empty());
+      assert_eq!(storage.retrieve_since_with_expiry(&key, &clock.now()), Some(Vec::new()));
+      assert_eq!(storage.retrieve_since_with_expiry(&SubotaiHash::random(), &cutoff), None);
+   }
+
+   #[test]
+   fn batches_are_checked_as_a_whole() {
+      let configuration = node::Configuration { max_storage: 3, .. Default::default() };
+      let storage = Storage::new(SubotaiHash::random(), configuration);
+      let key = SubotaiHash::random();
+      let stored = StorageEntry::Value(SubotaiHash::random());
+      storage.store(&key, &stored, &(time::now() + time::Duration::minutes(30)));
+
+      let fresh: Vec<_> = (0..3).map(|_| StorageEntry::Value(SubotaiHash::random())).collect();
+      assert!(!storage.can_accept_batch(&key, &fresh));
+      assert!(storage.can_accept_batch(&key, &fresh[..2]));
+      // Entries already stored, or repeated in the batch, only count once.
+      assert!(storage.can_accept_batch(&key, &[stored, fresh[0].clone(), fresh[0].clone(), fresh[1].clone()]));
+   }
+
+   #[cfg(feature = "ed25519")]
+   fn signed(data: &[u8]) -> StorageEntry {
+      use ed25519_dalek::{SecretKey, PublicKey, ExpandedSecretKey};
+      let secret = SecretKey::from_bytes(&[7u8; 32]).unwrap();
+      let public = PublicKey::from(&secret);
+      let signature = ExpandedSecretKey::from(&secret).sign(data, &public);
+      StorageEntry::Signed { data: data.to_vec(), pubkey: public.to_bytes().to_vec(), sig: signature.to_bytes().to_vec() }
+   }
+
+   #[cfg(feature = "ed25519")]
+   #[test]
+   fn signed_entries_are_verified_on_store() {
+      let storage = default_storage();
+      let key = SubotaiHash::random();
+      let expiration = time::now() + time::Duration::minutes(30);
+
+      let valid = signed(b"genuine");
+      assert!(valid.signature_verified());
+      assert_eq!(storage.store(&key, &valid, &expiration), StoreResult::Success);
+
+      let tampered = match signed(b"genuine") {
+         StorageEntry::Signed { pubkey, sig, .. } => StorageEntry::Signed { data: b"forged".to_vec(), pubkey: pubkey, sig: sig },
+         _ => unreachable!(),
+      };
+      assert!(!tampered.signature_verified());
+      assert_eq!(storage.store(&key, &tampered, &expiration), StoreResult::BadSignature);
+      assert_eq!(storage.retrieve(&key), Some(vec![valid]));
+   }
+
+   #[cfg(not(feature = "ed25519"))]
+   #[test]
+   fn signed_entries_are_stored_unverified_without_ed25519() {
+      let storage = default_storage();
+      let key = SubotaiHash::random();
+      let entry = StorageEntry::Signed { data: vec![1, 2, 3], pubkey: vec![0; 32], sig: vec![0; 64] };
+      assert_eq!(storage.store(&key, &entry, &(time::now() + time::Duration::minutes(30))), StoreResult::Success);
+      assert!(!storage.retrieve(&key).unwrap()[0].signature_verified());
+   }
+
+   #[test]
+   fn removing_entries() {
+      let storage = default_storage();
+      let key = SubotaiHash::random();
+      let entry = StorageEntry::Value(SubotaiHash::random());
+      let another_entry = StorageEntry::Blob(vec![1, 2, 3]);
+      let expiration = time::now() + time::Duration::minutes(30);
+      storage.store(&key, &entry, &expiration);
+      storage.store(&key, &another_entry, &expiration);
+
+      assert!(storage.remove(&key, &entry));
+      assert_eq!(storage.retrieve(&key), Some(vec![another_entry.clone()]));
+      assert!(!storage.remove(&key, &entry));
+
+      assert!(storage.remove(&key, &another_entry));
+      assert!(storage.retrieve(&key).is_none());
+      assert!(storage.keys().is_empty());
+   }
+
    #[test]
    fn storing_and_retrieving_on_same_key() {
       let storage = default_storage();
@@ -269,6 +715,106 @@ mod tests {
       assert_eq!(expiration_later, entries[0].1[0].1);
    }
 
+   #[test]
+   fn merging_overlapping_sets_keeps_all_entries_with_max_expiration() {
+      let now = time::now();
+      let storage = default_storage();
+      let shared_key = SubotaiHash::random();
+      let other_key = SubotaiHash::random();
+      let shared_entry = StorageEntry::Value(SubotaiHash::random());
+      let local_entry = StorageEntry::Value(SubotaiHash::random());
+      let imported_entry = StorageEntry::Blob(vec![1, 2, 3]);
+      let expiration_soon = now + time::Duration::minutes(30);
+      let expiration_later = now + time::Duration::hours(10);
+
+      storage.store(&shared_key, &shared_entry, &expiration_soon);
+      storage.store(&shared_key, &local_entry, &expiration_later);
+      storage.merge(vec![
+         (shared_key.clone(), vec![(shared_entry.clone(), expiration_later), (imported_entry.clone(), expiration_soon)]),
+         (other_key.clone(), vec![(imported_entry.clone(), expiration_later), (local_entry.clone(), now - time::Duration::minutes(1))]),
+      ]);
+
+      let mut all_entries = storage.get_all_entries();
+      all_entries.sort_by(|a, b| (&a.0 == &other_key).cmp(&(&b.0 == &other_key)));
+      assert_eq!(all_entries, vec![
+         (shared_key, vec![(shared_entry, expiration_later), (local_entry, expiration_later), (imported_entry.clone(), expiration_soon)]),
+         (other_key, vec![(imported_entry, expiration_later)]),
+      ]);
+   }
+
+   #[test]
+   fn entries_are_never_retrieved_past_expiration() {
+      let storage = default_storage();
+      let key = SubotaiHash::random();
+      let entry = StorageEntry::Value(SubotaiHash::random());
+      let imminent_expiration = time::now() + time::Duration::milliseconds(20);
+      storage.store(&key, &entry, &imminent_expiration);
+
+      while time::now() < imminent_expiration {
+         if let Some(entries) = storage.retrieve(&key) {
+            assert_eq!(entries, vec![entry.clone()]);
+         }
+      }
+      assert_eq!(storage.retrieve(&key), None);
+   }
+
+   #[test]
+   fn bounded_appends_stop_at_the_cap() {
+      let storage = default_storage();
+      let key = SubotaiHash::random();
+      let expiration = time::now() + time::Duration::minutes(30);
+      let entries: Vec<_> = (0..4).map(|_| StorageEntry::Value(SubotaiHash::random())).collect();
+
+      for entry in &entries[..3] {
+         assert_eq!(storage.append_bounded(&key, entry, &expiration, 3), StoreResult::Success);
+      }
+      assert_eq!(storage.append_bounded(&key, &entries[3], &expiration, 3), StoreResult::KeyGroupFull);
+      assert_eq!(storage.retrieve(&key).unwrap(), entries[..3].to_vec());
+   }
+
+   #[test]
+   fn storing_if_absent_never_overwrites() {
+      let storage = default_storage();
+      let key = SubotaiHash::random();
+      let expiration = time::now() + time::Duration::minutes(30);
+      let first = StorageEntry::Value(SubotaiHash::random());
+      let second = StorageEntry::Value(SubotaiHash::random());
+
+      assert_eq!(storage.store_if_absent(&key, &first, &expiration), StoreResult::Success);
+      assert_eq!(storage.store_if_absent(&key, &second, &expiration), StoreResult::AlreadyPresent);
+      assert_eq!(storage.retrieve(&key), Some(vec![first]));
+   }
+
+   #[test]
+   fn advancing_a_mock_clock_expires_entries() {
+      let clock = Arc::new(clock::MockClock::new(time::now()));
+      let storage = Storage::with_clock(SubotaiHash::random(), Default::default(), clock.clone());
+      let key = SubotaiHash::random();
+      let entry = StorageEntry::Value(SubotaiHash::random());
+      storage.store(&key, &entry, &(clock.now() + time::Duration::minutes(30)));
+
+      clock.advance(time::Duration::minutes(29));
+      assert_eq!(storage.retrieve(&key), Some(vec![entry]));
+      clock.advance(time::Duration::minutes(1));
+      assert_eq!(storage.retrieve(&key), None);
+      assert!(storage.is_empty());
+   }
+
+   #[test]
+   fn finding_the_nearest_stored_key() {
+      let storage = default_storage();
+      let target = SubotaiHash::random();
+      assert_eq!(storage.nearest_key(&target), None);
+
+      let expiration = time::now() + time::Duration::minutes(30);
+      for distance in &[150, 40, 90] {
+         let key = SubotaiHash::random_at_distance(&target, *distance);
+         storage.store(&key, &StorageEntry::Value(SubotaiHash::random()), &expiration);
+      }
+      let nearest = storage.nearest_key(&target).unwrap();
+      assert_eq!((&nearest ^ &target).height(), Some(40));
+   }
+
    #[test]
    fn storing_preexisting_entry_keeps_max_expiration() {
       let now = time::now();
@@ -309,6 +855,68 @@ mod tests {
       assert_eq!(storage.len(), 1);
    }
 
+   #[test]
+   fn load_factor_rises_as_storage_fills() {
+      let mut configuration: node::Configuration = Default::default();
+      configuration.max_storage = 10;
+      let storage = Storage::new(SubotaiHash::random(), configuration);
+      let expiration = time::now() + time::Duration::minutes(30);
+      assert_eq!(storage.load_factor(), 0.0);
+
+      let mut previous_load = 0.0;
+      for _ in 0..10 {
+         storage.store(&SubotaiHash::random(), &StorageEntry::Value(SubotaiHash::random()), &expiration);
+         assert!(storage.load_factor() > previous_load);
+         previous_load = storage.load_factor();
+      }
+      assert_eq!(storage.load_factor(), 1.0);
+   }
+
+   #[test]
+   fn storing_beyond_byte_capacity_fails() {
+      let mut configuration: node::Configuration = Default::default();
+      configuration.max_storage_bytes = 1000;
+      let storage = Storage::new(SubotaiHash::random(), configuration);
+      let expiration = time::now() + time::Duration::minutes(30);
+
+      assert_eq!(storage.store(&SubotaiHash::random(), &StorageEntry::Blob(vec![0u8; 800]), &expiration), StoreResult::Success);
+      assert_eq!(storage.store(&SubotaiHash::random(), &StorageEntry::Blob(vec![0u8; 800]), &expiration), StoreResult::StorageFull);
+      assert_eq!(storage.load_factor(), 0.8);
+   }
+
+   #[test]
+   fn storing_beyond_the_key_group_capacity_fails() {
+      let mut configuration: node::Configuration = Default::default();
+      configuration.max_entries_per_key = 5;
+      let storage = Storage::new(SubotaiHash::random(), configuration);
+      let expiration = time::now() + time::Duration::minutes(30);
+      let key = SubotaiHash::random();
+
+      let entries: Vec<_> = (0..6).map(|_| StorageEntry::Value(SubotaiHash::random())).collect();
+      for entry in &entries[..5] {
+         assert_eq!(storage.store(&key, entry, &expiration), StoreResult::Success);
+      }
+      assert_eq!(storage.store(&key, &entries[5], &expiration), StoreResult::KeyGroupFull);
+
+      // Refreshing a stored entry, or storing under a different key, still succeeds.
+      assert_eq!(storage.store(&key, &entries[0], &expiration), StoreResult::Success);
+      assert_eq!(storage.store(&SubotaiHash::random(), &entries[5], &expiration), StoreResult::Success);
+      assert!(!storage.can_accept_batch(&key, &entries[5..]));
+   }
+
+   #[test]
+   fn tagged_entries_count_metadata_against_blob_size() {
+      let mut configuration: node::Configuration = Default::default();
+      configuration.max_storage_blob_size = 10;
+      let storage = Storage::new(SubotaiHash::random(), configuration);
+      let expiration = time::now() + time::Duration::minutes(30);
+
+      let small = StorageEntry::Tagged { data: vec![0u8; 6], metadata: vec![1u8; 4] };
+      let big = StorageEntry::Tagged { data: vec![0u8; 6], metadata: vec![1u8; 5] };
+      assert_eq!(storage.store(&SubotaiHash::random(), &small, &expiration), StoreResult::Success);
+      assert_eq!(storage.store(&SubotaiHash::random(), &big, &expiration), StoreResult::BlobTooBig);
+   }
+
    fn default_storage() -> Storage {
       let default_config: node::Configuration = Default::default();
       Storage::new(SubotaiHash::random(), default_config)
@@ -0,0 +1,27 @@
+//! Verification of signed storage entries. Signatures are only checked when the 
+//! crate is built with the `ed25519` feature; otherwise no entry counts as verified.
+
+#[cfg(feature = "ed25519")]
+use ed25519_dalek::{PublicKey, Signature, Verifier};
+#[cfg(feature = "ed25519")]
+use std::convert::TryFrom;
+
+/// Whether `signature` is a valid ed25519 signature of `data` by `public_key`.
+#[cfg(feature = "ed25519")]
+pub fn verify(data: &[u8], public_key: &[u8], signature: &[u8]) -> bool {
+   match (PublicKey::from_bytes(public_key), Signature::try_from(signature)) {
+      (Ok(public_key), Ok(signature)) => public_key.verify(data, &signature).is_ok(),
+      _ => false,
+   }
+}
+
+/// Signatures can't be checked without the `ed25519` feature.
+#[cfg(not(feature = "ed25519"))]
+pub fn verify(_data: &[u8], _public_key: &[u8], _signature: &[u8]) -> bool {
+   false
+}
+
+/// Whether signatures are checked by this build.
+pub fn enabled() -> bool {
+   cfg!(feature = "ed25519")
+}